@@ -0,0 +1,42 @@
+//! Compares the latency of the first Event and of later Events, for a factory locked with
+//! `lock()` and one locked with `compile()`. Run with `cargo bench --bench dispatch`.
+
+use std::time::{Duration, Instant};
+use statement::{LockedStateMachineFactory, StateMachineFactory};
+
+const STATES: u32 = 200;
+const EVENTS: usize = 1000;
+
+/// A ring of States, each moving to the next one on its own Event
+fn factory() -> StateMachineFactory<'static, u32, u32> {
+    let mut factory = StateMachineFactory::new().with_states(0..STATES);
+    for state in 0..STATES {
+        let event: &'static u32 = Box::leak(Box::new(state));
+        factory = factory.with_event_transition(event, state, (state + 1) % STATES);
+    }
+    factory
+}
+
+/// The time taken by the first Event, and the average over the following ones
+fn measure(locked: &LockedStateMachineFactory<'static, u32, u32>) -> (Duration, Duration) {
+    let mut sm = locked.build(0, ());
+    let started = Instant::now();
+    sm.handle_event(0).expect("no effects to fail");
+    let first = started.elapsed();
+
+    let started = Instant::now();
+    for event in 1..=EVENTS {
+        sm.handle_event(event as u32 % STATES).expect("no effects to fail");
+    }
+    (first, started.elapsed() / EVENTS as u32)
+}
+
+fn main() {
+    let (first, warm) = measure(&factory().lock());
+    println!("lock:    first event {:?}, then {:?} per event", first, warm);
+
+    let compiled = factory().compile();
+    let (first, warm) = measure(&compiled);
+    println!("compile: first event {:?}, then {:?} per event", first, warm);
+    println!("{:?}", compiled.dispatch_stats().expect("compiled"));
+}
@@ -0,0 +1,47 @@
+//! Compares the predicates evaluated, and the time taken, by repeated calls to `accepted_events`
+//! with and without the guard cache. Run with `cargo bench --bench guard_cache`. The predicates
+//! here are trivial, so looking them up costs about as much as evaluating them; the cache pays off
+//! for predicates that are expensive to evaluate.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use statement::StateMachineFactory;
+use statement::FromState::Any;
+use statement::ToState::Same;
+
+const GUARDS: usize = 50;
+const CALLS: usize = 1000;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Events {
+    Refresh,
+    Submit,
+    Cancel
+}
+
+static EVALUATED: AtomicUsize = AtomicUsize::new(0);
+
+fn factory() -> StateMachineFactory<'static, Events, u32> {
+    (0..GUARDS).fold(StateMachineFactory::new(), |factory, guard| {
+        factory.with_predicated_transition(Any, Same, move |d| {
+            EVALUATED.fetch_add(1, Ordering::Relaxed);
+            *d.from as usize % GUARDS == guard && matches!(d.event, Events::Submit)
+        })
+    })
+}
+
+fn measure(name: &str, factory: StateMachineFactory<'static, Events, u32>) {
+    let sm = factory.lock().build(GUARDS as u32 - 1, ());
+    let candidates = [Events::Refresh, Events::Submit, Events::Cancel];
+    EVALUATED.store(0, Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..CALLS {
+        assert_eq!(vec![Events::Submit], sm.accepted_events(&candidates));
+    }
+    println!("{}: {} predicates evaluated, {:?} per call", name, EVALUATED.load(Ordering::Relaxed), started.elapsed() / CALLS as u32);
+}
+
+fn main() {
+    measure("uncached", factory());
+    measure("cached  ", factory().with_idempotent_guard_cache());
+}
@@ -0,0 +1,88 @@
+//! Derive macros for the `statement` crate, re-exported by it with the `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident};
+
+/// Derives a constructor for each variant of an Event enum, named after the variant in snake case
+/// and taking its fields as arguments in order, so that `Events::Digit { digit: 2 }` can be written
+/// `Events::digit(2)`.
+///
+/// ```ignore
+/// #[derive(EventConstructors)]
+/// enum Events {
+///     Clear,
+///     Digit { digit: u8 },
+///     Resize(u32, u32),
+/// }
+///
+/// assert_eq!(Events::Digit { digit: 2 }, Events::digit(2));
+/// assert_eq!(Events::Resize(80, 24), Events::resize(80, 24));
+/// ```
+#[proc_macro_derive(EventConstructors)]
+pub fn derive_event_constructors(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let Data::Enum(data) = &input.data else {
+        return Error::new_spanned(&input.ident, "EventConstructors can only be derived for enums").to_compile_error().into();
+    };
+
+    let constructors = data.variants.iter().map(|variant| {
+        let variant_name = &variant.ident;
+        let name = Ident::new(&snake_case(&variant_name.to_string()), Span::call_site());
+        let doc = format!("Creates a [Self::{}] Event.", variant_name);
+        match &variant.fields {
+            Fields::Unit => quote! {
+                #[doc = #doc]
+                pub fn #name() -> Self {
+                    Self::#variant_name
+                }
+            },
+            Fields::Named(fields) => {
+                let names: Vec<_> = fields.named.iter().map(|field| &field.ident).collect();
+                let types = fields.named.iter().map(|field| &field.ty);
+                quote! {
+                    #[doc = #doc]
+                    pub fn #name(#(#names: #types),*) -> Self {
+                        Self::#variant_name { #(#names),* }
+                    }
+                }
+            }
+            Fields::Unnamed(fields) => {
+                let names: Vec<_> = (0..fields.unnamed.len()).map(|index| format_ident!("field{}", index)).collect();
+                let types = fields.unnamed.iter().map(|field| &field.ty);
+                quote! {
+                    #[doc = #doc]
+                    pub fn #name(#(#names: #types),*) -> Self {
+                        Self::#variant_name(#(#names),*)
+                    }
+                }
+            }
+        }
+    });
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        #[allow(dead_code)]
+        impl #impl_generics #name #type_generics #where_clause {
+            #(#constructors)*
+        }
+    }.into()
+}
+
+/// Converts a variant name such as `HttpRequest` into `http_request`
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if index > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
@@ -0,0 +1,134 @@
+//! Actor wrapper: a [StateMachine] owned by a thread of its own and driven through a mailbox.
+
+use std::fmt::Debug;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+use crate::{StateMachine, StateMachineError};
+
+/// An Event in an actor's mailbox, with the channel to send its outcome on if it was asked for
+struct Message<TEvent, TState: Debug + Send + Clone + Eq + PartialEq> {
+    event: TEvent,
+    reply: Option<Sender<Result<TState, StateMachineError<TState>>>>,
+}
+
+/// Handle to a State Machine running as an actor, created with [StateMachine::into_actor]. The
+/// actor's thread owns the State Machine and handles the Events sent to it one at a time, in the
+/// order they arrive, so callers never manage locks themselves.
+///
+/// The handle can be shared between threads by reference (for example with [std::thread::scope]
+/// or an `Arc`). Dropping it closes the mailbox; the actor handles the Events already sent and
+/// then stops, and the drop waits for it to do so. If an effect or predicate panics, the actor
+/// stops: Events sent afterwards are discarded, and [ActorHandle::ask] panics.
+pub struct ActorHandle<TEvent, TState: Debug + Send + Clone + Eq + PartialEq> {
+    mailbox: Option<Sender<Message<TEvent, TState>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl <TEvent, TState: Debug + Send + Clone + Eq + PartialEq> ActorHandle<TEvent, TState> {
+    /// Sends an Event to the actor without waiting for it to be handled. The outcome is discarded.
+    pub fn send(&self, event: TEvent) {
+        let _ = self.mailbox().send(Message {
+            event,
+            reply: None,
+        });
+    }
+
+    /// Sends an Event to the actor and waits for it to be handled, returning the resulting state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the actor has stopped because an effect or predicate panicked.
+    pub fn ask(&self, event: TEvent) -> Result<TState, StateMachineError<TState>> {
+        let (reply, outcome) = channel();
+        let _ = self.mailbox().send(Message {
+            event,
+            reply: Some(reply),
+        });
+        outcome.recv().expect("the actor stopped after a panic")
+    }
+
+    fn mailbox(&self) -> &Sender<Message<TEvent, TState>> {
+        self.mailbox.as_ref().expect("the mailbox is only closed on drop")
+    }
+}
+
+impl <TEvent, TState: Debug + Send + Clone + Eq + PartialEq> Drop for ActorHandle<TEvent, TState> {
+    fn drop(&mut self) {
+        // Closing the mailbox lets the actor finish the Events already sent, and then stop
+        self.mailbox.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl <TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Sync + Eq + PartialEq + 'static, TData> StateMachine<'static, TEvent, TState, TData>
+where TEvent: Send + Sync + 'static, TData: Send + 'static
+{
+    /// Moves this State Machine onto a thread of its own, returning an [ActorHandle] for sending it
+    /// Events.
+    pub fn into_actor(mut self) -> ActorHandle<TEvent, TState> {
+        let (mailbox, messages) = channel::<Message<TEvent, TState>>();
+        let thread = std::thread::spawn(move || {
+            for message in messages {
+                let outcome = self.handle_event(message.event).cloned();
+                if let Some(reply) = message.reply {
+                    let _ = reply.send(outcome);
+                }
+            }
+        });
+        ActorHandle {
+            mailbox: Some(mailbox),
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::StateMachineFactory;
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Open,
+        Closed
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Request,
+        Close
+    }
+
+    static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn test_actor() {
+        let actor = StateMachineFactory::new()
+            .with_event_transition(&Events::Close, States::Open, States::Closed)
+            .with_predicated_transition_effect(Any, Same, |d| matches!(d.event, Events::Request), |_| {
+                REQUESTS.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock()
+            .build(States::Open, ())
+            .into_actor();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        actor.send(Events::Request);
+                    }
+                });
+            }
+        });
+
+        // Events are handled in the order they arrive, so every Request has been handled by now
+        assert_eq!(Ok(States::Closed), actor.ask(Events::Close).map_err(|e| e.to_string()));
+        assert_eq!(100, REQUESTS.load(Ordering::SeqCst));
+    }
+}
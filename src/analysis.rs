@@ -0,0 +1,502 @@
+//! Read-only analysis of the Transitions registered with a [StateMachineFactory].
+
+use std::fmt::Debug;
+use crate::{FromState, StateMachineFactory, Trigger};
+use crate::Target::{Calc, Same, To};
+
+/// The problems found by [StateMachineFactory::validate_workflow], each list in the order of the
+/// States or Transitions it is about
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WorkflowReport<TState> {
+    /// Final States that no sequence of Transitions leads to from the initial State
+    pub unreachable_final_states: Vec<TState>,
+    /// States other than final States that have no Transition out of them, where the workflow
+    /// would get stuck
+    pub dead_ends: Vec<TState>,
+    /// Transitions into a State that isn't one of the workflow's States, by position along with
+    /// the target
+    pub unknown_targets: Vec<(usize, TState)>,
+}
+
+impl <TState> WorkflowReport<TState> {
+    /// True if no problem was found.
+    pub fn is_valid(&self) -> bool {
+        self.unreachable_final_states.is_empty() && self.dead_ends.is_empty() && self.unknown_targets.is_empty()
+    }
+}
+
+/// The Transitions of a definition as a graph over its known States. Only Transitions with a fixed
+/// target appear as edges.
+pub(crate) struct StateGraph<TState> {
+    pub(crate) states: Vec<TState>,
+    pub(crate) edges: Vec<Edge>,
+}
+
+/// An edge of a [StateGraph], referring to States by their position in [StateGraph::states]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Edge {
+    pub(crate) from: usize,
+    pub(crate) to: usize,
+    /// The position of the Transition this edge came from, in registration order
+    pub(crate) transition: usize,
+}
+
+impl <TState: PartialEq<TState> + Clone> StateGraph<TState> {
+    fn index_of(&self, state: &TState) -> usize {
+        self.states.iter().position(|s| s == state).expect("all states named by transitions are known")
+    }
+
+    /// The positions of the States that `from_state` describes
+    pub(crate) fn expand(&self, from_state: &FromState<TState>) -> Vec<usize> {
+        match from_state {
+            FromState::Any => (0..self.states.len()).collect(),
+            FromState::AnyOf(states) => states.iter().map(|s| self.index_of(s)).collect(),
+            FromState::Not(states) => (0..self.states.len())
+                .filter(|index| !states.contains(&self.states[*index]))
+                .collect(),
+            FromState::From(state) => vec![self.index_of(state)]
+        }
+    }
+
+    /// Groups States into strongly-connected components, using Tarjan's algorithm
+    fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        struct Tarjan<'g> {
+            edges: &'g [Edge],
+            next_index: usize,
+            index: Vec<Option<usize>>,
+            low_link: Vec<usize>,
+            on_stack: Vec<bool>,
+            stack: Vec<usize>,
+            components: Vec<Vec<usize>>,
+        }
+
+        impl Tarjan<'_> {
+            fn visit(&mut self, state: usize) {
+                self.index[state] = Some(self.next_index);
+                self.low_link[state] = self.next_index;
+                self.next_index += 1;
+                self.stack.push(state);
+                self.on_stack[state] = true;
+
+                for edge in self.edges.iter().filter(|e| e.from == state) {
+                    match self.index[edge.to] {
+                        None => {
+                            self.visit(edge.to);
+                            self.low_link[state] = self.low_link[state].min(self.low_link[edge.to]);
+                        }
+                        Some(index) if self.on_stack[edge.to] => {
+                            self.low_link[state] = self.low_link[state].min(index);
+                        }
+                        Some(_) => {}
+                    }
+                }
+
+                if Some(self.low_link[state]) == self.index[state] {
+                    let mut component = Vec::new();
+                    while let Some(member) = self.stack.pop() {
+                        self.on_stack[member] = false;
+                        component.push(member);
+                        if member == state {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    self.components.push(component);
+                }
+            }
+        }
+
+        let state_count = self.states.len();
+        let mut tarjan = Tarjan {
+            edges: &self.edges,
+            next_index: 0,
+            index: vec![None; state_count],
+            low_link: vec![0; state_count],
+            on_stack: vec![false; state_count],
+            stack: Vec::new(),
+            components: Vec::new(),
+        };
+        for state in 0..state_count {
+            if tarjan.index[state].is_none() {
+                tarjan.visit(state);
+            }
+        }
+        tarjan.components.sort();
+        tarjan.components
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Every State this definition knows about: the registered States, followed by any other State
+    /// named by a Transition, in the order they were first named.
+    pub(crate) fn known_states(&self) -> Vec<TState> {
+        let mut states = self.definition.states.clone();
+        let mut add = |state: &TState| {
+            if !states.contains(state) {
+                states.push(state.clone());
+            }
+        };
+        for transition in &self.transitions {
+            match &transition.from_state {
+                FromState::Any => {}
+                FromState::AnyOf(from_states) | FromState::Not(from_states) => from_states.iter().for_each(&mut add),
+                FromState::From(from_state) => add(from_state)
+            }
+            if let To(to_state) = &transition.get_to_state {
+                add(to_state);
+            }
+        }
+        states
+    }
+
+    /// Builds the graph of Transitions with a fixed target. [Same](crate::ToState::Same) Transitions are included as
+    /// edges from each State back into itself.
+    pub(crate) fn state_graph(&self) -> StateGraph<TState> {
+        let mut graph = StateGraph {
+            states: self.known_states(),
+            edges: Vec::new(),
+        };
+        for (position, transition) in self.transitions.iter().enumerate() {
+            let to_state = match &transition.get_to_state {
+                To(to_state) => Some(graph.index_of(to_state)),
+                Same => None,
+                Calc(_) => continue
+            };
+            for from in graph.expand(&transition.from_state) {
+                graph.edges.push(Edge {
+                    from,
+                    to: to_state.unwrap_or(from),
+                    transition: position,
+                });
+            }
+        }
+        graph
+    }
+
+    /// Finds the cycles between States: groups of two or more States that can each be reached
+    /// from one another through Transitions. Each group is listed in the order its States are
+    /// known to the factory. Transitions from a State back into itself are not considered cycles
+    /// here; see [StateMachineFactory::find_self_loops].
+    pub fn find_cycles(&self) -> Vec<Vec<TState>> {
+        let graph = self.state_graph();
+        graph.strongly_connected_components()
+            .into_iter()
+            .filter(|component| component.len() > 1)
+            .map(|component| component.into_iter().map(|state| graph.states[state].clone()).collect())
+            .collect()
+    }
+
+    /// Finds the States that have a Transition back into themselves, either through [Same](crate::ToState::Same) or by
+    /// naming the State as both the source and the target.
+    pub fn find_self_loops(&self) -> Vec<TState> {
+        let graph = self.state_graph();
+        graph.states.iter()
+            .enumerate()
+            .filter(|(state, _)| graph.edges.iter().any(|e| e.from == *state && e.to == *state))
+            .map(|(_, state)| state.clone())
+            .collect()
+    }
+
+    /// Lists the States with a Transition directly into `target`, along with what triggers it,
+    /// answering questions such as "how can the machine end up in `Error`?". Transitions from
+    /// [FromState::Any], [FromState::AnyOf] or [FromState::Not] are listed once for each State they
+    /// apply from, in the order the States are known to the factory, and Transitions are listed in
+    /// registration order. [Same](crate::ToState::Same) Transitions count as reaching the State they start from, while
+    /// [Calc](crate::ToState::Calc) Transitions are left out, as their target is only known once they are taken.
+    pub fn states_reaching(&self, target: &TState) -> Vec<(TState, Trigger<'a, TEvent>)> {
+        let graph = self.state_graph();
+        graph.edges.iter()
+            .filter(|edge| graph.states[edge.to] == *target)
+            .map(|edge| (graph.states[edge.from].clone(), self.transitions[edge.transition].trigger()))
+            .collect()
+    }
+
+    /// Checks the definition as a workflow through `all_states`, starting from `initial` and meant
+    /// to end in one of `final_states`: every final State has to be reachable from `initial`,
+    /// every other State has to have a Transition out of it, and every Transition has to lead to
+    /// one of `all_states`. Guards can't be evaluated statically, so guarded Transitions count as
+    /// taken. [Calc](crate::ToState::Calc) Transitions count as a way out of a State, but as their
+    /// target is only known once they are taken, they lead nowhere for reachability. Meant to be
+    /// called from a test, as in `assert!(factory.validate_workflow(..).is_valid())`.
+    pub fn validate_workflow(&self, initial: &TState, all_states: &[TState], final_states: &[TState]) -> WorkflowReport<TState> {
+        let graph = self.state_graph();
+        let mut reachable = vec![false; graph.states.len()];
+        let mut unexplored: Vec<usize> = graph.states.iter().position(|state| state == initial).into_iter().collect();
+        while let Some(state) = unexplored.pop() {
+            if std::mem::replace(&mut reachable[state], true) {
+                continue;
+            }
+            unexplored.extend(graph.edges.iter().filter(|edge| edge.from == state).map(|edge| edge.to));
+        }
+
+        WorkflowReport {
+            unreachable_final_states: final_states.iter()
+                .filter(|state| state != &initial && !graph.states.iter().zip(&reachable).any(|(known, reachable)| *reachable && known == *state))
+                .cloned()
+                .collect(),
+            dead_ends: all_states.iter()
+                .filter(|state| !final_states.contains(state))
+                .filter(|state| !self.transitions.iter().any(|transition| transition.from_state.matches(state) && match &transition.get_to_state {
+                    To(to_state) => to_state != *state,
+                    Same => false,
+                    Calc(_) => true
+                }))
+                .cloned()
+                .collect(),
+            unknown_targets: self.transitions.iter()
+                .enumerate()
+                .filter_map(|(position, transition)| match &transition.get_to_state {
+                    To(to_state) if !all_states.contains(to_state) => Some((position, to_state.clone())),
+                    _ => None
+                })
+                .collect(),
+        }
+    }
+
+    /// Finds the first pair of effects that apply to the same Event in the same known State, for
+    /// [StateMachineFactory::strict], as the State and the positions of the two Transitions.
+    pub(crate) fn find_overlapping_effects(&self) -> Option<(TState, usize, usize)> {
+        let checked: Vec<_> = self.transitions.iter()
+            .enumerate()
+            .filter(|(_, transition)| transition.effect.is_some() && !transition.cross_cutting && !transition.exclusive && !transition.consuming)
+            .collect();
+        for state in self.known_states() {
+            for (index, (first, earlier)) in checked.iter().enumerate() {
+                for (second, later) in &checked[index + 1..] {
+                    let same_event = match (earlier.event, later.event) {
+                        (Some(a), Some(b)) => self.definition.event_matches(a, b),
+                        _ => true
+                    };
+                    if same_event && earlier.from_state.matches(&state) && later.from_state.matches(&state) {
+                        return Some((state, *first, *second));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Lists the Events from `all_events` that have at least one Transition from `state`. Guards
+    /// can't be evaluated statically, so a guarded Transition counts as accepting every Event and
+    /// the result is an upper bound; see [crate::StateMachine::accepted_events] for the Events a
+    /// running State Machine actually accepts. As there, Transitions that apply to every Event
+    /// without a predicate (such as loggers) don't count.
+    pub fn events_accepted_in(&self, state: &TState, all_events: &[TEvent]) -> Vec<TEvent>
+    where TEvent: Clone
+    {
+        all_events.iter()
+            .filter(|event| self.transitions.iter().any(|transition| {
+                transition.from_state.matches(state) && match transition.event {
+                    Some(expected) => self.definition.event_matches(expected, event),
+                    None => transition.event_predicate.is_some()
+                }
+            }))
+            .cloned()
+            .collect()
+    }
+
+    /// Lists every pair of a State from `all_states` and an Event from `all_events` that no
+    /// Transition handles, in the order of `all_states` and then of `all_events`. As for
+    /// [StateMachineFactory::events_accepted_in], a guarded Transition counts as handling every
+    /// Event, and Transitions that apply to every Event without a predicate don't count.
+    pub fn unhandled_events(&self, all_states: &[TState], all_events: &[TEvent]) -> Vec<(TState, TEvent)>
+    where TEvent: Clone
+    {
+        all_states.iter()
+            .flat_map(|state| {
+                let accepted = self.events_accepted_in(state, all_events);
+                all_events.iter()
+                    .filter(move |event| !accepted.iter().any(|accepted| self.definition.event_matches(accepted, event)))
+                    .map(move |event| (state.clone(), event.clone()))
+            })
+            .collect()
+    }
+
+    /// Checks that every Event from `all_events` is handled in every State from `all_states`, the
+    /// way a `match` has to cover every variant, for protocol machines that must not silently drop
+    /// Events. Pairs for which `ignored` returns true are intentionally left unhandled, for
+    /// example `|state, event| matches!((state, event), (Idle, Equals) | (_, Digit(_)))`. Meant to
+    /// be called from a test.
+    ///
+    /// # Panics
+    ///
+    /// Panics listing every pair found by [StateMachineFactory::unhandled_events] that isn't
+    /// ignored, if there are any.
+    pub fn assert_all_events_handled(&self, all_states: &[TState], all_events: &[TEvent], ignored: impl Fn(&TState, &TEvent) -> bool)
+    where TEvent: Clone + Debug
+    {
+        let unhandled: Vec<_> = self.unhandled_events(all_states, all_events)
+            .into_iter()
+            .filter(|(state, event)| !ignored(state, event))
+            .collect();
+        assert!(unhandled.is_empty(), "Events with no Transition, as (state, event): {:?}", unhandled);
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{StateMachineFactory, Trigger, WorkflowReport};
+    use crate::FromState::{AnyOf, Not};
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum CalculatorStates {
+        Idle,
+        Adding,
+        Subtracting,
+        Multiplying,
+        Dividing
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum CalculatorEvents {
+        Add,
+        Subtract,
+        Multiply,
+        Divide,
+        Equals
+    }
+
+    #[test]
+    fn test_find_cycles() {
+        use CalculatorStates::*;
+
+        let factory = StateMachineFactory::<CalculatorEvents, CalculatorStates, ()>::new()
+            .with_states([Idle, Adding, Subtracting, Multiplying, Dividing])
+            .with_event_transition(&CalculatorEvents::Equals, AnyOf(vec![Adding, Subtracting, Multiplying, Dividing]), Idle)
+            .with_event_transition(&CalculatorEvents::Add, Idle, Adding)
+            .with_event_transition(&CalculatorEvents::Subtract, Idle, Subtracting)
+            .with_event_transition(&CalculatorEvents::Multiply, Idle, Multiplying)
+            .with_event_transition(&CalculatorEvents::Divide, Idle, Dividing);
+
+        assert_eq!(vec![vec![Idle, Adding, Subtracting, Multiplying, Dividing]], factory.find_cycles());
+        assert!(factory.find_self_loops().is_empty());
+    }
+
+    #[test]
+    fn test_states_reaching() {
+        use CalculatorStates::*;
+
+        let factory = StateMachineFactory::<CalculatorEvents, CalculatorStates, ()>::new()
+            .with_states([Idle, Adding, Subtracting, Multiplying, Dividing])
+            .with_event_transition(&CalculatorEvents::Equals, AnyOf(vec![Adding, Subtracting, Multiplying, Dividing]), Idle)
+            .with_event_transition(&CalculatorEvents::Add, Idle, Adding)
+            .with_predicated_transition(Adding, Same, |_| true);
+
+        let equals = Trigger::Event(&CalculatorEvents::Equals);
+        assert_eq!(vec![(Adding, equals), (Subtracting, equals), (Multiplying, equals), (Dividing, equals)], factory.states_reaching(&Idle));
+        assert_eq!(vec![(Idle, Trigger::Event(&CalculatorEvents::Add)), (Adding, Trigger::Guard)], factory.states_reaching(&Adding));
+        assert!(factory.states_reaching(&Dividing).is_empty());
+    }
+
+    #[test]
+    fn test_find_cycles_acyclic() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum Workflow {
+            Draft,
+            Review,
+            Published,
+            Rejected
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Submit,
+            Approve,
+            Reject,
+            Edit
+        }
+
+        let factory = StateMachineFactory::<Events, Workflow, ()>::new()
+            .with_event_transition(&Events::Submit, Workflow::Draft, Workflow::Review)
+            .with_event_transition(&Events::Approve, Workflow::Review, Workflow::Published)
+            .with_event_transition(&Events::Reject, Workflow::Review, Workflow::Rejected)
+            .with_event_transition(&Events::Edit, Workflow::Draft, Same);
+
+        assert!(factory.find_cycles().is_empty());
+        // Same transitions are reported separately as self loops
+        assert_eq!(vec![Workflow::Draft], factory.find_self_loops());
+    }
+
+    #[test]
+    fn test_events_accepted_in() {
+        use CalculatorStates::*;
+        use CalculatorEvents::*;
+
+        let factory = StateMachineFactory::<CalculatorEvents, CalculatorStates, ()>::new()
+            .with_event_transition(&Equals, Not(vec![Idle]), Idle)
+            .with_event_transition(&Add, Idle, Adding)
+            .with_event_transition(&Subtract, Idle, Subtracting)
+            .with_predicated_transition(Dividing, Idle, |_| true);
+        let all_events = [Add, Subtract, Multiply, Divide, Equals];
+
+        assert_eq!(vec![Add, Subtract], factory.events_accepted_in(&Idle, &all_events));
+        assert_eq!(vec![Equals], factory.events_accepted_in(&Adding, &all_events));
+        // The guarded Transition might accept any Event
+        assert_eq!(all_events.to_vec(), factory.events_accepted_in(&Dividing, &all_events));
+        // Not is expanded to every other known State
+        assert_eq!(vec![vec![Idle, Adding, Subtracting]], factory.find_cycles());
+    }
+
+    #[test]
+    fn test_unhandled_events() {
+        use CalculatorStates::*;
+        use CalculatorEvents::*;
+
+        let factory = StateMachineFactory::<CalculatorEvents, CalculatorStates, ()>::new()
+            .with_event_transition(&Equals, Not(vec![Idle]), Idle)
+            .with_event_transition(&Add, AnyOf(vec![Idle, Adding]), Adding)
+            .with_event_transition(&Subtract, Idle, Subtracting);
+        let all_states = [Idle, Adding, Subtracting];
+        let all_events = [Add, Subtract, Equals];
+
+        assert_eq!(vec![(Idle, Equals), (Adding, Subtract), (Subtracting, Add), (Subtracting, Subtract)],
+            factory.unhandled_events(&all_states, &all_events));
+        factory.assert_all_events_handled(&all_states, &all_events, |state, event| {
+            matches!((state, event), (Idle, Equals) | (Adding | Subtracting, Subtract) | (Subtracting, Add))
+        });
+        let missing = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| factory.assert_all_events_handled(&all_states, &all_events, |_, event| *event != Add)));
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_validate_workflow() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Draft,
+            Review,
+            Approved,
+            Published,
+            Archived,
+            Lost
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Submit,
+            Approve,
+            Publish,
+            Misplace
+        }
+
+        let all_states = [States::Draft, States::Review, States::Approved, States::Published, States::Archived];
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Submit, States::Draft, States::Review)
+            .with_event_transition(&Events::Approve, States::Review, States::Approved)
+            .with_event_transition(&Events::Misplace, States::Review, States::Lost)
+            .with_event_transition(&Events::Publish, States::Approved, Same);
+
+        assert_eq!(WorkflowReport {
+            unreachable_final_states: vec![States::Published, States::Archived],
+            // Approved only loops back into itself
+            dead_ends: vec![States::Approved],
+            unknown_targets: vec![(2, States::Lost)],
+        }, factory.validate_workflow(&States::Draft, &all_states, &[States::Published, States::Archived]));
+
+        let fixed = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Submit, States::Draft, States::Review)
+            .with_event_transition(&Events::Approve, States::Review, States::Approved)
+            .with_event_transition(&Events::Publish, States::Approved, States::Published);
+        assert!(fixed.validate_workflow(&States::Draft, &all_states[..4], &[States::Published]).is_valid());
+    }
+}
@@ -0,0 +1,111 @@
+//! Guards that await a future, for Transitions whose legality depends on external state.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use crate::{DispatchContext, FromState, StateMachine, StateMachineError, StateMachineFactory, StateMachineTransition, StateTransitionEffectData, ToState};
+
+/// Boxed guard returning a future that resolves to whether its Transition may be taken
+pub(crate) type AsyncGuard<'a, TEvent, TData> = Arc<dyn Fn(&TEvent, &TData) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> + Send + Sync + 'a>;
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Adds a Transition with a Side Effect whose guard returns a future, such as a lookup of a
+    /// remote feature flag, like `.with_predicated_transition_effect(..)`. The future has to own
+    /// whatever it needs, so a guard typically clones a handle out of the data before moving it
+    /// into an `async` block.
+    ///
+    /// Async guards are only awaited by [StateMachine::handle_event_async], which awaits every
+    /// async guard whose Transition matches the current State and Event before handling the Event
+    /// like [StateMachine::handle_event]. Any other way of handling an Event, and any Event
+    /// enqueued by an effect, counts the guard as failed. Awaiting happens in the matching path,
+    /// one guard after another, so each async guard adds its full latency to handling the Event;
+    /// synchronous predicates remain the fast path, and a Transition that can be decided without
+    /// IO shouldn't use an async guard.
+    pub fn with_async_guarded_transition_effect<TFuture: Future<Output = bool> + Send + 'a>(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, guard: impl Fn(&TEvent, &TData) -> TFuture + Send + Sync + 'a, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let id = self.transitions.iter().filter(|transition| transition.async_guard.is_some()).count();
+        let mut transition = StateMachineTransition::new(Some(Arc::new(move |d| d.context.async_guards.borrow().get(id).copied().unwrap_or(false))), from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.async_guard = Some((id, Arc::new(move |event, data| Box::pin(guard(event, data)))));
+        self.transitions.push(transition);
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Handles an Event like [StateMachine::handle_event], after awaiting the async guards of the
+    /// Transitions that match the current State and the Event (see
+    /// [StateMachineFactory::with_async_guarded_transition_effect]). The guards are awaited
+    /// against the State the State Machine is in when this is called, before any elapsed lazy
+    /// timeout or sticky Event is handled; a Transition out of a State only entered while handling
+    /// the Event counts its async guard as failed.
+    pub async fn handle_event_async(&mut self, event: TEvent) -> Result<&TState, StateMachineError<TState>> {
+        let definition = self.definition.clone();
+        let pending: Vec<_> = self.transitions.iter()
+            .filter(|transition| transition.event.is_none_or(|expected| definition.event_matches(expected, &event)))
+            .filter(|transition| definition.state_matches(&transition.from_state, &self.state))
+            .filter_map(|transition| transition.async_guard.as_ref().map(|(id, guard)| (*id, guard(&event, &self.data))))
+            .collect();
+
+        let context = DispatchContext::new();
+        for (id, guard) in pending {
+            let passed = guard.await;
+            let mut async_guards = context.async_guards.borrow_mut();
+            if async_guards.len() <= id {
+                async_guards.resize(id + 1, false);
+            }
+            async_guards[id] = passed;
+        }
+        self.handle_external_event_in(&event, context)?;
+        Ok(&self.state)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Poll;
+    use crate::StateMachineFactory;
+    use crate::executor::block_on;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Waiting,
+        Enrolled
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Enroll
+    }
+
+    /// Resolves to the flag's value after being pending once, like a remote lookup
+    async fn remote_flag(flag: Arc<AtomicBool>) -> bool {
+        let mut polled = false;
+        std::future::poll_fn(|cx| {
+            if polled {
+                return Poll::Ready(());
+            }
+            polled = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }).await;
+        flag.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_async_guarded_transition() {
+        let factory = StateMachineFactory::<Events, States, Arc<AtomicBool>>::new()
+            .with_async_guarded_transition_effect(States::Waiting, States::Enrolled, |_, flag| remote_flag(flag.clone()), |_| Ok(()))
+            .lock();
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut sm = factory.build(States::Waiting, flag.clone());
+        assert_eq!(&States::Waiting, block_on(sm.handle_event_async(Events::Enroll)).expect("unexpected error"));
+        flag.store(true, Ordering::SeqCst);
+        // Handling the Event synchronously doesn't await the guard
+        assert_eq!(&States::Waiting, sm.handle_event(Events::Enroll).expect("unexpected error"));
+        assert_eq!(&States::Enrolled, block_on(sm.handle_event_async(Events::Enroll)).expect("unexpected error"));
+    }
+}
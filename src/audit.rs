@@ -0,0 +1,117 @@
+//! Audit records of the changes Events make to a State Machine, for undo stacks and audit logs.
+
+use std::fmt::Debug;
+use crate::{SharedStateMachine, StateMachine, StateMachineError};
+use crate::export::json_string;
+
+/// A complete record of handling one Event, returned by [StateMachine::handle_event_audited]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Audit<TEvent, TState, TData> {
+    /// The Event that was handled
+    pub event: TEvent,
+    /// The state before handling the Event
+    pub before_state: TState,
+    /// The state after handling the Event and every Event it enqueued
+    pub after_state: TState,
+    /// The data before handling the Event
+    pub before_data: TData,
+    /// The data after handling the Event and every Event it enqueued
+    pub after_data: TData,
+}
+
+impl <TEvent: Debug, TState: Debug, TData: Debug> Audit<TEvent, TState, TData> {
+    /// Renders the record as a single line of JSON, for appending to a JSON Lines audit log, as
+    /// in `{"event": "Add", "before_state": "Idle", "after_state": "Adding", "before_data": "0", "after_data": "0"}`.
+    /// Each field holds the [Debug] representation of the value, as in
+    /// [crate::StateMachineFactory::to_json_definition].
+    pub fn to_json_line(&self) -> String {
+        format!("{{\"event\": {}, \"before_state\": {}, \"after_state\": {}, \"before_data\": {}, \"after_data\": {}}}",
+            json_string(&format!("{:?}", self.event)),
+            json_string(&format!("{:?}", self.before_state)),
+            json_string(&format!("{:?}", self.after_state)),
+            json_string(&format!("{:?}", self.before_data)),
+            json_string(&format!("{:?}", self.after_data)))
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData: Clone> StateMachine<'a, TEvent, TState, TData> {
+    /// Handles an Event like [StateMachine::handle_event], and returns a snapshot of the state and
+    /// data from before and after handling it, such as an entry for an undo stack. Both snapshots
+    /// are taken within the same call, so nothing else can change the State Machine between them.
+    /// This clones the data twice, so it is a separate method from [StateMachine::handle_event].
+    pub fn handle_event_audited(&mut self, event: TEvent) -> Result<Audit<TEvent, TState, TData>, StateMachineError<TState>> {
+        let before_state = self.state.clone();
+        let before_data = self.data.clone();
+        self.handle_external_event(&event)?;
+        Ok(Audit {
+            event,
+            before_state,
+            after_state: self.state.clone(),
+            before_data,
+            after_data: self.data.clone(),
+        })
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData: Clone> SharedStateMachine<'a, TEvent, TState, TData> {
+    /// Handles an Event like [StateMachine::handle_event_audited], holding the write lock from the
+    /// first snapshot to the last, so that no other handle can slip a Transition in between.
+    pub fn handle_event_audited(&self, event: TEvent) -> Result<Audit<TEvent, TState, TData>, StateMachineError<TState>> {
+        let result = self.write().handle_event_audited(event);
+        self.wakeup.notify();
+        result
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::cell::Cell;
+    use crate::{Audit, StateMachineFactory};
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Add,
+        Digit(i64)
+    }
+
+    #[test]
+    fn test_handle_event_audited() {
+        let factory = StateMachineFactory::<Events, States, Cell<i64>>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_predicated_transition_effect(States::Adding, Same, |d| matches!(d.event, Events::Digit(_)), |d| {
+                if let Events::Digit(digit) = d.event {
+                    d.data.set(d.data.get() + digit);
+                }
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(States::Idle, Cell::new(40));
+        assert_eq!(States::Adding, sm.handle_event_audited(Events::Add).expect("unexpected error").after_state);
+        assert_eq!(Audit {
+            event: Events::Digit(2),
+            before_state: States::Adding,
+            after_state: States::Adding,
+            before_data: Cell::new(40),
+            after_data: Cell::new(42),
+        }, sm.handle_event_audited(Events::Digit(2)).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_audit_to_json_line() {
+        let mut sm = StateMachineFactory::<Events, States, Vec<&str>>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .lock()
+            .build(States::Idle, vec!["a \"quoted\" entry"]);
+
+        let audit = sm.handle_event_audited(Events::Add).expect("unexpected error");
+        assert_eq!(r#"{"event": "Add", "before_state": "Idle", "after_state": "Adding", "before_data": "[\"a \\\"quoted\\\" entry\"]", "after_data": "[\"a \\\"quoted\\\" entry\"]"}"#, audit.to_json_line());
+    }
+}
@@ -0,0 +1,220 @@
+//! Compact binary logs of the Events a State Machine has handled, for storing long event streams
+//! and replaying them later, enabled by the `binary-log` feature.
+
+use std::fmt::Debug;
+use std::io::{self, Read};
+use std::sync::Arc;
+use thiserror::Error;
+use crate::{StateMachine, StateMachineError, StateMachineFactory};
+
+/// An Event that can be written to a binary event log with [StateMachine::export_log_binary] and
+/// read back by [StateMachine::replay_log_binary]. The crate doesn't depend on a serialization
+/// library, so the application encodes its Events, by hand or with the codec of its choice; the
+/// log only frames each encoding with its length.
+pub trait BinaryEvent: Sized {
+    /// Appends the encoding of this Event to `buffer`.
+    fn encode(&self, buffer: &mut Vec<u8>);
+
+    /// Reads an Event back from the bytes [BinaryEvent::encode] wrote for it.
+    fn decode(bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Error replaying a binary event log with [StateMachine::replay_log_binary]. The records before
+/// the one that failed have been replayed.
+#[derive(Error, Debug)]
+pub enum BinaryLogError<TState: Debug + Send + Clone + Eq + PartialEq> {
+    /// Reading the log failed
+    #[error("error reading event log: {0}")]
+    Io(io::Error),
+    /// The log ends in the middle of the record at the given position
+    #[error("event log ends within record {0}")]
+    Truncated(usize),
+    /// The Event of the record at the given position couldn't be decoded
+    #[error("error decoding record {0} of event log: {1}")]
+    Decode(usize, Box<dyn std::error::Error + Send + Sync>),
+    /// Handling a replayed Event failed
+    #[error("error replaying event log: {0}")]
+    Replay(StateMachineError<TState>),
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Makes State Machines keep a log of the Events passed to [StateMachine::handle_event] and
+    /// the other ways of handling an Event, for [StateMachine::export_log_binary]. Only Events
+    /// that were handled successfully are logged. Events that effects enqueue or schedule, sticky
+    /// Events and timeouts aren't logged, as replaying the logged Events raises them again. The
+    /// log clones every Event and grows with every one, so it is off by default.
+    pub fn record_event_log(mut self) -> Self
+    where TEvent: Clone
+    {
+        if self.definition.event_clone.is_none() {
+            self.definition.event_clone = Some(Arc::new(TEvent::clone));
+        }
+        self.definition.record_event_log = true;
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// The Events logged since the State Machine was built, oldest first; see
+    /// [StateMachineFactory::record_event_log].
+    pub fn event_log(&self) -> &[TEvent] {
+        &self.event_log
+    }
+
+    /// Adds `event` to the event log, if the definition records one
+    pub(crate) fn log_event(&mut self, event: &TEvent) {
+        if !self.definition.record_event_log {
+            return;
+        }
+        let clone = self.definition.event_clone.as_ref().expect("events are cloneable whenever they are logged");
+        self.event_log.push(clone(event));
+    }
+
+    /// Encodes the [StateMachine::event_log] as a binary log: each Event in turn, encoded with
+    /// [BinaryEvent::encode] and preceded by the length of its encoding as four little-endian
+    /// bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an Event's encoding is longer than `u32::MAX` bytes.
+    pub fn export_log_binary(&self) -> Vec<u8>
+    where TEvent: BinaryEvent
+    {
+        let mut log = Vec::new();
+        for event in &self.event_log {
+            let start = log.len();
+            log.extend_from_slice(&[0; 4]);
+            event.encode(&mut log);
+            let length = u32::try_from(log.len() - start - 4).expect("encoded events fit in a record");
+            log[start..start + 4].copy_from_slice(&length.to_le_bytes());
+        }
+        log
+    }
+
+    /// Reconstructs the State Machine from a binary log written by
+    /// [StateMachine::export_log_binary], decoding and replaying one Event at a time as with
+    /// [StateMachine::replay_log], so the log is never held in memory as a whole. Replaying stops
+    /// at the first record that can't be read or decoded, or whose Event fails.
+    pub fn replay_log_binary(&mut self, mut reader: impl Read) -> Result<&TState, BinaryLogError<TState>>
+    where TEvent: BinaryEvent
+    {
+        let mut failure = None;
+        let mut buffer = Vec::new();
+        let mut record = 0;
+        let events = std::iter::from_fn(|| {
+            let event = match read_record(&mut reader, &mut buffer, record) {
+                Ok(true) => TEvent::decode(&buffer).map_err(|e| BinaryLogError::Decode(record, e)),
+                Ok(false) => return None,
+                Err(e) => Err(e)
+            };
+            record += 1;
+            event.map_err(|e| failure = Some(e)).ok()
+        });
+        let replayed = self.replay_log(events).map(|_| ()).map_err(BinaryLogError::Replay);
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        replayed.map(|()| &self.state)
+    }
+}
+
+/// Reads the record at position `record` into `buffer`, returning false at the end of the log
+fn read_record<TState: Debug + Send + Clone + Eq + PartialEq>(reader: &mut impl Read, buffer: &mut Vec<u8>, record: usize) -> Result<bool, BinaryLogError<TState>> {
+    let mut length = [0; 4];
+    let mut filled = 0;
+    while filled < length.len() {
+        match reader.read(&mut length[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(BinaryLogError::Truncated(record)),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(BinaryLogError::Io(e))
+        }
+    }
+    buffer.resize(u32::from_le_bytes(length) as usize, 0);
+    reader.read_exact(buffer).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => BinaryLogError::Truncated(record),
+        _ => BinaryLogError::Io(e)
+    })?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::{BinaryEvent, BinaryLogError, StateMachineFactory};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Digit(u8),
+        Add,
+        Note(String)
+    }
+
+    impl BinaryEvent for Events {
+        fn encode(&self, buffer: &mut Vec<u8>) {
+            match self {
+                Events::Digit(digit) => buffer.extend_from_slice(&[0, *digit]),
+                Events::Add => buffer.push(1),
+                Events::Note(note) => {
+                    buffer.push(2);
+                    buffer.extend_from_slice(note.as_bytes());
+                }
+            }
+        }
+
+        fn decode(bytes: &[u8]) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+            match bytes {
+                [0, digit] => Ok(Events::Digit(*digit)),
+                [1] => Ok(Events::Add),
+                [2, note @ ..] => Ok(Events::Note(String::from_utf8(note.to_vec())?)),
+                _ => Err("unknown event".into())
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_log_round_trip() {
+        let emails = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_predicated_transition_effect(Any, Same, |d| matches!(d.event, Events::Note(_)), |_| {
+                emails.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .side_effecting()
+            .record_event_log()
+            .lock();
+
+        let mut sm = factory.build(States::Idle, ());
+        let events = vec![Events::Digit(4), Events::Note("carry the one".to_string()), Events::Add, Events::Digit(2)];
+        sm.fire_all(events.clone()).expect("unexpected error");
+        assert_eq!(events, sm.event_log());
+        let log = sm.export_log_binary();
+        assert_eq!(&[2, 0, 0, 0, 0, 4], &log[..6]);
+
+        // The replica ends up where the original is, without sending the email again
+        let mut replica = factory.build(States::Idle, ());
+        assert_eq!(&States::Adding, replica.replay_log_binary(log.as_slice()).expect("unexpected error"));
+        assert_eq!(events, replica.event_log());
+        assert_eq!(1, emails.load(Ordering::SeqCst));
+
+        // The records before a truncated or undecodable one are replayed
+        let mut truncated = factory.build(States::Idle, ());
+        assert!(matches!(truncated.replay_log_binary(&log[..log.len() - 1]), Err(BinaryLogError::Truncated(3))));
+        assert_eq!(&events[..3], truncated.event_log());
+        assert_eq!(States::Adding, truncated.state);
+
+        let mut corrupt = factory.build(States::Idle, ());
+        assert!(matches!(corrupt.replay_log_binary([1, 0, 0, 0, 1, 1, 0, 0, 0, 9].as_slice()), Err(BinaryLogError::Decode(1, _))));
+        assert_eq!(States::Adding, corrupt.state);
+    }
+}
@@ -0,0 +1,155 @@
+//! Typed, step-by-step builder for event Transitions.
+
+use std::fmt::Debug;
+use crate::{FromState, StateMachineFactory, StateTransitionEffectData, ToState};
+
+/// First step of [StateMachineFactory::transition], waiting for the State the Transition applies
+/// from.
+///
+/// Each step is a different type that only offers the next step, so a Transition can't be
+/// registered before its source, Event and target have all been named, in that order:
+///
+/// ```compile_fail
+/// # use statement::StateMachineFactory;
+/// # use statement::FromState::Any;
+/// #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+/// enum States { Idle, Adding }
+/// #[derive(Eq, PartialEq)]
+/// enum Events { Add }
+///
+/// // `run` is only available once the target has been given with `to`
+/// let factory = StateMachineFactory::<Events, States, ()>::new()
+///     .transition().from(Any).on(&Events::Add).run(|_| Ok(()));
+/// ```
+#[must_use = "the Transition is only registered once the builder is completed"]
+pub struct TransitionBuilder<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    factory: StateMachineFactory<'a, TEvent, TState, TData>,
+}
+
+/// Step of [StateMachineFactory::transition] waiting for the Event the Transition applies to
+#[must_use = "the Transition is only registered once the builder is completed"]
+pub struct TransitionFromBuilder<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    factory: StateMachineFactory<'a, TEvent, TState, TData>,
+    from_state: FromState<TState>,
+}
+
+/// Step of [StateMachineFactory::transition] waiting for the State the Transition leads to
+#[must_use = "the Transition is only registered once the builder is completed"]
+pub struct TransitionEventBuilder<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    factory: StateMachineFactory<'a, TEvent, TState, TData>,
+    from_state: FromState<TState>,
+    event: &'a TEvent,
+}
+
+/// Last step of [StateMachineFactory::transition], which registers the Transition with or without
+/// an effect
+#[must_use = "the Transition is only registered once the builder is completed"]
+pub struct TransitionTargetBuilder<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    factory: StateMachineFactory<'a, TEvent, TState, TData>,
+    from_state: FromState<TState>,
+    event: &'a TEvent,
+    get_to_state: ToState<TEvent, TState, TData>,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData>
+where TEvent: PartialEq<TEvent> + Sync
+{
+    /// Starts building an event Transition one named step at a time, as in
+    /// `factory.transition().from(Any).on(&Events::Add).to(States::Adding).run(effect)`. This
+    /// registers the same Transition as `.with_event_transition_effect(..)`, without the risk of
+    /// passing its positional arguments in the wrong order.
+    pub fn transition(self) -> TransitionBuilder<'a, TEvent, TState, TData> {
+        TransitionBuilder {
+            factory: self,
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> TransitionBuilder<'a, TEvent, TState, TData>
+where TEvent: PartialEq<TEvent> + Sync
+{
+    /// Sets the State or States the Transition applies from.
+    pub fn from(self, from_state: impl Into<FromState<TState>>) -> TransitionFromBuilder<'a, TEvent, TState, TData> {
+        TransitionFromBuilder {
+            factory: self.factory,
+            from_state: from_state.into(),
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> TransitionFromBuilder<'a, TEvent, TState, TData>
+where TEvent: PartialEq<TEvent> + Sync
+{
+    /// Sets the Event the Transition applies to.
+    pub fn on(self, event: &'a TEvent) -> TransitionEventBuilder<'a, TEvent, TState, TData> {
+        TransitionEventBuilder {
+            factory: self.factory,
+            from_state: self.from_state,
+            event,
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> TransitionEventBuilder<'a, TEvent, TState, TData>
+where TEvent: PartialEq<TEvent> + Sync
+{
+    /// Sets the State the Transition leads to.
+    pub fn to(self, get_to_state: impl Into<ToState<TEvent, TState, TData>>) -> TransitionTargetBuilder<'a, TEvent, TState, TData> {
+        TransitionTargetBuilder {
+            factory: self.factory,
+            from_state: self.from_state,
+            event: self.event,
+            get_to_state: get_to_state.into(),
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> TransitionTargetBuilder<'a, TEvent, TState, TData>
+where TEvent: PartialEq<TEvent> + Sync
+{
+    /// Registers the Transition with a Side Effect, returning the factory.
+    pub fn run(self, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> StateMachineFactory<'a, TEvent, TState, TData> {
+        self.factory.with_event_transition_effect(self.event, self.from_state, self.get_to_state, effect)
+    }
+
+    /// Registers the Transition without a Side Effect, returning the factory.
+    pub fn done(self) -> StateMachineFactory<'a, TEvent, TState, TData> {
+        self.factory.with_event_transition(self.event, self.from_state, self.get_to_state)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::StateMachineFactory;
+    use crate::FromState::Any;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Add,
+        Clear
+    }
+
+    #[test]
+    fn test_transition_builder() {
+        let swaps = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .transition().from(States::Idle).on(&Events::Add).to(States::Adding).run(|_| {
+                swaps.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .transition().from(Any).on(&Events::Clear).to(States::Idle).done()
+            .lock();
+
+        let mut sm = factory.build(States::Idle, ());
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+        assert_eq!(&States::Idle, sm.handle_event(Events::Clear).expect("unexpected error"));
+        assert_eq!(1, swaps.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,167 @@
+//! Cancelling work started for a State once the State Machine leaves it.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Waker};
+use crate::{StateMachine, StateTransitionEffectData};
+
+/// A token that is cancelled once the State Machine leaves the state it was handed out for, by
+/// [StateTransitionEffectData::exit_token]. Long-running work started on entering a state, such as
+/// a polling loop or a task spawned on an async runtime, checks [CancellationToken::is_cancelled]
+/// or awaits [CancellationToken::cancelled] to stop once its state has been superseded, instead of
+/// running on as an orphan. Tokens are independent of any particular runtime, and can be cloned
+/// into as many tasks as need them.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenState>,
+}
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    /// The tasks waiting for cancellation
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    /// True once the state the token was handed out for has been left.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// A future that completes once the token is cancelled, for racing against the work it
+    /// guards, as in `select!`.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        Cancelled { token: self.clone() }
+    }
+
+    /// Cancels the token, waking every task awaiting [CancellationToken::cancelled]
+    pub(crate) fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Release);
+        for waker in self.inner.wakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken").field("cancelled", &self.is_cancelled()).finish()
+    }
+}
+
+/// Future returned by [CancellationToken::cancelled]
+struct Cancelled {
+    token: CancellationToken,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.token.inner.wakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Checked again under the lock, so that a cancellation in between isn't missed
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl <TEvent, TState, TData> StateTransitionEffectData<'_, TEvent, TState, TData> {
+    /// A token that is cancelled when the State Machine leaves [StateTransitionEffectData::to],
+    /// the state it is in once this Transition completes. An entry effect starting a long-running
+    /// task, such as an animation while `Connecting`, passes the token to the task so that it
+    /// stops when the State Machine moves on. Descending into the initial substates of the state
+    /// entered doesn't count as leaving it, so the token lasts until the State Machine moves on
+    /// from the substate it ends up in.
+    ///
+    /// If handling the Event fails, the state isn't entered, and the token is cancelled right
+    /// away. Dropping the State Machine doesn't cancel its tokens.
+    pub fn exit_token(&self) -> CancellationToken {
+        let token = CancellationToken::default();
+        self.context.exit_tokens.borrow_mut().push(token.clone());
+        token
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Cancels the tokens handed out for the state being left
+    pub(crate) fn cancel_exit_tokens(&mut self) {
+        for token in self.exit_tokens.drain(..) {
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::{Context, Poll, Wake, Waker};
+    use crate::{CancellationToken, StateMachineFactory};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Disconnected,
+        Connecting,
+        Connected
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Connect,
+        Retry,
+        Established
+    }
+
+    struct Flag(AtomicBool);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_exit_token() {
+        let tokens: Mutex<Vec<CancellationToken>> = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Connect, States::Disconnected, States::Connecting)
+            .with_event_transition_effect(&Events::Retry, States::Connecting, crate::ToState::Same, |d| {
+                tokens.lock().unwrap().push(d.exit_token());
+                Ok(())
+            })
+            .with_event_transition(&Events::Established, States::Connecting, States::Connected)
+            .with_entry_effect(States::Connecting, |d| {
+                tokens.lock().unwrap().push(d.exit_token());
+                Ok(())
+            })
+            .lock();
+        let mut sm = factory.build(States::Disconnected, ());
+
+        sm.handle_event(Events::Connect).expect("unexpected error");
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        let woken = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(woken.clone());
+        let mut cancelled = pin!(tokens.lock().unwrap()[0].cancelled());
+        assert_eq!(Poll::Pending, cancelled.as_mut().poll(&mut Context::from_waker(&waker)));
+        assert!(tokens.lock().unwrap().iter().all(|token| !token.is_cancelled()));
+
+        sm.handle_event(Events::Established).expect("unexpected error");
+        assert!(tokens.lock().unwrap().iter().all(CancellationToken::is_cancelled));
+        assert!(woken.0.load(Ordering::SeqCst));
+        assert_eq!(Poll::Ready(()), cancelled.as_mut().poll(&mut Context::from_waker(&waker)));
+    }
+}
@@ -0,0 +1,137 @@
+//! Orthogonal regions: several State Machines driven by the same Events.
+
+use std::fmt::Debug;
+use crate::{StateMachine, StateMachineError, StateTransitionEffectData};
+use crate::queue::DispatchContext;
+
+/// Identifier for a region added to a [CompositeMachine] with [CompositeMachine::add_region]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct RegionId(usize);
+
+impl RegionId {
+    /// The position of the region in the order regions were added.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// A set of orthogonal regions, each of which is an independent [StateMachine]. Every Event the
+/// `CompositeMachine` handles is handled by each region in turn, in the order the regions were
+/// added.
+///
+/// Effects and predicates in one region can read the states of the others through
+/// [StateTransitionEffectData::region_state], which allows regions to coordinate (for example, only
+/// allowing playback in an audio region while a network region is connected). They see a snapshot
+/// taken when their region began handling the Event, so a region observes the transitions made by
+/// the regions before it, and no region can modify another.
+pub struct CompositeMachine<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    regions: Vec<StateMachine<'a, TEvent, TState, TData>>,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Default for CompositeMachine<'a, TEvent, TState, TData> {
+    fn default() -> Self {
+        Self {
+            regions: Vec::new(),
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> CompositeMachine<'a, TEvent, TState, TData> {
+    /// Creates a `CompositeMachine` without any regions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a State Machine as a new region, returning the identifier other regions can use to
+    /// read its state.
+    pub fn add_region(&mut self, state_machine: StateMachine<'a, TEvent, TState, TData>) -> RegionId {
+        self.regions.push(state_machine);
+        RegionId(self.regions.len() - 1)
+    }
+
+    /// The State Machine of a region, or `None` if the region doesn't exist.
+    pub fn region(&self, region: RegionId) -> Option<&StateMachine<'a, TEvent, TState, TData>> {
+        self.regions.get(region.0)
+    }
+
+    /// The current state of a region, or `None` if the region doesn't exist.
+    pub fn region_state(&self, region: RegionId) -> Option<&TState> {
+        self.region(region).map(|state_machine| &state_machine.state)
+    }
+
+    fn region_states(&self) -> Vec<TState> {
+        self.regions.iter().map(|state_machine| state_machine.state.clone()).collect()
+    }
+
+    /// Handles an Event in every region, in the order the regions were added. Events enqueued by an
+    /// effect are handled by the region that enqueued them, before the next region receives the
+    /// Event. If a region fails to handle the Event, the error is returned straight away and the
+    /// remaining regions don't receive it.
+    pub fn handle_event(&mut self, event: TEvent) -> Result<(), StateMachineError<TState>> {
+        for region in 0..self.regions.len() {
+            let context = DispatchContext::with_regions(self.region_states());
+            self.regions[region].handle_event_in(&event, &context)?;
+        }
+        Ok(())
+    }
+}
+
+impl <TEvent, TState, TData> StateTransitionEffectData<'_, TEvent, TState, TData> {
+    /// The state of a region of the [CompositeMachine] that this State Machine is part of, as it was
+    /// when this machine's region began handling the Event. Returns `None` if there is no such
+    /// region, including when the State Machine isn't part of a `CompositeMachine`.
+    pub fn region_state(&self, region: RegionId) -> Option<&TState> {
+        self.context.regions.get(region.0)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use super::RegionId;
+    use crate::{CompositeMachine, StateMachineFactory};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Disconnected,
+        Connected,
+        Stopped,
+        Playing
+    }
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Connect,
+        Play
+    }
+
+    #[test]
+    fn test_region_state() {
+        let network = StateMachineFactory::new()
+            .with_event_transition(&Events::Connect, States::Disconnected, States::Connected)
+            .lock()
+            .build(States::Disconnected, ());
+
+        // The network region is added first, so it is assigned the first identifier
+        let network_region = RegionId(0);
+        let audio = StateMachineFactory::new()
+            .with_predicated_transition(States::Stopped, States::Playing, move |data| {
+                matches!(data.event, Events::Play)
+                    && data.region_state(network_region) == Some(&States::Connected)
+            })
+            .lock()
+            .build(States::Stopped, ());
+
+        let mut composite = CompositeMachine::new();
+        assert_eq!(network_region, composite.add_region(network));
+        let audio_region = composite.add_region(audio);
+
+        // Play is refused while the network is disconnected
+        composite.handle_event(Events::Play).expect("unexpected error");
+        assert_eq!(Some(&States::Stopped), composite.region_state(audio_region));
+
+        composite.handle_event(Events::Connect).expect("unexpected error");
+        composite.handle_event(Events::Play).expect("unexpected error");
+        assert_eq!(Some(&States::Connected), composite.region_state(network_region));
+        assert_eq!(Some(&States::Playing), composite.region_state(audio_region));
+    }
+}
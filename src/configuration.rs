@@ -0,0 +1,92 @@
+//! The active configuration of a State Machine with nested States.
+
+use std::fmt::Debug;
+use crate::{Definition, StateMachine, StateMachineFactory};
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Declares `child` as a substate of `parent`, other than its initial substate, which
+    /// [StateMachineFactory::with_initial_substate] declares already. This has no effect on how
+    /// Events are handled, and only places `child` under `parent` in
+    /// [StateMachine::active_states]. Declaring another parent for the same child replaces the
+    /// previous one.
+    pub fn with_substate(mut self, parent: TState, child: TState) -> Self {
+        self.definition.parent_states.retain(|(existing, _)| *existing != child);
+        self.definition.parent_states.push((child, parent));
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Definition<'a, TEvent, TState, TData> {
+    /// The parent of `state`, declared with [StateMachineFactory::with_substate] or
+    /// [StateMachineFactory::with_initial_substate]
+    fn parent_state(&self, state: &TState) -> Option<&TState> {
+        self.parent_states.iter()
+            .find(|(child, _)| self.same_state(child, state))
+            .map(|(_, parent)| parent)
+            .or_else(|| self.initial_substates.iter().find(|(_, child)| self.same_state(child, state)).map(|(parent, _)| parent))
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// The active configuration of the State Machine: the current state along with every state
+    /// it is nested in, from the outermost parent down to the current state, which a Transition
+    /// into a parent always leaves at a leaf. A State Machine without nested states is in a
+    /// configuration of just its current state.
+    pub fn active_states(&self) -> Vec<TState> {
+        let mut configuration = vec![self.state.clone()];
+        // Bounded by the number of parents, in case the declared nesting forms a cycle
+        for _ in 0..self.definition.parent_states.len() + self.definition.initial_substates.len() {
+            let Some(parent) = self.definition.parent_state(configuration.last().expect("the configuration holds the current state")) else {
+                break;
+            };
+            configuration.push(parent.clone());
+        }
+        configuration.reverse();
+        configuration
+    }
+
+    /// The [StateMachine::active_states] as a readable path such as `Connected > Idle`, rendering
+    /// each state with [Debug].
+    pub fn active_configuration_string(&self) -> String {
+        self.active_states().iter()
+            .map(|state| format!("{state:?}"))
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::StateMachineFactory;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Disconnected,
+        Connected,
+        Idle,
+        Busy
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Connect,
+        Request
+    }
+
+    #[test]
+    fn test_active_states() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Connect, States::Disconnected, States::Connected)
+            .with_event_transition(&Events::Request, States::Idle, States::Busy)
+            .with_initial_substate(States::Connected, States::Idle)
+            .with_substate(States::Connected, States::Busy)
+            .lock();
+
+        let mut sm = factory.build(States::Disconnected, ());
+        assert_eq!(vec![States::Disconnected], sm.active_states());
+        sm.handle_event(Events::Connect).expect("unexpected error");
+        assert_eq!(vec![States::Connected, States::Idle], sm.active_states());
+        sm.handle_event(Events::Request).expect("unexpected error");
+        assert_eq!("Connected > Busy", sm.active_configuration_string());
+    }
+}
@@ -0,0 +1,117 @@
+//! Coverage of a definition by the Events a State Machine has handled, enabled by the `coverage`
+//! feature.
+
+use std::fmt::Debug;
+use crate::StateMachine;
+
+/// How often one Transition of a definition has been taken
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransitionCoverage {
+    /// The position of the Transition in the locked definition, which is registration order unless
+    /// dependencies were declared with [crate::StateMachineFactory::after]
+    pub position: usize,
+    /// The id given with [crate::StateMachineFactory::effect_id], or else the name of the
+    /// Transition's predicate, if it has either
+    pub label: Option<String>,
+    /// The number of times the Transition was taken
+    pub times_taken: u64,
+}
+
+/// Which Transitions of a definition a State Machine has taken, returned by
+/// [StateMachine::coverage]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CoverageReport {
+    /// Every Transition of the definition, in order
+    pub transitions: Vec<TransitionCoverage>,
+}
+
+impl CoverageReport {
+    /// The Transitions that have been taken at least once.
+    pub fn covered(&self) -> Vec<&TransitionCoverage> {
+        self.transitions.iter().filter(|transition| transition.times_taken > 0).collect()
+    }
+
+    /// The Transitions that have never been taken.
+    pub fn uncovered(&self) -> Vec<&TransitionCoverage> {
+        self.transitions.iter().filter(|transition| transition.times_taken == 0).collect()
+    }
+
+    /// The fraction of Transitions that have been taken at least once, or 1 for a definition
+    /// without Transitions.
+    pub fn ratio(&self) -> f64 {
+        if self.transitions.is_empty() {
+            return 1.0;
+        }
+        self.covered().len() as f64 / self.transitions.len() as f64
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Reports which Transitions this State Machine has taken since it was built, for checking that
+    /// a test suite exercises the whole definition, much like code coverage. Transitions whose
+    /// predicate failed or whose effect failed aren't counted as taken.
+    pub fn coverage(&self) -> CoverageReport {
+        CoverageReport {
+            transitions: self.transitions.iter()
+                .enumerate()
+                .map(|(position, transition)| TransitionCoverage {
+                    position,
+                    label: transition.effect_id.clone().or_else(|| transition.name.clone()),
+                    times_taken: self.coverage.get(position).copied().unwrap_or(0),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Counts the Transition at `position` as taken in `coverage`
+pub(crate) fn record(coverage: &mut Vec<u64>, position: usize) {
+    if coverage.len() <= position {
+        coverage.resize(position + 1, 0);
+    }
+    coverage[position] += 1;
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::StateMachineFactory;
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Clear,
+        Digit(u8),
+        Add,
+        Equals
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut sm = StateMachineFactory::<Events, States, ()>::new()
+            .with_transition_effect(Any, Same, |_| Ok(())).effect_id("logger")
+            .with_event_transition(&Events::Clear, Any, States::Idle).effect_id("clear")
+            .with_named_predicated_transition_effect("digit", Any, Same, |d| matches!(d.event, Events::Digit(_)), |_| Ok(()))
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .lock()
+            .build(States::Idle, ());
+
+        for event in [Events::Digit(1), Events::Add, Events::Digit(2), Events::Equals] {
+            sm.handle_event(event).expect("unexpected error");
+        }
+        let report = sm.coverage();
+        assert_eq!(vec![4, 0, 2, 1, 1], report.transitions.iter().map(|transition| transition.times_taken).collect::<Vec<_>>());
+        let uncovered = report.uncovered();
+        assert_eq!(1, uncovered.len());
+        assert_eq!(Some("clear"), uncovered[0].label.as_deref());
+        assert_eq!(Some("digit"), report.covered()[1].label.as_deref());
+        assert_eq!(0.8, report.ratio());
+    }
+}
@@ -0,0 +1,117 @@
+//! Deferred Events: Events buffered in some States until the State Machine leaves them.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::{FromState, StateMachine, StateMachineError, StateMachineFactory};
+use crate::queue::DispatchContext;
+
+impl <'a, TEvent: PartialEq, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Defers Events matching `event` while in `state`, as UML statecharts do: instead of being
+    /// handled, such an Event is buffered until the State Machine changes state, and is then
+    /// handled in the new state, unless it is deferred there too. This suits Events that may
+    /// arrive before the State Machine is ready for them, such as a `Ship` request arriving while
+    /// a payment is still being authorized. Events match as for
+    /// [StateMachineFactory::with_event_transition].
+    ///
+    /// Deferral applies to Events enqueued by effects as much as to Events passed to
+    /// [StateMachine::handle_event]. As soon as handling an Event changes the state, the deferred
+    /// Events are handled, in the order they arrived, before the Events enqueued while handling
+    /// it; the buffered Events are listed by [StateMachine::deferred_events].
+    pub fn with_deferred_event(mut self, state: impl Into<FromState<TState>>, event: &'a TEvent) -> Self
+    where TEvent: Clone
+    {
+        if self.definition.event_eq.is_none() {
+            self.definition.event_eq = Some(Arc::new(|a, b| a == b));
+        }
+        if self.definition.event_clone.is_none() {
+            self.definition.event_clone = Some(Arc::new(TEvent::clone));
+        }
+        self.definition.deferred_events.push((state.into(), event));
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// The Events buffered because they were deferred in the state they arrived in (see
+    /// [StateMachineFactory::with_deferred_event]), in the order they arrived.
+    pub fn deferred_events(&self) -> &[TEvent] {
+        &self.deferred
+    }
+
+    /// Dispatches an Event unless it is deferred in the current state, in which case it is
+    /// buffered, and then handles the buffered Events if the state changed
+    pub(crate) fn dispatch_or_defer(&mut self, event: &TEvent, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        let definition = self.definition.clone();
+        if definition.deferred_events.iter().any(|(state, deferred)| definition.state_matches(state, &self.state) && definition.event_matches(deferred, event)) {
+            let clone = definition.event_clone.as_ref().expect("events are cloneable whenever one is deferred");
+            self.deferred.push(clone(event));
+            return Ok(());
+        }
+        if self.deferred.is_empty() {
+            return self.dispatch(event, context);
+        }
+
+        let changed_before = std::mem::replace(&mut self.state_changed, false);
+        let result = self.dispatch(event, context);
+        let changed = self.state_changed;
+        self.state_changed |= changed_before;
+        result?;
+        if changed {
+            let mut pending = std::mem::take(&mut self.deferred).into_iter();
+            while let Some(deferred) = pending.next() {
+                if let Err(error) = self.dispatch_or_defer(&deferred, context) {
+                    self.deferred.extend(pending);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Mutex;
+    use crate::StateMachineFactory;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Authorizing,
+        Paid,
+        Shipped
+    }
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Authorized,
+        Ship,
+        Cancel
+    }
+
+    #[test]
+    fn test_deferred_event() {
+        let factory = StateMachineFactory::<Events, States, Mutex<Vec<&str>>>::new()
+            .with_deferred_event(States::Authorizing, &Events::Ship)
+            .with_event_transition_effect(&Events::Authorized, States::Authorizing, States::Paid, |d| {
+                d.data.lock().unwrap().push("authorized");
+                Ok(())
+            })
+            .with_event_transition_effect(&Events::Ship, States::Paid, States::Shipped, |d| {
+                d.data.lock().unwrap().push("shipped");
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(States::Authorizing, Mutex::default());
+        assert_eq!(&States::Authorizing, sm.handle_event(Events::Ship).expect("unexpected error"));
+        assert_eq!(&[Events::Ship], sm.deferred_events());
+        // Events that aren't deferred are handled as usual, and don't release the buffer unless
+        // they change the state
+        sm.handle_event(Events::Cancel).expect("unexpected error");
+        assert_eq!(1, sm.deferred_events().len());
+        assert_eq!(&States::Shipped, sm.handle_event(Events::Authorized).expect("unexpected error"));
+        assert!(sm.deferred_events().is_empty());
+        assert!(sm.state_changed());
+        assert_eq!(vec!["authorized", "shipped"], *sm.data.lock().unwrap());
+    }
+}
@@ -0,0 +1,160 @@
+//! Structural comparison of definitions, for catching unintended changes to a State Machine.
+
+use std::fmt::Debug;
+use crate::{StateMachineFactory, Trigger, TransitionVisitor};
+
+/// The structure of one Transition from one State, as visited by [StateMachineFactory::visit].
+/// States and Events are named by their [Debug] representation.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TransitionShape {
+    /// The State the Transition is taken from
+    pub from: String,
+    /// The Event of an event Transition, or `guard` or `auto` for other Transitions
+    pub trigger: String,
+    /// The State the Transition leads to, or `None` when it is calculated
+    pub to: Option<String>,
+    /// The name of the Transition's predicate, if it has one
+    pub label: Option<String>,
+}
+
+/// A difference between two definitions, found by [StateMachineFactory::diff]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum DefinitionChange {
+    /// The Transition only exists in the new definition
+    Added(TransitionShape),
+    /// The Transition only exists in the old definition
+    Removed(TransitionShape),
+    /// A Transition from the same State, with the same trigger and label, leads somewhere else in
+    /// the new definition. The first shape is the old Transition and the second the new one.
+    Retargeted(TransitionShape, TransitionShape),
+}
+
+/// Collects the [TransitionShape] of every Transition of a definition
+#[derive(Default)]
+struct ShapeCollector {
+    shapes: Vec<TransitionShape>,
+}
+
+impl <TEvent: Debug, TState: Debug> TransitionVisitor<TEvent, TState> for ShapeCollector {
+    fn visit_transition(&mut self, from: &TState, trigger: Trigger<'_, TEvent>, to: Option<&TState>, label: Option<&str>) {
+        self.shapes.push(TransitionShape {
+            from: format!("{:?}", from),
+            trigger: match trigger {
+                Trigger::Event(event) => format!("{:?}", event),
+                Trigger::Guard => "guard".to_string(),
+                Trigger::Auto => "auto".to_string()
+            },
+            to: to.map(|to| format!("{:?}", to)),
+            label: label.map(str::to_string),
+        });
+    }
+}
+
+/// Hashes bytes with 64-bit FNV-1a, which unlike [std::hash::DefaultHasher] is the same across
+/// Rust versions and platforms
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3))
+}
+
+impl <'a, TEvent: Debug, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// A hash of the structure of the definition, as serialized by
+    /// [StateMachineFactory::to_json_definition]. It only changes when the structure does, and is
+    /// stable across Rust versions and platforms, so a test can compare it to a committed value to
+    /// catch unintended changes to a State Machine. Effects, predicates and calculated targets
+    /// can't be compared, so changing them doesn't change the hash.
+    pub fn structural_hash(&self) -> u64 {
+        fnv1a(self.to_json_definition().as_bytes())
+    }
+
+    /// Lists the structural differences between this definition and `other`, treating this one as
+    /// the old definition: the Transitions only `other` has, those only this one has, and those that
+    /// lead somewhere else in `other`. Transitions are compared by their [TransitionShape], so as
+    /// for [StateMachineFactory::structural_hash], changes to closures aren't found. Removed and
+    /// retargeted Transitions are listed first, in the order of this definition, followed by the
+    /// added ones in the order of `other`.
+    pub fn diff<TOtherData>(&self, other: &StateMachineFactory<'_, TEvent, TState, TOtherData>) -> Vec<DefinitionChange> {
+        let mut old = ShapeCollector::default();
+        self.visit(&mut old);
+        let mut new = ShapeCollector::default();
+        other.visit(&mut new);
+
+        // Transitions that are unchanged cancel out first, so that only the differences are paired
+        let mut added: Vec<Option<TransitionShape>> = new.shapes.into_iter().map(Some).collect();
+        let mut removed = Vec::new();
+        for shape in old.shapes {
+            match added.iter_mut().find(|candidate| candidate.as_ref() == Some(&shape)) {
+                Some(unchanged) => *unchanged = None,
+                None => removed.push(shape)
+            }
+        }
+
+        let mut changes = Vec::new();
+        for shape in removed {
+            let retargeted = added.iter_mut().find(|candidate| candidate.as_ref().is_some_and(|candidate| {
+                candidate.from == shape.from && candidate.trigger == shape.trigger && candidate.label == shape.label
+            }));
+            match retargeted.and_then(Option::take) {
+                Some(new_shape) => changes.push(DefinitionChange::Retargeted(shape, new_shape)),
+                None => changes.push(DefinitionChange::Removed(shape))
+            }
+        }
+        changes.extend(added.into_iter().flatten().map(DefinitionChange::Added));
+        changes
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{DefinitionChange, StateMachineFactory, TransitionShape};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding,
+        Subtracting
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    enum Events {
+        Add,
+        Subtract,
+        Equals
+    }
+
+    fn shape(from: &str, trigger: &str, to: &str) -> TransitionShape {
+        TransitionShape {
+            from: from.to_string(),
+            trigger: trigger.to_string(),
+            to: Some(to.to_string()),
+            label: None,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Subtract, States::Idle, States::Subtracting)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle);
+        let new = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Subtract, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Subtracting, States::Idle);
+
+        assert_eq!(vec![
+            DefinitionChange::Retargeted(shape("Idle", "Subtract", "Subtracting"), shape("Idle", "Subtract", "Adding")),
+            DefinitionChange::Removed(shape("Adding", "Equals", "Idle")),
+            DefinitionChange::Added(shape("Subtracting", "Equals", "Idle")),
+        ], old.diff(&new));
+        assert!(old.diff(&old).is_empty());
+
+        // A golden value pins the structure; it changes with it, but not with the closures
+        assert_eq!(0x3dcdad89cd4ea0d2, old.structural_hash());
+        assert_ne!(old.structural_hash(), new.structural_hash());
+        let with_effect = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition_effect(&Events::Add, States::Idle, States::Adding, |_| Ok(()))
+            .with_event_transition(&Events::Subtract, States::Idle, States::Subtracting)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle);
+        assert_eq!(old.structural_hash(), with_effect.structural_hash());
+    }
+}
@@ -0,0 +1,136 @@
+//! Precomputed dispatch tables, built by [StateMachineFactory::compile].
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::{Definition, LockedStateMachineFactory, StateMachineFactory};
+
+/// Size and build time of the dispatch table built by [StateMachineFactory::compile]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DispatchStats {
+    /// The number of registered States the table has a Transition list for
+    pub states: usize,
+    /// The number of Transitions in the definition
+    pub transitions: usize,
+    /// The total length of the per-State Transition lists. Transitions from [crate::FromState::Any]
+    /// or [crate::FromState::AnyOf] are counted once for each State they apply from.
+    pub entries: usize,
+    /// How long building the table took
+    pub elapsed: Duration,
+}
+
+/// For each registered State, in registration order, the positions of the Transitions that may
+/// apply from it
+pub(crate) struct DispatchIndex {
+    candidates: Vec<Vec<usize>>,
+    stats: DispatchStats,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Definition<'a, TEvent, TState, TData> {
+    /// The positions of the Transitions that may apply from `state`, or `None` if the definition
+    /// wasn't compiled or `state` isn't registered, in which case every Transition has to be
+    /// considered
+    pub(crate) fn candidates(&self, state: &TState) -> Option<&[usize]> {
+        let index = self.dispatch_index.as_ref()?;
+        let position = self.states.iter().position(|registered| self.same_state(registered, state))?;
+        Some(&index.candidates[position])
+    }
+}
+
+/// The position of the first Transition at or after `from` that may apply, out of `len`
+/// Transitions, given the [Definition::candidates] for the current State
+pub(crate) fn next_candidate(candidates: Option<&[usize]>, from: usize, len: usize) -> Option<usize> {
+    match candidates {
+        Some(candidates) => candidates.get(candidates.partition_point(|&index| index < from)).copied(),
+        None => (from < len).then_some(from)
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Locks the factory like [StateMachineFactory::lock], after resolving which Transitions may
+    /// apply from each State registered with [StateMachineFactory::with_states]. Handling an Event
+    /// then only considers those Transitions, instead of checking the source of every Transition in
+    /// the definition, so definitions with many States dispatch in time proportional to the
+    /// Transitions of the current State. Transitions are still considered in the same order, so
+    /// this doesn't change which Transitions are taken. States that weren't registered fall
+    /// back to considering every Transition.
+    ///
+    /// The size of the table is available from [LockedStateMachineFactory::dispatch_stats].
+    ///
+    /// # Panics
+    ///
+    /// Panics in the same cases as [StateMachineFactory::lock].
+    pub fn compile(self) -> LockedStateMachineFactory<'a, TEvent, TState, TData> {
+        let mut locked = self.lock();
+        let started = Instant::now();
+        let definition = Arc::get_mut(&mut locked.definition).expect("nothing else shares a definition that was just locked");
+        let candidates: Vec<Vec<usize>> = definition.states.iter()
+            .map(|state| locked.transitions.iter().enumerate()
+                .filter(|(_, transition)| definition.state_matches(&transition.from_state, state))
+                .map(|(index, _)| index)
+                .collect())
+            .collect();
+        let stats = DispatchStats {
+            states: candidates.len(),
+            transitions: locked.transitions.len(),
+            entries: candidates.iter().map(Vec::len).sum(),
+            elapsed: started.elapsed(),
+        };
+        definition.dispatch_index = Some(DispatchIndex {
+            candidates,
+            stats,
+        });
+        locked
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> LockedStateMachineFactory<'a, TEvent, TState, TData> {
+    /// The size and build time of the dispatch table, or `None` if the factory was locked without
+    /// [StateMachineFactory::compile].
+    pub fn dispatch_stats(&self) -> Option<&DispatchStats> {
+        self.definition.dispatch_index.as_ref().map(|index| &index.stats)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::StateMachineFactory;
+    use crate::FromState::{Any, AnyOf};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding,
+        Subtracting,
+        Done
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Add,
+        Subtract,
+        Reset
+    }
+
+    #[test]
+    fn test_compile() {
+        let factory = || StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Subtract, AnyOf(vec![States::Idle, States::Adding]), States::Subtracting)
+            .with_event_transition(&Events::Reset, Any, States::Idle);
+
+        assert!(factory().lock().dispatch_stats().is_none());
+        let compiled = factory().with_states([States::Idle, States::Adding, States::Subtracting]).compile();
+        let stats = compiled.dispatch_stats().expect("compiled");
+        assert_eq!((3, 3), (stats.states, stats.transitions));
+        assert_eq!(3 + 2 + 1, stats.entries);
+
+        // States follow the same Transitions as without the table, including unregistered ones
+        let mut sm = compiled.build(States::Idle, ());
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+        assert_eq!(&States::Subtracting, sm.handle_event(Events::Subtract).expect("unexpected error"));
+        assert_eq!(&States::Subtracting, sm.handle_event(Events::Add).expect("unexpected error"));
+        let mut sm = compiled.build(States::Done, ());
+        assert_eq!(&States::Idle, sm.handle_event(Events::Reset).expect("unexpected error"));
+    }
+}
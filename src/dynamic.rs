@@ -0,0 +1,191 @@
+//! State Machines whose States and Events are strings, for definitions loaded at runtime.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::{DefinitionError, JsonDefinition, JsonError, StateMachine, StateMachineFactory, StateMachineTransition, StateTransitionEffectData, TransitionEffect};
+
+/// A State Machine whose States and Events are strings, built from a definition known only at
+/// runtime, such as one loaded from a configuration file
+pub type DynMachine<'a, TData> = StateMachine<'a, String, String, TData>;
+
+/// A [StateMachineFactory] for [DynMachine]s, whose Transitions are added with
+/// [StateMachineFactory::with_dyn_transition]
+pub type DynMachineFactory<'a, TData> = StateMachineFactory<'a, String, String, TData>;
+
+/// Effects registered by name, for the Transitions of a [DynMachineFactory] to refer to. Effects
+/// can't be loaded from configuration, so the application registers every effect its definitions
+/// may use, and the definitions name them.
+pub struct EffectRegistry<'a, TData> {
+    effects: HashMap<String, TransitionEffect<'a, String, String, TData>>,
+}
+
+impl <TData> Default for EffectRegistry<'_, TData> {
+    fn default() -> Self {
+        Self { effects: HashMap::new() }
+    }
+}
+
+impl <'a, TData> EffectRegistry<'a, TData> {
+    /// Creates an empty `EffectRegistry`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `effect` as `name`, replacing any effect registered under the same name.
+    pub fn with_effect(mut self, name: impl Into<String>, effect: impl Fn(StateTransitionEffectData<String, String, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self {
+        self.effects.insert(name.into(), Arc::new(effect));
+        self
+    }
+}
+
+impl <'a, TData> DynMachineFactory<'a, TData> {
+    /// Adds a Transition from the State `from_state` to `to_state` on the Event `event`, running
+    /// the effect registered in `registry` as `effect`, if any. States and Events match by string
+    /// equality, and the Transition is named after its Event, so that it shows up as such in
+    /// coverage and exports. The Transition is exclusive, as with
+    /// [StateMachineFactory::with_exclusive_transition_effect], so that an Event moves the State
+    /// Machine one step, as a configured table of Transitions reads, rather than on through every
+    /// later Transition on the same Event. Otherwise it is an ordinary Transition, handled by the
+    /// same dispatch logic as any other, so a definition can combine these with Transitions added
+    /// by the other methods, such as an `Any` logger.
+    ///
+    /// This gives up the compile-time checks of enum States and Events: a misspelt State is just
+    /// one that no Transition leads to. Fails with [DefinitionError::UnknownEffect] if `effect`
+    /// isn't registered.
+    pub fn with_dyn_transition(mut self, from_state: &str, event: &str, to_state: &str, effect: Option<&str>, registry: &EffectRegistry<'a, TData>) -> Result<Self, DefinitionError<String>> {
+        let effect_name = effect;
+        let effect = effect
+            .map(|name| registry.effects.get(name).cloned().ok_or_else(|| DefinitionError::UnknownEffect(name.to_string())))
+            .transpose()?;
+        let expected = event.to_string();
+        let mut transition = StateMachineTransition::new(Some(Arc::new(move |d: &StateTransitionEffectData<String, String, TData>| *d.event == expected)), from_state.to_string().into(), to_state.to_string().into(), effect);
+        transition.name = Some(event.to_string());
+        transition.dyn_names = Some((event.to_string(), effect_name.map(str::to_string)));
+        transition.exclusive = true;
+        self.transitions.push(transition);
+        Ok(self)
+    }
+
+    /// Loads a definition serialized with [StateMachineFactory::to_json_definition], or written by
+    /// another tool in the same format, relinking effects by name. States and Events are named by
+    /// the strings in the document; for a definition exported from enum States and Events, that is
+    /// the name of each variant. Its initial state, States and metadata are restored, and:
+    /// - each `event` Transition is added with [StateMachineFactory::with_dyn_transition], running
+    ///   the effect registered in `registry` under its `label`, if `registry` has one by that name;
+    /// - each `auto` Transition is added with [StateMachineFactory::with_auto_transition].
+    ///
+    /// Predicates and calculated targets can't be serialized, so `guard` Transitions and
+    /// Transitions without a `to` State fail with [JsonError::NotLoadable], identified by their
+    /// position in the document. Fails with [JsonError::Malformed] if the document can't be read,
+    /// as for [JsonDefinition::parse]. A loaded factory exports the same document again, with the
+    /// event Transitions labelled with their effect.
+    pub fn from_json_definition(json: &str, registry: &EffectRegistry<'a, TData>) -> Result<Self, JsonError> {
+        let document = JsonDefinition::parse(json)?;
+        let mut factory = Self::new().with_states(document.states);
+        if let Some(initial) = document.initial {
+            factory = factory.with_initial(initial);
+        }
+        for (state, entries) in document.state_metadata {
+            for (key, value) in entries {
+                factory = factory.with_state_metadata(state.clone(), key, value);
+            }
+        }
+        for (position, transition) in document.transitions.into_iter().enumerate() {
+            factory = match (transition.trigger.as_str(), transition.event, transition.to) {
+                ("event", Some(event), Some(to_state)) => {
+                    let effect = transition.label.filter(|label| registry.effects.contains_key(label));
+                    factory.with_dyn_transition(&transition.from, &event, &to_state, effect.as_deref(), registry)
+                        .expect("only registered effects are named")
+                }
+                ("auto", None, Some(to_state)) => factory.with_auto_transition(transition.from, to_state),
+                _ => return Err(JsonError::NotLoadable(position))
+            };
+        }
+        Ok(factory)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Mutex;
+    use crate::{DefinitionError, DynMachineFactory, EffectRegistry, JsonError, StateMachineFactory};
+
+    #[test]
+    fn test_dyn_machine() {
+        let registry = EffectRegistry::new()
+            .with_effect("count", |d: crate::StateTransitionEffectData<String, String, Mutex<Vec<String>>>| {
+                d.data.lock().unwrap().push(format!("{} -> {}", d.from, d.to));
+                Ok(())
+            });
+        let definition = [
+            ("red", "timer", "green", Some("count")),
+            ("green", "timer", "yellow", Some("count")),
+            ("yellow", "timer", "red", Some("count")),
+            ("green", "emergency", "red", None),
+        ];
+        let mut factory = DynMachineFactory::new();
+        for (from_state, event, to_state, effect) in definition {
+            factory = factory.with_dyn_transition(from_state, event, to_state, effect, &registry).expect("unexpected error");
+        }
+        let mut sm = factory.lock().build("red".to_string(), Mutex::default());
+
+        for event in ["timer", "emergency", "timer", "timer", "emergency"] {
+            sm.handle_event(event.to_string()).expect("unexpected error");
+        }
+        assert_eq!("yellow", sm.state);
+        assert_eq!(vec!["red -> green", "red -> green", "green -> yellow"], *sm.data.lock().unwrap());
+
+        let unknown = DynMachineFactory::<Mutex<Vec<String>>>::new()
+            .with_dyn_transition("red", "timer", "green", Some("missing"), &registry);
+        assert_eq!(Some(DefinitionError::UnknownEffect("missing".to_string())), unknown.err());
+    }
+
+    #[test]
+    fn test_from_json_definition() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Red,
+            Green
+        }
+
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Timer
+        }
+
+        let exported = StateMachineFactory::<Events, States, ()>::new()
+            .with_initial(States::Red)
+            .with_event_transition(&Events::Timer, States::Red, States::Green)
+            .with_event_transition(&Events::Timer, States::Green, States::Red)
+            .with_state_metadata(States::Red, "color", "#f00");
+        // An editor links the first Transition to an effect by naming it
+        let mut document = exported.json_definition();
+        document.transitions[0].label = Some("count".to_string());
+
+        let registry = EffectRegistry::new()
+            .with_effect("count", |d: crate::StateTransitionEffectData<String, String, Mutex<Vec<String>>>| {
+                d.data.lock().unwrap().push(format!("{} -> {}", d.from, d.to));
+                Ok(())
+            });
+        let factory = DynMachineFactory::from_json_definition(&document.to_json(), &registry).expect("unexpected error");
+        // Loading and exporting again changes nothing, however often it is repeated
+        let reexported = factory.to_json_definition();
+        assert_eq!(document.to_json(), reexported);
+        let reloaded = DynMachineFactory::from_json_definition(&reexported, &registry).expect("unexpected error");
+        assert_eq!(reexported, reloaded.to_json_definition());
+
+        let mut sm = factory.lock().build_initial(Mutex::default());
+        assert_eq!("Red", sm.state);
+        for _ in 0..3 {
+            sm.handle_event("Timer".to_string()).expect("unexpected error");
+        }
+        assert_eq!("Green", sm.state);
+        assert_eq!(vec!["Red -> Green", "Red -> Green"], *sm.data.lock().unwrap());
+
+        let guarded = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Timer, States::Red, States::Green)
+            .with_predicated_transition(States::Green, States::Red, |_| true)
+            .to_json_definition();
+        assert_eq!(Some(JsonError::NotLoadable(1)), DynMachineFactory::<()>::from_json_definition(&guarded, &EffectRegistry::new()).err());
+    }
+}
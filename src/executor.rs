@@ -0,0 +1,215 @@
+//! Driving the timeouts of a [SharedStateMachine] from a task on an async executor, enabled by the
+//! `async` feature.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+use std::time::Duration;
+use crate::{SharedStateMachine, StateMachineError};
+use crate::timer::{TimerLoop, TimerStep, Wakeup, TIMER_ERROR_BACKOFF};
+
+/// A future that can be sent to an [Executor]
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// The async runtime a timer started with [SharedStateMachine::spawn_timer_on] runs on. The crate
+/// doesn't depend on any runtime, so implement this for a handle to the one the application uses:
+/// for tokio, `spawn` is `Handle::spawn` and `sleep` is `Box::pin(tokio::time::sleep(duration))`.
+///
+/// The task ticks the State Machine through [SharedStateMachine::tick], which takes its blocking
+/// write lock and holds it while the effects of any Events fired run. Meanwhile, the thread polling
+/// the task is blocked, as it is whenever the lock is held by another thread, so State Machines
+/// with long-running effects are better served by an executor whose tasks may block, or by
+/// [SharedStateMachine::spawn_timer].
+pub trait Executor: Clone + Send + Sync + 'static {
+    /// Runs `task` in the background until it completes.
+    fn spawn(&self, task: BoxFuture);
+
+    /// Returns a future resolving once `duration` has passed.
+    fn sleep(&self, duration: Duration) -> BoxFuture;
+}
+
+impl <TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Sync + Eq + PartialEq + 'static, TData> SharedStateMachine<'static, TEvent, TState, TData>
+where TEvent: Clone + Send + Sync + 'static, TData: Send + Sync + 'static
+{
+    /// Spawns a task on `executor` that calls [SharedStateMachine::tick] whenever the timeout of
+    /// the current state elapses or a scheduled Event comes due, like
+    /// [SharedStateMachine::spawn_timer] but without a thread of its own. The task sleeps with
+    /// [Executor::sleep] until the next deadline and is woken early whenever an Event is handled.
+    /// As with `spawn_timer`, errors returned by these Events are passed to `on_error`, after which
+    /// the task sleeps for at least [crate::TIMER_ERROR_BACKOFF] before ticking again.
+    ///
+    /// The task stops at its next wake-up once the returned [ExecutorTimerHandle] is dropped. Ticking
+    /// blocks the thread polling the task while it waits for and holds the State Machine's write
+    /// lock; see [Executor].
+    pub fn spawn_timer_on(&self, executor: impl Executor, on_error: impl FnMut(StateMachineError<TState>) + Send + 'static) -> ExecutorTimerHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut timer = TimerLoop::new(self.clone(), cancelled.clone(), on_error);
+        let sleeper = executor.clone();
+        executor.spawn(Box::pin(async move {
+            loop {
+                match timer.step() {
+                    TimerStep::Continue => {}
+                    TimerStep::Wait(generation, Some(timeout)) => first_of(sleeper.sleep(timeout), timer.shared.wakeup.changed(generation)).await,
+                    TimerStep::Wait(generation, None) => timer.shared.wakeup.changed(generation).await,
+                    TimerStep::Backoff => sleeper.sleep(TIMER_ERROR_BACKOFF).await,
+                    TimerStep::Stop => break
+                }
+            }
+        }));
+        ExecutorTimerHandle {
+            cancelled,
+            wakeup: self.wakeup.clone(),
+        }
+    }
+}
+
+/// Resolves once either future does
+async fn first_of(first: impl Future<Output = ()>, second: impl Future<Output = ()>) {
+    let mut first = pin!(first);
+    let mut second = pin!(second);
+    std::future::poll_fn(|cx| {
+        if first.as_mut().poll(cx).is_ready() || second.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }).await
+}
+
+/// Polls a future on the current thread until it completes
+#[cfg(test)]
+pub(crate) fn block_on<T>(future: impl Future<Output = T>) -> T {
+    use std::task::{Context, Wake, Waker};
+    use std::thread::Thread;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park()
+        }
+    }
+}
+
+/// Handle to a timer task started with [SharedStateMachine::spawn_timer_on]. Dropping it stops the
+/// task, which can't be waited for, unlike the thread of a [crate::TimerHandle].
+pub struct ExecutorTimerHandle {
+    cancelled: Arc<AtomicBool>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl Drop for ExecutorTimerHandle {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.wakeup.notify();
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Poll, Waker};
+    use std::time::{Duration, Instant};
+    use crate::StateMachineFactory;
+    use super::{block_on, BoxFuture, Executor};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Connecting,
+        Failed
+    }
+
+    #[derive(Clone, Eq, PartialEq)]
+    enum Events {
+        TimedOut
+    }
+
+    /// Runs each task on a thread of its own, and sleeps on another thread, counting the tasks
+    #[derive(Clone, Default)]
+    struct ThreadExecutor {
+        spawned: Arc<AtomicUsize>,
+    }
+
+    impl Executor for ThreadExecutor {
+        fn spawn(&self, task: BoxFuture) {
+            self.spawned.fetch_add(1, Ordering::SeqCst);
+            std::thread::spawn(move || block_on(task));
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture {
+            let waker: Arc<Mutex<Option<Waker>>> = Arc::default();
+            let done = Arc::new(Mutex::new(false));
+            let (thread_waker, thread_done) = (waker.clone(), done.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(duration);
+                *thread_done.lock().unwrap() = true;
+                if let Some(waker) = thread_waker.lock().unwrap().take() {
+                    waker.wake();
+                }
+            });
+            Box::pin(std::future::poll_fn(move |cx| {
+                let mut registered = waker.lock().unwrap();
+                if *done.lock().unwrap() {
+                    return Poll::Ready(());
+                }
+                *registered = Some(cx.waker().clone());
+                Poll::Pending
+            }))
+        }
+    }
+
+    #[test]
+    fn test_spawn_timer_on() {
+        let shared = StateMachineFactory::new()
+            .with_timeout(States::Connecting, Duration::from_millis(20), Events::TimedOut)
+            .with_event_transition(&Events::TimedOut, States::Connecting, States::Failed)
+            .lock().build(States::Connecting, ())
+            .into_shared();
+
+        let executor = ThreadExecutor::default();
+        let timer = shared.spawn_timer_on(executor.clone(), |e| panic!("unexpected error {e:?}"));
+        assert_eq!(1, executor.spawned.load(Ordering::SeqCst));
+        let started = Instant::now();
+        while shared.current_state() != States::Failed {
+            assert!(started.elapsed() < Duration::from_secs(5), "timeout never fired");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        drop(timer);
+    }
+
+    #[test]
+    fn test_spawn_timer_on_errors() {
+        let shared = StateMachineFactory::new()
+            .with_timeout(States::Connecting, Duration::from_millis(5), Events::TimedOut)
+            .with_event_transition_effect(&Events::TimedOut, States::Connecting, States::Failed, |_| Err(Box::new(std::fmt::Error)))
+            .lock().build(States::Connecting, ())
+            .into_shared();
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let timer_errors = errors.clone();
+        let timer = shared.spawn_timer_on(ThreadExecutor::default(), move |_| {
+            timer_errors.fetch_add(1, Ordering::SeqCst);
+        });
+        let started = Instant::now();
+        while errors.load(Ordering::SeqCst) == 0 {
+            assert!(started.elapsed() < Duration::from_secs(5), "timeout never fired");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // The task backs off rather than firing again as soon as the timeout elapses
+        std::thread::sleep(crate::TIMER_ERROR_BACKOFF / 2);
+        assert_eq!(1, errors.load(Ordering::SeqCst));
+        drop(timer);
+    }
+}
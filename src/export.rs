@@ -0,0 +1,414 @@
+//! Exporters rendering a definition as diagrams or tables for review.
+
+use std::fmt::{Debug, Write};
+use std::iter::once;
+use crate::{StateMachineFactory, Trigger, TransitionVisitor};
+use crate::Target::{Calc, Same, To};
+
+/// Label given to edges of guarded Transitions that have no name
+pub(crate) const GUARD_LABEL: &str = "pred";
+
+/// The label of an edge, or `None` for an unlabelled edge
+fn edge_label<TEvent: Debug>(trigger: Trigger<'_, TEvent>, label: Option<&str>) -> Option<String> {
+    match (label, trigger) {
+        (Some(label), _) => Some(label.to_string()),
+        (None, Trigger::Event(event)) => Some(format!("{:?}", event)),
+        (None, Trigger::Guard) => Some(GUARD_LABEL.to_string()),
+        (None, Trigger::Auto) => None
+    }
+}
+
+/// Writes a definition in the Graphviz DOT language
+struct DotExporter<'m, TState> {
+    output: String,
+    /// The metadata of the States, written as node attributes
+    metadata: &'m [(TState, Vec<(String, String)>)],
+}
+
+impl <TState> DotExporter<'_, TState> {
+    fn node(state: &impl Debug) -> String {
+        quoted(&format!("{:?}", state))
+    }
+}
+
+/// Quotes and escapes a string for DOT, writing newlines as DOT line breaks
+fn quoted(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("\r\n", "\\n")
+        .replace(['\n', '\r'], "\\n");
+    format!("\"{}\"", escaped)
+}
+
+impl <TEvent: Debug, TState: Debug + PartialEq> TransitionVisitor<TEvent, TState> for DotExporter<'_, TState> {
+    fn visit_state(&mut self, state: &TState) {
+        let attributes: Vec<String> = self.metadata.iter()
+            .filter(|(annotated, _)| annotated == state)
+            .flat_map(|(_, entries)| entries.iter().map(|(key, value)| format!("{}={}", quoted(key), quoted(value))))
+            .collect();
+        let _ = match attributes.len() {
+            0 => writeln!(self.output, "    {};", Self::node(state)),
+            _ => writeln!(self.output, "    {} [{}];", Self::node(state), attributes.join(", "))
+        };
+    }
+
+    fn visit_transition(&mut self, from: &TState, trigger: Trigger<'_, TEvent>, to: Option<&TState>, label: Option<&str>) {
+        let Some(to) = to else { return };
+        let _ = match edge_label(trigger, label) {
+            Some(label) => writeln!(self.output, "    {} -> {} [label={}];", Self::node(from), Self::node(to), quoted(&label)),
+            None => writeln!(self.output, "    {} -> {};", Self::node(from), Self::node(to))
+        };
+    }
+}
+
+/// Writes a definition as a Mermaid state diagram
+#[derive(Default)]
+struct MermaidExporter {
+    output: String,
+    /// The identifier given to each State, by its [Debug] representation
+    ids: Vec<(String, String)>,
+}
+
+impl MermaidExporter {
+    /// Mermaid state identifiers may only contain word characters, so other characters are
+    /// replaced with underscores, and States that would end up with the same identifier are told
+    /// apart with a numbered suffix
+    fn id(&mut self, state: &impl Debug) -> String {
+        let name = format!("{:?}", state);
+        if let Some((_, id)) = self.ids.iter().find(|(named, _)| *named == name) {
+            return id.clone();
+        }
+        let base: String = name.chars()
+            .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
+            .collect();
+        let mut id = base.clone();
+        let mut suffix = 1;
+        while self.ids.iter().any(|(_, taken)| *taken == id) {
+            suffix += 1;
+            id = format!("{}_{}", base, suffix);
+        }
+        self.ids.push((name, id.clone()));
+        id
+    }
+
+    /// Mermaid ends a label at a newline and reads `:`, `--` and `#` as syntax, so these are
+    /// written as entity codes, and newlines as spaces
+    fn label(label: &str) -> String {
+        label.replace('#', "#35;")
+            .replace(':', "#58;")
+            .replace("--", "#45;#45;")
+            .replace("\r\n", " ")
+            .replace(['\n', '\r'], " ")
+    }
+}
+
+impl <TEvent: Debug, TState: Debug> TransitionVisitor<TEvent, TState> for MermaidExporter {
+    fn visit_state(&mut self, state: &TState) {
+        let id = self.id(state);
+        let _ = writeln!(self.output, "    {}", id);
+    }
+
+    fn visit_transition(&mut self, from: &TState, trigger: Trigger<'_, TEvent>, to: Option<&TState>, label: Option<&str>) {
+        let Some(to) = to else { return };
+        let (from, to) = (self.id(from), self.id(to));
+        let _ = match edge_label(trigger, label) {
+            Some(label) => writeln!(self.output, "    {} --> {} : {}", from, to, Self::label(&label)),
+            None => writeln!(self.output, "    {} --> {}", from, to)
+        };
+    }
+}
+
+/// Quotes and escapes a string for JSON
+pub(crate) fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => { let _ = write!(quoted, "\\u{:04x}", c as u32); }
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl <'a, TEvent: Debug, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Renders the definition as a Graphviz DOT digraph. States and Events are named by their
+    /// [Debug] representation; edges of event Transitions are labelled with their Event and edges
+    /// of guarded Transitions with the name of their predicate, or "pred" if it has none.
+    /// Transitions with a calculated target are left out, and the initial state recorded with
+    /// [StateMachineFactory::with_initial] is marked with an arrow from a point. Metadata attached
+    /// with [StateMachineFactory::with_state_metadata] is written as attributes of the State's
+    /// node. Names and labels are quoted, with quotes and backslashes escaped and newlines written
+    /// as line breaks.
+    pub fn to_dot(&self) -> String {
+        let mut exporter = DotExporter {
+            output: String::from("digraph {\n"),
+            metadata: &self.definition.state_metadata,
+        };
+        if let Some(initial_state) = &self.initial_state {
+            let _ = writeln!(exporter.output, "    __start [shape=point];");
+            let _ = writeln!(exporter.output, "    __start -> {};", DotExporter::<TState>::node(initial_state));
+        }
+        self.visit(&mut exporter);
+        exporter.output.push_str("}\n");
+        exporter.output
+    }
+
+    /// Renders the definition as a Mermaid state diagram, labelled in the same way as
+    /// [StateMachineFactory::to_dot]. Characters of the States' [Debug] representation that
+    /// Mermaid doesn't allow in identifiers are replaced with underscores, with a numbered suffix
+    /// such as `_2` for States that would otherwise share an identifier, and `#`, `:` and `--` in
+    /// labels are written as entity codes.
+    pub fn to_mermaid(&self) -> String {
+        let mut exporter = MermaidExporter {
+            output: String::from("stateDiagram-v2\n"),
+            ..MermaidExporter::default()
+        };
+        if let Some(initial_state) = &self.initial_state {
+            let id = exporter.id(initial_state);
+            let _ = writeln!(exporter.output, "    [*] --> {}", id);
+        }
+        self.visit(&mut exporter);
+        exporter.output
+    }
+
+    /// Serializes the structure of the definition to JSON, for tools that render or diff State
+    /// Machine definitions. Effects, predicates and calculated targets can't be serialized, so only
+    /// their presence is recorded. The document has the following fields:
+    /// - `initial`: the state recorded with [StateMachineFactory::with_initial], or `null`.
+    /// - `states`: every known State.
+    /// - `state_metadata`: only if any State has metadata attached with
+    ///   [StateMachineFactory::with_state_metadata], an object with the metadata of each of those
+    ///   States as an object of its keys and values.
+    /// - `events`: every Event named by an event Transition, in the order they were first named.
+    /// - `transitions`: one entry for each Transition and State it can be taken from, as visited by
+    ///   [StateMachineFactory::visit], with its `from` and `to` States (`to` is `null` when
+    ///   calculated), its `trigger` kind (`event`, `guard` or `auto`), its `event` (or `null`) and
+    ///   its `label` (or `null`).
+    ///
+    /// States and Events are named by their [Debug] representation, and strings by their value.
+    pub fn to_json_definition(&self) -> String {
+        self.json_definition().to_json()
+    }
+
+    /// Renders the definition as a table with a row for every known State and a column for each
+    /// of the provided Events, formatted as a Markdown table. Each cell shows the State the machine
+    /// moves into when it receives that Event in that State, following the first state-changing
+    /// Transition that could apply, as [crate::StateMachine::handle_event] would:
+    /// - `-` when no Transition changes the State.
+    /// - `ignored` when the Event is ignored (see [StateMachineFactory::with_ignored_event]).
+    /// - The target State, followed by `?` when the Transition is guarded by a predicate, as
+    ///   whether it applies can only be decided at runtime.
+    /// - `?` when the target State is calculated.
+    ///
+    /// Transitions that keep the machine in the same State, such as loggers, aren't shown.
+    pub fn to_matrix(&self, events: &[TEvent]) -> String {
+        let header: Vec<String> = once(String::new())
+            .chain(events.iter().map(|event| format!("{:?}", event)))
+            .collect();
+        let rows: Vec<Vec<String>> = self.known_states().iter()
+            .map(|state| once(format!("{:?}", state))
+                .chain(events.iter().map(|event| self.matrix_cell(state, event)))
+                .collect())
+            .collect();
+
+        let widths: Vec<usize> = (0..header.len())
+            .map(|column| once(&header).chain(&rows).map(|row| row[column].chars().count()).max().unwrap_or(0))
+            .collect();
+        let format_row = |row: &[String]| {
+            let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!(" {:width$} ", cell)).collect();
+            format!("|{}|\n", cells.join("|"))
+        };
+
+        let mut output = format_row(&header);
+        let separators: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+        let _ = writeln!(output, "|{}|", separators.join("|"));
+        for row in &rows {
+            output.push_str(&format_row(row));
+        }
+        output
+    }
+
+    /// The cell of [StateMachineFactory::to_matrix] for an Event received in a State
+    fn matrix_cell(&self, state: &TState, event: &TEvent) -> String {
+        for transition in &self.transitions {
+            if !transition.from_state.matches(state) {
+                continue;
+            }
+            if let Some(expected) = transition.event {
+                if !self.definition.event_matches(expected, event) {
+                    continue;
+                }
+            }
+            if transition.ignored {
+                return "ignored".to_string();
+            }
+            match &transition.get_to_state {
+                Same => {}
+                To(to_state) if to_state == state => {}
+                To(to_state) if transition.event_predicate.is_some() => return format!("{:?}?", to_state),
+                To(to_state) => return format!("{:?}", to_state),
+                Calc(_) => return "?".to_string()
+            }
+        }
+        "-".to_string()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::StateMachineFactory;
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    enum Events {
+        Add,
+        Equals,
+        Clear,
+        Say(String)
+    }
+
+    #[test]
+    fn test_exports() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_initial(States::Idle)
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .with_predicated_transition(States::Adding, States::Adding, |_| true);
+
+        assert_eq!("digraph {
+    __start [shape=point];
+    __start -> \"Idle\";
+    \"Idle\";
+    \"Adding\";
+    \"Idle\" -> \"Adding\" [label=\"Add\"];
+    \"Adding\" -> \"Idle\" [label=\"Equals\"];
+    \"Adding\" -> \"Adding\" [label=\"pred\"];
+}
+", factory.to_dot());
+
+        assert_eq!("stateDiagram-v2
+    [*] --> Idle
+    Idle
+    Adding
+    Idle --> Adding : Add
+    Adding --> Idle : Equals
+    Adding --> Adding : pred
+", factory.to_mermaid());
+    }
+
+    #[test]
+    fn test_named_predicate() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_named_predicated_transition_effect("is_equals", States::Adding, States::Idle, |d| matches!(d.event, Events::Equals), |_| Ok(()))
+            .with_predicated_transition(States::Idle, States::Adding, |d| matches!(d.event, Events::Add));
+
+        assert_eq!("stateDiagram-v2
+    Adding
+    Idle
+    Adding --> Idle : is_equals
+    Idle --> Adding : pred
+", factory.to_mermaid());
+    }
+
+    #[test]
+    fn test_export_escaping() {
+        let say = Events::Say("a\\b:c--d#".to_string());
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&say, States::Idle, States::Adding)
+            .with_named_predicated_transition_effect("first\nsecond", States::Adding, States::Idle, |_| true, |_| Ok(()))
+            .with_state_metadata(States::Idle, "tooltip", "say \"hi\"\\");
+
+        assert_eq!(r#"digraph {
+    "Idle" ["tooltip"="say \"hi\"\\"];
+    "Adding";
+    "Idle" -> "Adding" [label="Say(\"a\\\\b:c--d#\")"];
+    "Adding" -> "Idle" [label="first\nsecond"];
+}
+"#, factory.to_dot());
+
+        assert_eq!(r#"stateDiagram-v2
+    Idle
+    Adding
+    Idle --> Adding : Say("a\\b#58;c#45;#45;d#35;")
+    Adding --> Idle : first second
+"#, factory.to_mermaid());
+    }
+
+    #[test]
+    fn test_mermaid_ids() {
+        let factory = StateMachineFactory::<&str, &str, ()>::new()
+            .with_initial("A(1)")
+            .with_event_transition(&"go", "A(1)", "A_1_")
+            .with_event_transition(&"back", "A_1_", "A(1)");
+
+        assert_eq!(r#"stateDiagram-v2
+    [*] --> _A_1__
+    _A_1__
+    _A_1___2
+    _A_1__ --> _A_1___2 : "go"
+    _A_1___2 --> _A_1__ : "back"
+"#, factory.to_mermaid());
+    }
+
+    #[test]
+    fn test_to_matrix() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_predicated_transition(States::Adding, States::Idle, |d| matches!(d.event, Events::Equals))
+            .with_event_transition(&Events::Clear, Any, States::Idle)
+            .with_transition_effect(Any, Same, |_| Ok(()));
+
+        assert_eq!("\
+|        | Add    | Equals | Clear |
+|--------|--------|--------|-------|
+| Idle   | Adding | -      | -     |
+| Adding | Idle?  | Idle?  | Idle? |
+", factory.to_matrix(&[Events::Add, Events::Equals, Events::Clear]));
+    }
+
+    #[test]
+    fn test_to_json_definition() {
+        let definition = |message: &'static str| StateMachineFactory::<Events, States, ()>::new()
+            .with_initial(States::Idle)
+            .with_event_transition_effect(&Events::Add, States::Idle, States::Adding, move |_| {
+                let _ = message;
+                Ok(())
+            })
+            .with_predicated_transition(States::Adding, States::Idle, |d| matches!(d.event, Events::Equals));
+
+        let json = definition("one").to_json_definition();
+        assert_eq!(r#"{
+  "initial": "Idle",
+  "states": [
+    "Idle",
+    "Adding"
+  ],
+  "events": [
+    "Add"
+  ],
+  "transitions": [
+    {"from": "Idle", "trigger": "event", "event": "Add", "to": "Adding", "label": null},
+    {"from": "Adding", "trigger": "guard", "event": null, "to": "Idle", "label": null}
+  ]
+}
+"#, json);
+
+        // Only the structure is serialized, so definitions that differ in their closures alone
+        // produce the same document
+        assert_eq!(json, definition("two").to_json_definition());
+        assert!(StateMachineFactory::<Events, States, ()>::new().to_json_definition().contains("\"states\": []"));
+    }
+}
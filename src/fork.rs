@@ -0,0 +1,146 @@
+//! Forking a factory into variants that share its closures.
+
+use std::fmt::Debug;
+use crate::{Definition, EntryEffect, StateMachineFactory, StateMachineTransition};
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Copies this factory, with everything registered so far, so that a variant of the definition
+    /// can be derived from it without registering everything again, for example to A/B test a
+    /// behaviour or to customize a definition per tenant. Transitions and other settings can then
+    /// be added to either factory without affecting the other. Effects, predicates and other
+    /// closures aren't copied: both factories share them, so capturing state in a closure shares it
+    /// between every State Machine built from either factory.
+    pub fn fork(&self) -> Self
+    where TEvent: Clone
+    {
+        Self {
+            cycle: self.cycle,
+            transitions: self.transitions.clone(),
+            definition: self.definition.clone(),
+            initial_state: self.initial_state.clone(),
+            strict: self.strict,
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Clone for StateMachineTransition<'a, TEvent, TState, TData> {
+    fn clone(&self) -> Self {
+        Self {
+            from_state: self.from_state.clone(),
+            get_to_state: self.get_to_state.clone(),
+            event_predicate: self.event_predicate.clone(),
+            effect: self.effect.clone(),
+            else_effect: self.else_effect.clone(),
+            event: self.event,
+            exclusive: self.exclusive,
+            consuming: self.consuming,
+            ignored: self.ignored,
+            cross_cutting: self.cross_cutting,
+            side_effecting: self.side_effecting,
+            name: self.name.clone(),
+            dyn_names: self.dyn_names.clone(),
+            effect_id: self.effect_id.clone(),
+            after: self.after.clone(),
+            overrides: self.overrides.clone(),
+            shadowed_by: self.shadowed_by.clone(),
+            fallback: self.fallback,
+            required_count: self.required_count,
+            privilege: self.privilege.clone(),
+            #[cfg(feature = "async")]
+            async_guard: self.async_guard.clone(),
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Clone for EntryEffect<'a, TEvent, TState, TData> {
+    fn clone(&self) -> Self {
+        Self {
+            to_state: self.to_state.clone(),
+            from_state: self.from_state.clone(),
+            effect: self.effect.clone(),
+        }
+    }
+}
+
+impl <'a, TEvent: Clone, TState: PartialEq<TState> + Clone + Send + 'a, TData> Clone for Definition<'a, TEvent, TState, TData> {
+    /// Copies the definition of a factory that hasn't been compiled, so without a dispatch index
+    fn clone(&self) -> Self {
+        Self {
+            states: self.states.clone(),
+            initial_substates: self.initial_substates.clone(),
+            parent_states: self.parent_states.clone(),
+            exclusive_groups: self.exclusive_groups.clone(),
+            entry_effects: self.entry_effects.clone(),
+            transit_effects: self.transit_effects.clone(),
+            region_effects: self.region_effects.clone(),
+            pre_hooks: self.pre_hooks.clone(),
+            listeners: self.listeners.clone(),
+            transition_loggers: self.transition_loggers.clone(),
+            invariants: self.invariants.clone(),
+            before_dispatch: self.before_dispatch.clone(),
+            after_dispatch: self.after_dispatch.clone(),
+            event_key: self.event_key.clone(),
+            event_eq: self.event_eq.clone(),
+            event_clone: self.event_clone.clone(),
+            record_last_event: self.record_last_event,
+            #[cfg(feature = "binary-log")]
+            record_event_log: self.record_event_log,
+            deferred_events: self.deferred_events.clone(),
+            state_key: self.state_key.clone(),
+            guard_budget: self.guard_budget,
+            queue_limit: self.queue_limit,
+            self_transition_limit: self.self_transition_limit,
+            max_transitions: self.max_transitions,
+            clock: self.clock.clone(),
+            timeouts: self.timeouts.clone(),
+            lazy_timeouts: self.lazy_timeouts,
+            inactivity_timeout: self.inactivity_timeout.clone(),
+            dispatch_index: None,
+            guard_cache: self.guard_cache,
+            guard_cache_ttl: self.guard_cache_ttl,
+            has_fallbacks: self.has_fallbacks,
+            state_metadata: self.state_metadata.clone(),
+            #[cfg(feature = "tracing")]
+            event_format: self.event_format.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::StateMachineFactory;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding,
+        Subtracting
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Add,
+        Subtract,
+        Equals
+    }
+
+    #[test]
+    fn test_fork() {
+        let base = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle);
+        let variant = base.fork()
+            .with_event_transition(&Events::Subtract, States::Idle, States::Subtracting)
+            .lock();
+        let base = base.lock();
+
+        let mut sm = variant.build(States::Idle, ());
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+        assert_eq!(&States::Idle, sm.handle_event(Events::Equals).expect("unexpected error"));
+        assert_eq!(&States::Subtracting, sm.handle_event(Events::Subtract).expect("unexpected error"));
+
+        let mut sm = base.build(States::Idle, ());
+        assert_eq!(&States::Idle, sm.handle_event(Events::Subtract).expect("unexpected error"));
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+    }
+}
@@ -0,0 +1,151 @@
+//! Groups of Transitions of which exactly one has to be taken, for critical decision points.
+
+use std::fmt::Debug;
+use crate::{Definition, DefinitionError, StateMachine, StateMachineError, StateMachineFactory, StateMachineTransition, StateTransitionEffectData};
+use crate::order::identified;
+use crate::queue::DispatchContext;
+
+/// Transitions declared with [StateMachineFactory::exclusive_group]
+#[derive(Clone)]
+pub(crate) struct ExclusiveGroup {
+    /// The effect ids of the Transitions in the group, as declared
+    ids: Vec<String>,
+    /// The positions of the Transitions in the group, once the factory is locked
+    positions: Vec<usize>,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Declares that exactly one of the Transitions identified with
+    /// [StateMachineFactory::effect_id] as `ids` applies whenever their State and Event do, as
+    /// for a decision between `approve`, `reject` and `escalate`, whose guards are meant to cover
+    /// every case without overlapping. Guards can't be compared statically, so this is checked as
+    /// Events are handled: before an Event is dispatched from a State the group's Transitions
+    /// start from, their guards are evaluated, and handling fails with
+    /// [StateMachineError::ExclusiveGroupViolated] unless exactly one passed, before any effect
+    /// runs. Transitions without a guard always pass.
+    ///
+    /// Locking fails with [DefinitionError::UnknownEffectId] if no Transition is identified as one
+    /// of `ids`, and with [DefinitionError::InconsistentExclusiveGroup] if the Transitions don't
+    /// all start from the same States on the same Event.
+    pub fn exclusive_group(mut self, ids: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.definition.exclusive_groups.push(ExclusiveGroup {
+            ids: ids.into_iter().map(Into::into).collect(),
+            positions: Vec::new(),
+        });
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + 'a, TData> Definition<'a, TEvent, TState, TData> {
+    /// Finds the Transitions of each exclusive group in the locked order of `transitions`, and
+    /// checks that they share their State and Event
+    pub(crate) fn resolve_exclusive_groups(&mut self, transitions: &[StateMachineTransition<'a, TEvent, TState, TData>]) -> Result<(), DefinitionError<TState>> {
+        let mut groups = std::mem::take(&mut self.exclusive_groups);
+        for group in &mut groups {
+            group.positions.clear();
+            for id in &group.ids {
+                group.positions.extend(identified(transitions, id)?);
+            }
+            // An empty group has nothing to check
+            let Some(&first) = group.positions.first() else {
+                continue;
+            };
+            let first = &transitions[first];
+            let consistent = group.positions.iter().all(|&position| {
+                let transition = &transitions[position];
+                transition.from_state == first.from_state && match (transition.event, first.event) {
+                    (Some(event), Some(expected)) => self.event_matches(expected, event),
+                    (None, None) => true,
+                    _ => false
+                }
+            });
+            if !consistent {
+                return Err(DefinitionError::InconsistentExclusiveGroup(group.ids.clone()));
+            }
+        }
+        self.exclusive_groups = groups;
+        Ok(())
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Checks that exactly one guard passes in each exclusive group applying to `event` in the
+    /// current state
+    pub(crate) fn check_exclusive_groups(&self, event: &TEvent, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        for group in &self.definition.exclusive_groups {
+            let Some(&first) = group.positions.first() else {
+                continue;
+            };
+            let first = &self.transitions[first];
+            if first.event.is_some_and(|expected| !self.definition.event_matches(expected, event))
+                || !self.definition.state_matches(&first.from_state, &self.state) {
+                continue;
+            }
+            let passed = group.positions.iter()
+                .filter(|&&position| {
+                    let transition = &self.transitions[position];
+                    let Some(predicate) = &transition.event_predicate else {
+                        return true;
+                    };
+                    let to_state = transition.to_state(event, &self.state, &self.data);
+                    context.in_guard.set(true);
+                    let passed = predicate(&StateTransitionEffectData {
+                        data: &self.data,
+                        event,
+                        from: &self.state,
+                        to: &to_state,
+                        context
+                    });
+                    context.in_guard.set(false);
+                    passed
+                })
+                .count();
+            if passed != 1 {
+                return Err(StateMachineError::ExclusiveGroupViolated(self.state.clone(), group.ids.clone(), passed));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{DefinitionError, StateMachineError, StateMachineFactory};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Pending,
+        Approved,
+        Rejected
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Review
+    }
+
+    #[test]
+    fn test_exclusive_group() {
+        // Amounts of exactly 100 slip through both guards
+        let factory = StateMachineFactory::<Events, States, u32>::new()
+            .with_predicated_transition_effect(States::Pending, States::Approved, |d| *d.data <= 100, |_| Ok(()))
+            .effect_id("approve")
+            .with_predicated_transition_effect(States::Pending, States::Rejected, |d| *d.data >= 100, |_| Ok(()))
+            .effect_id("reject")
+            .exclusive_group(["approve", "reject"])
+            .lock();
+
+        let mut sm = factory.build(States::Pending, 50);
+        assert_eq!(&States::Approved, sm.handle_event(Events::Review).expect("unexpected error"));
+        let mut sm = factory.build(States::Pending, 100);
+        let error = sm.handle_event(Events::Review).expect_err("both guards pass");
+        assert!(matches!(error, StateMachineError::ExclusiveGroupViolated(States::Pending, _, 2)));
+        assert_eq!(States::Pending, sm.state);
+
+        let inconsistent = StateMachineFactory::<Events, States, u32>::new()
+            .with_event_transition(&Events::Review, States::Pending, States::Approved).effect_id("approve")
+            .with_event_transition(&Events::Review, States::Approved, States::Rejected).effect_id("reject")
+            .exclusive_group(["approve", "reject"]);
+        assert_eq!(Some(DefinitionError::InconsistentExclusiveGroup(vec!["approve".to_string(), "reject".to_string()])), inconsistent.try_lock().err());
+    }
+}
@@ -0,0 +1,251 @@
+//! Guard expressions combining named predicates, for Transitions with complex admission rules.
+
+use std::any::Any;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Not;
+use std::sync::Arc;
+use crate::{EventPredicate, FromState, StateMachine, StateMachineError, StateMachineFactory, StateMachineTransition, StateTransitionEffectData, ToState, TransitionEffect};
+use crate::queue::DispatchContext;
+use crate::export::GUARD_LABEL;
+
+/// A guard built from predicates with [Guard::all_of], [Guard::any_of] and `!`, such as
+/// `Guard::all_of(vec![Guard::any_of(vec![is_member, is_invited]), is_open])`. Groups are evaluated
+/// in order and short-circuit like `&&` and `||`, so a predicate is only evaluated when its result
+/// can still make a difference.
+///
+/// A guard is displayed as its expression, such as `(is_member or is_invited) and is_open`, which
+/// is also how Transitions guarded by it are labelled in exports. Predicates without a name are
+/// displayed as `pred`.
+pub struct Guard<'a, TEvent, TState, TData> {
+    kind: GuardKind<'a, TEvent, TState, TData>,
+}
+
+enum GuardKind<'a, TEvent, TState, TData> {
+    Predicate(Option<String>, EventPredicate<'a, TEvent, TState, TData>),
+    AllOf(Vec<Guard<'a, TEvent, TState, TData>>),
+    AnyOf(Vec<Guard<'a, TEvent, TState, TData>>),
+    Not(Box<Guard<'a, TEvent, TState, TData>>),
+}
+
+impl <'a, TEvent, TState, TData> Guard<'a, TEvent, TState, TData> {
+    /// A guard made of one predicate.
+    pub fn new(predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a) -> Self {
+        Self {
+            kind: GuardKind::Predicate(None, Arc::new(predicate)),
+        }
+    }
+
+    /// A guard made of one predicate, displayed as `name`.
+    pub fn named(name: impl Into<String>, predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a) -> Self {
+        Self {
+            kind: GuardKind::Predicate(Some(name.into()), Arc::new(predicate)),
+        }
+    }
+
+    /// A guard passing when all of `guards` pass, which an empty group always does.
+    pub fn all_of(guards: Vec<Self>) -> Self {
+        Self {
+            kind: GuardKind::AllOf(guards),
+        }
+    }
+
+    /// A guard passing when any of `guards` passes, which an empty group never does.
+    pub fn any_of(guards: Vec<Self>) -> Self {
+        Self {
+            kind: GuardKind::AnyOf(guards),
+        }
+    }
+
+    /// Evaluates the guard for a Transition.
+    pub fn evaluate(&self, data: &StateTransitionEffectData<TEvent, TState, TData>) -> bool {
+        match &self.kind {
+            GuardKind::Predicate(_, predicate) => predicate(data),
+            GuardKind::AllOf(guards) => guards.iter().all(|guard| guard.evaluate(data)),
+            GuardKind::AnyOf(guards) => guards.iter().any(|guard| guard.evaluate(data)),
+            GuardKind::Not(guard) => !guard.evaluate(data)
+        }
+    }
+
+    /// Writes the guard, in parentheses if it is a group with more than one guard
+    fn fmt_operand(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            GuardKind::AllOf(guards) | GuardKind::AnyOf(guards) if guards.len() > 1 => write!(f, "({})", self),
+            _ => write!(f, "{}", self)
+        }
+    }
+}
+
+impl <TEvent, TState, TData> Display for Guard<'_, TEvent, TState, TData> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (guards, operator, empty) = match &self.kind {
+            GuardKind::Predicate(name, _) => return f.write_str(name.as_deref().unwrap_or(GUARD_LABEL)),
+            GuardKind::Not(guard) => {
+                f.write_str("not ")?;
+                return guard.fmt_operand(f);
+            }
+            GuardKind::AllOf(guards) => (guards, " and ", "true"),
+            GuardKind::AnyOf(guards) => (guards, " or ", "false")
+        };
+        if guards.is_empty() {
+            return f.write_str(empty);
+        }
+        for (index, guard) in guards.iter().enumerate() {
+            if index > 0 {
+                f.write_str(operator)?;
+            }
+            guard.fmt_operand(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl <TEvent, TState, TData> Not for Guard<'_, TEvent, TState, TData> {
+    type Output = Self;
+
+    /// A guard passing when this one fails, as in `!is_banned`.
+    fn not(self) -> Self {
+        Self {
+            kind: GuardKind::Not(Box::new(self)),
+        }
+    }
+}
+
+impl <TEvent, TState, TData> Debug for Guard<'_, TEvent, TState, TData> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Guard({})", self)
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData: 'a> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Adds a Transition guarded by a [Guard] expression, like
+    /// `.with_predicated_transition(..)`. The Transition is named after the expression.
+    pub fn with_guarded_transition(self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, guard: Guard<'a, TEvent, TState, TData>) -> Self
+    {
+        self.push_guarded_transition(from_state.into(), get_to_state.into(), guard, None)
+    }
+
+    /// Adds a Transition guarded by a [Guard] expression with a Side Effect, like
+    /// `.with_predicated_transition_effect(..)`. The Transition is named after the expression.
+    pub fn with_guarded_transition_effect(self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, guard: Guard<'a, TEvent, TState, TData>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.push_guarded_transition(from_state.into(), get_to_state.into(), guard, Some(Arc::new(effect)))
+    }
+
+    fn push_guarded_transition(mut self, from_state: FromState<TState>, get_to_state: ToState<TEvent, TState, TData>, guard: Guard<'a, TEvent, TState, TData>, effect: Option<TransitionEffect<'a, TEvent, TState, TData>>) -> Self
+    {
+        let name = guard.to_string();
+        let mut transition = StateMachineTransition::new(Some(Arc::new(move |d| guard.evaluate(d))), from_state, get_to_state, effect);
+        transition.name = Some(name);
+        self.transitions.push(transition);
+        self
+    }
+}
+
+impl <TEvent, TState, TData> StateTransitionEffectData<'_, TEvent, TState, TData> {
+    /// The guard context passed to [StateMachine::handle_event_with_guard_ctx], if it is a `T`.
+    /// Only predicates see it: this returns `None` in effects, listeners and everywhere else, as
+    /// well as for Events passed to the other ways of handling an Event.
+    pub fn guard_ctx<T: Any>(&self) -> Option<&T> {
+        if !self.context.in_guard.get() {
+            return None;
+        }
+        self.context.guard_ctx.as_ref()?.downcast_ref()
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Handles an Event like [StateMachine::handle_event], making `ctx` available to predicates
+    /// through [StateTransitionEffectData::guard_ctx], for decisions that depend on the caller
+    /// rather than on the State Machine, such as the permissions of the current user. The context
+    /// is visible to the predicates evaluated for the Event and the Events it enqueues, and is
+    /// dropped once the Event has been handled, so it never ends up in the data.
+    pub fn handle_event_with_guard_ctx<TContext: Any + Send>(&mut self, event: TEvent, ctx: TContext) -> Result<&TState, StateMachineError<TState>> {
+        let mut context = DispatchContext::new();
+        context.guard_ctx = Some(Box::new(ctx));
+        self.handle_external_event_in(&event, context)?;
+        Ok(&self.state)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::{Guard, StateMachineFactory};
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Queued,
+        Admitted
+    }
+
+    #[derive(Debug)]
+    struct Visitor {
+        member: bool,
+        invited: bool,
+        age: u32
+    }
+
+    #[test]
+    fn test_guarded_transition() {
+        let evaluated = AtomicUsize::new(0);
+        let counted = |name: &'static str, predicate: fn(&Visitor) -> bool| {
+            let evaluated = &evaluated;
+            Guard::named(name, move |d: &crate::StateTransitionEffectData<Visitor, States, ()>| {
+                evaluated.fetch_add(1, Ordering::SeqCst);
+                predicate(d.event)
+            })
+        };
+        let factory = StateMachineFactory::<Visitor, States, ()>::new()
+            .with_guarded_transition(States::Queued, States::Admitted, Guard::all_of(vec![
+                Guard::any_of(vec![counted("is_member", |v| v.member), counted("is_invited", |v| v.invited)]),
+                !counted("is_minor", |v| v.age < 18)
+            ]));
+        assert!(factory.to_mermaid().contains("Queued --> Admitted : (is_member or is_invited) and not is_minor"), "{}", factory.to_mermaid());
+        let factory = factory.lock();
+
+        // A member short-circuits the invitation check
+        let mut sm = factory.build(States::Queued, ());
+        assert_eq!(&States::Admitted, sm.handle_event(Visitor { member: true, invited: false, age: 30 }).expect("unexpected error"));
+        assert_eq!(2, evaluated.swap(0, Ordering::SeqCst));
+
+        // Failing the first group short-circuits the age check
+        let mut sm = factory.build(States::Queued, ());
+        assert_eq!(&States::Queued, sm.handle_event(Visitor { member: false, invited: false, age: 30 }).expect("unexpected error"));
+        assert_eq!(2, evaluated.swap(0, Ordering::SeqCst));
+        assert_eq!(&States::Queued, sm.handle_event(Visitor { member: false, invited: true, age: 12 }).expect("unexpected error"));
+        assert_eq!(3, evaluated.swap(0, Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_guard_ctx() {
+        struct Permissions {
+            can_publish: bool
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Publish
+        }
+
+        let effect_saw_ctx = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, &str, ()>::new()
+            .with_predicated_transition_effect("draft", "published", |d| {
+                d.guard_ctx::<Permissions>().is_some_and(|permissions| permissions.can_publish)
+            }, |d| {
+                if d.guard_ctx::<Permissions>().is_some() {
+                    effect_saw_ctx.fetch_add(1, Ordering::SeqCst);
+                }
+                Ok(())
+            })
+            .with_transition_effect("draft", Same, |_| Ok(()))
+            .lock();
+
+        let mut sm = factory.build("draft", ());
+        assert_eq!(&"draft", sm.handle_event_with_guard_ctx(Events::Publish, Permissions { can_publish: false }).expect("unexpected error"));
+        assert_eq!(&"draft", sm.handle_event(Events::Publish).expect("unexpected error"));
+        assert_eq!(&"draft", sm.handle_event_with_guard_ctx(Events::Publish, "not permissions").expect("unexpected error"));
+        assert_eq!(&"published", sm.handle_event_with_guard_ctx(Events::Publish, Permissions { can_publish: true }).expect("unexpected error"));
+        assert_eq!(0, effect_saw_ctx.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,179 @@
+//! Opt-in memoization of predicates for [StateMachine::accepted_events].
+
+use std::fmt::Debug;
+use std::mem::{discriminant, Discriminant};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use crate::{StateMachine, StateMachineFactory};
+
+/// The cached outcomes of predicates of one State Machine. Cloning a State Machine gives the clone
+/// an empty cache. The cache is boxed, as most State Machines never use it.
+pub(crate) struct GuardCache<TEvent, TState> {
+    cached: Box<Mutex<CachedGuards<TEvent, TState>>>,
+}
+
+impl <TEvent, TState> Default for GuardCache<TEvent, TState> {
+    fn default() -> Self {
+        Self {
+            cached: Box::new(Mutex::new(CachedGuards::default())),
+        }
+    }
+}
+
+impl <TEvent, TState> Clone for GuardCache<TEvent, TState> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl <TEvent, TState> GuardCache<TEvent, TState> {
+    fn lock(&self) -> MutexGuard<'_, CachedGuards<TEvent, TState>> {
+        self.cached.lock().unwrap()
+    }
+}
+
+/// The outcomes of predicates since the cache was last cleared, all from the same state
+struct CachedGuards<TEvent, TState> {
+    state: Option<TState>,
+    /// For each Transition by position, whether its predicate passed for each Event variant seen
+    outcomes: Vec<Vec<(Discriminant<TEvent>, bool)>>,
+    filled_at: Option<Instant>,
+}
+
+impl <TEvent, TState> Default for CachedGuards<TEvent, TState> {
+    fn default() -> Self {
+        Self {
+            state: None,
+            outcomes: Vec::new(),
+            filled_at: None,
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Caches the outcome of predicates evaluated by [StateMachine::accepted_events], by Transition,
+    /// state and Event variant, so that a user interface asking the same question repeatedly only
+    /// evaluates each predicate once.
+    ///
+    /// **Only enable this if every predicate is a pure function of the state and of the Event's
+    /// variant.** A cached outcome is reused for the same state and any Event of the same variant,
+    /// whatever its payload, and whatever the State Machine's data. The cache is cleared whenever
+    /// the State Machine handles an Event, as effects may change its data; code that changes the
+    /// data in other ways, for example through [StateMachine::data], must call
+    /// [StateMachine::invalidate_guard_cache]. Handling Events always evaluates predicates afresh.
+    pub fn with_idempotent_guard_cache(mut self) -> Self {
+        self.definition.guard_cache = true;
+        self
+    }
+
+    /// Enables [StateMachineFactory::with_idempotent_guard_cache], clearing the cache once `ttl`
+    /// has passed since it was first filled, according to the definition's [crate::Clock].
+    pub fn with_guard_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.definition.guard_cache = true;
+        self.definition.guard_cache_ttl = Some(ttl);
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Clears the predicate outcomes cached by [StateMachineFactory::with_idempotent_guard_cache],
+    /// for example after changing the State Machine's data directly.
+    pub fn invalidate_guard_cache(&self) {
+        *self.guard_cache.lock() = CachedGuards::default();
+    }
+
+    /// The outcome of the predicate of the Transition at position `transition` for `event`, from
+    /// the cache if it is enabled and has one, or else from `evaluate`
+    pub(crate) fn cached_guard(&self, transition: usize, event: &TEvent, evaluate: impl FnOnce() -> bool) -> bool {
+        if !self.definition.guard_cache {
+            return evaluate();
+        }
+
+        let kind = discriminant(event);
+        // The clock is only read to expire the cache
+        let now = self.definition.guard_cache_ttl.map(|_| self.definition.clock.now());
+        {
+            let mut cache = self.guard_cache.lock();
+            let expired = now.zip(cache.filled_at).zip(self.definition.guard_cache_ttl)
+                .is_some_and(|((now, filled_at), ttl)| now.saturating_duration_since(filled_at) >= ttl);
+            // The state only changes between Events if it is assigned directly
+            let moved = cache.state.as_ref().is_some_and(|state| !self.definition.same_state(state, &self.state));
+            if expired || moved {
+                *cache = CachedGuards::default();
+            }
+            let cached = cache.outcomes.get(transition)
+                .and_then(|outcomes| outcomes.iter().find(|(cached_kind, _)| *cached_kind == kind));
+            if let Some((_, passed)) = cached {
+                return *passed;
+            }
+        }
+
+        // The lock isn't held while the predicate runs
+        let passed = evaluate();
+        let mut cache = self.guard_cache.lock();
+        if cache.filled_at.is_none() {
+            cache.filled_at = now;
+        }
+        cache.state.get_or_insert_with(|| self.state.clone());
+        if cache.outcomes.len() <= transition {
+            cache.outcomes.resize_with(transition + 1, Vec::new);
+        }
+        cache.outcomes[transition].push((kind, passed));
+        passed
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::{MockClock, StateMachineFactory};
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Running
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Start,
+        Ping(u32)
+    }
+
+    #[test]
+    fn test_guard_cache() {
+        let guards = AtomicUsize::new(0);
+        let clock = MockClock::new();
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_clock(clock.clone())
+            .with_guard_cache_ttl(Duration::from_secs(10))
+            .with_event_transition(&Events::Start, States::Idle, States::Running)
+            .with_predicated_transition(States::Running, Same, |d| {
+                guards.fetch_add(1, Ordering::SeqCst);
+                matches!(d.event, Events::Ping(_))
+            })
+            .lock();
+        let mut sm = factory.build(States::Running, ());
+        let candidates = [Events::Start, Events::Ping(1), Events::Ping(2)];
+
+        // Each Event variant evaluates the predicate once, whatever its payload
+        assert_eq!(vec![Events::Ping(1), Events::Ping(2)], sm.accepted_events(&candidates));
+        assert_eq!(vec![Events::Ping(1), Events::Ping(2)], sm.accepted_events(&candidates));
+        assert_eq!(2, guards.load(Ordering::SeqCst));
+
+        sm.invalidate_guard_cache();
+        sm.accepted_events(&candidates);
+        assert_eq!(4, guards.load(Ordering::SeqCst));
+        clock.advance(Duration::from_secs(10));
+        sm.accepted_events(&candidates);
+        assert_eq!(6, guards.load(Ordering::SeqCst));
+
+        // Handling an Event evaluates the predicate afresh, and clears the cache
+        sm.handle_event(Events::Ping(3)).expect("unexpected error");
+        assert_eq!(7, guards.load(Ordering::SeqCst));
+        sm.accepted_events(&candidates);
+        assert_eq!(9, guards.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,437 @@
+//! The JSON document written by [StateMachineFactory::to_json_definition], and reading it back.
+
+use std::fmt::Debug;
+use thiserror::Error;
+use crate::{StateMachineFactory, Trigger};
+use crate::export::json_string;
+
+/// The structure of a definition, as serialized by [StateMachineFactory::to_json_definition] and
+/// read back by [JsonDefinition::parse]. States and Events are named by their [Debug]
+/// representation, except that strings are named by their value, without quotes. Parsing a
+/// document and serializing it again with [JsonDefinition::to_json] yields the same document, so
+/// tools can read, edit and write definitions, and load them into a [crate::DynMachineFactory]
+/// with [crate::DynMachineFactory::from_json_definition].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct JsonDefinition {
+    /// The state recorded with [StateMachineFactory::with_initial], if any
+    pub initial: Option<String>,
+    /// Every known State
+    pub states: Vec<String>,
+    /// The metadata attached to States with [StateMachineFactory::with_state_metadata], as the
+    /// keys and values of each State that has any
+    pub state_metadata: Vec<(String, Vec<(String, String)>)>,
+    /// Every Event named by an event Transition, in the order they were first named
+    pub events: Vec<String>,
+    /// One entry for each Transition and State it can be taken from
+    pub transitions: Vec<JsonTransition>,
+}
+
+/// Error reading a [JsonDefinition] with [JsonDefinition::parse], or loading one with
+/// [crate::DynMachineFactory::from_json_definition]
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum JsonError {
+    /// The document isn't a definition written by [StateMachineFactory::to_json_definition], for
+    /// the reason given
+    #[error("malformed JSON definition: {0}")]
+    Malformed(String),
+    /// The Transition at the given position has a predicate or a calculated target, which can't be
+    /// serialized, so it can't be loaded
+    #[error("transition {0} of the JSON definition has a predicate or a calculated target")]
+    NotLoadable(usize),
+}
+
+/// One Transition from one State of a [JsonDefinition]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JsonTransition {
+    /// The State the Transition is taken from
+    pub from: String,
+    /// The kind of trigger: `event`, `guard` or `auto`
+    pub trigger: String,
+    /// The Event of an event Transition
+    pub event: Option<String>,
+    /// The State the Transition leads to, or `None` when it is calculated
+    pub to: Option<String>,
+    /// The name of the Transition's predicate, if it has one
+    pub label: Option<String>,
+}
+
+impl <'a, TEvent: Debug, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// The structure of the definition, as serialized by
+    /// [StateMachineFactory::to_json_definition], for tools that would rather inspect it than
+    /// parse the document.
+    pub fn json_definition(&self) -> JsonDefinition {
+        let states = self.known_states();
+        let mut events = Vec::new();
+        let mut transitions = Vec::new();
+        self.visit_edges(&states, |from, transition, to| {
+            // Transitions of a DynMachineFactory are labelled with their effect, which is what
+            // loading the document relinks
+            let (trigger, event, label) = match (&transition.dyn_names, transition.trigger()) {
+                (Some((event, effect)), _) => ("event", Some(event.clone()), effect.clone()),
+                (None, Trigger::Event(event)) => ("event", Some(name(event)), transition.name.clone()),
+                (None, Trigger::Guard) => ("guard", None, transition.name.clone()),
+                (None, Trigger::Auto) => ("auto", None, transition.name.clone())
+            };
+            if let Some(event) = event.as_ref().filter(|event| !events.contains(*event)) {
+                events.push(event.clone());
+            }
+            transitions.push(JsonTransition {
+                from: name(from),
+                trigger: trigger.to_string(),
+                event,
+                to: to.map(name),
+                label,
+            });
+        });
+        JsonDefinition {
+            initial: self.initial_state.as_ref().map(name),
+            states: states.iter().map(name).collect(),
+            state_metadata: self.definition.state_metadata.iter()
+                .map(|(state, entries)| (name(state), entries.clone()))
+                .collect(),
+            events,
+            transitions,
+        }
+    }
+}
+
+/// Names a State or Event by its [Debug] representation, or a string by its value, so that the
+/// States and Events of a [crate::DynMachineFactory] keep their names when loaded and exported again
+fn name(value: &impl Debug) -> String {
+    let debug = format!("{:?}", value);
+    unquoted(&debug).unwrap_or(debug)
+}
+
+/// Reverses the [Debug] representation of a string, or returns `None` if `debug` isn't one
+fn unquoted(debug: &str) -> Option<String> {
+    let mut chars = debug.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut value = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return None,
+            '\\' => value.push(match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'u' => {
+                    if chars.next()? != '{' {
+                        return None;
+                    }
+                    let digits: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?
+                }
+                c @ ('\\' | '"' | '\'') => c,
+                _ => return None
+            }),
+            c => value.push(c)
+        }
+    }
+    Some(value)
+}
+
+/// Writes a string, or `null`
+fn json_option(value: Option<&String>) -> String {
+    value.map_or("null".to_string(), |value| json_string(value))
+}
+
+impl JsonDefinition {
+    /// Serializes the definition in the format described by
+    /// [StateMachineFactory::to_json_definition].
+    pub fn to_json(&self) -> String {
+        let list = |values: Vec<String>| match values.len() {
+            0 => "[]".to_string(),
+            _ => format!("[\n    {}\n  ]", values.join(",\n    "))
+        };
+        // Left out when empty, so that definitions without metadata keep their structural hash
+        let state_metadata = match self.state_metadata.len() {
+            0 => String::new(),
+            _ => {
+                let states: Vec<String> = self.state_metadata.iter()
+                    .map(|(state, entries)| {
+                        let entries: Vec<String> = entries.iter().map(|(key, value)| format!("{}: {}", json_string(key), json_string(value))).collect();
+                        format!("{}: {{{}}}", json_string(state), entries.join(", "))
+                    })
+                    .collect();
+                format!("\n  \"state_metadata\": {{\n    {}\n  }},", states.join(",\n    "))
+            }
+        };
+        let transitions = self.transitions.iter()
+            .map(|transition| format!(
+                "{{\"from\": {}, \"trigger\": {}, \"event\": {}, \"to\": {}, \"label\": {}}}",
+                json_string(&transition.from), json_string(&transition.trigger), json_option(transition.event.as_ref()),
+                json_option(transition.to.as_ref()), json_option(transition.label.as_ref())))
+            .collect();
+        format!("{{\n  \"initial\": {},\n  \"states\": {},{}\n  \"events\": {},\n  \"transitions\": {}\n}}\n",
+            json_option(self.initial.as_ref()), list(self.states.iter().map(|state| json_string(state)).collect()), state_metadata,
+            list(self.events.iter().map(|event| json_string(event)).collect()), list(transitions))
+    }
+
+    /// Reads a document in the format written by [StateMachineFactory::to_json_definition].
+    /// Fails with [JsonError::Malformed] if it isn't valid JSON, or is missing a field or has one
+    /// of the wrong type. Fields other than those of the format are ignored.
+    pub fn parse(json: &str) -> Result<Self, JsonError> {
+        let mut parser = Parser { json, position: 0 };
+        let document = parser.value()?;
+        parser.whitespace();
+        if parser.position < json.len() {
+            return Err(parser.error("the end of the document"));
+        }
+
+        let strings = |value: &Value, field: &str| match value {
+            Value::Array(values) => values.iter().map(|value| value.string(field)).collect(),
+            _ => Err(malformed(field, "an array"))
+        };
+        let state_metadata = match document.optional_field("state_metadata") {
+            Some(Value::Object(states)) => states.iter()
+                .map(|(state, entries)| match entries {
+                    Value::Object(entries) => entries.iter()
+                        .map(|(key, value)| Ok((key.clone(), value.string("state_metadata")?)))
+                        .collect::<Result<_, _>>()
+                        .map(|entries| (state.clone(), entries)),
+                    _ => Err(malformed("state_metadata", "an object"))
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(malformed("state_metadata", "an object")),
+            None => Vec::new()
+        };
+        let transitions = match document.field("transitions")? {
+            Value::Array(transitions) => transitions.iter()
+                .map(|transition| Ok(JsonTransition {
+                    from: transition.field("from")?.string("from")?,
+                    trigger: transition.field("trigger")?.string("trigger")?,
+                    event: transition.field("event")?.optional_string("event")?,
+                    to: transition.field("to")?.optional_string("to")?,
+                    label: transition.field("label")?.optional_string("label")?,
+                }))
+                .collect::<Result<_, _>>()?,
+            _ => return Err(malformed("transitions", "an array"))
+        };
+        Ok(JsonDefinition {
+            initial: document.field("initial")?.optional_string("initial")?,
+            states: strings(document.field("states")?, "states")?,
+            state_metadata,
+            events: strings(document.field("events")?, "events")?,
+            transitions,
+        })
+    }
+}
+
+/// The error for a field that doesn't hold what it should
+fn malformed(field: &str, expected: &str) -> JsonError {
+    JsonError::Malformed(format!("expected {} for {:?}", expected, field))
+}
+
+/// The subset of JSON values that definitions are made of
+enum Value {
+    Null,
+    String(String),
+    Array(Vec<Value>),
+    /// The members of an object, in the order they were written
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn optional_field(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(key, _)| key == name).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    fn field(&self, name: &str) -> Result<&Value, JsonError> {
+        self.optional_field(name).ok_or_else(|| malformed(name, "a field"))
+    }
+
+    fn string(&self, field: &str) -> Result<String, JsonError> {
+        match self {
+            Value::String(value) => Ok(value.clone()),
+            _ => Err(malformed(field, "a string"))
+        }
+    }
+
+    fn optional_string(&self, field: &str) -> Result<Option<String>, JsonError> {
+        match self {
+            Value::Null => Ok(None),
+            _ => self.string(field).map(Some)
+        }
+    }
+}
+
+/// Reads JSON values from a document, one character at a time
+struct Parser<'j> {
+    json: &'j str,
+    /// The byte offset of the next character
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, expected: &str) -> JsonError {
+        JsonError::Malformed(format!("expected {} at byte {}", expected, self.position))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.json[self.position..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.peek()?;
+        self.position += next.len_utf8();
+        Some(next)
+    }
+
+    fn whitespace(&mut self) {
+        while self.peek().is_some_and(|c| matches!(c, ' ' | '\t' | '\n' | '\r')) {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `token` after any whitespace, if it comes next
+    fn eat(&mut self, token: char) -> bool {
+        self.whitespace();
+        if self.peek() == Some(token) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, token: char) -> Result<(), JsonError> {
+        match self.eat(token) {
+            true => Ok(()),
+            false => Err(self.error(&format!("{:?}", token)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, JsonError> {
+        self.whitespace();
+        match self.peek() {
+            Some('"') => self.string().map(Value::String),
+            Some('[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                if !self.eat(']') {
+                    loop {
+                        values.push(self.value()?);
+                        if self.eat(']') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Value::Array(values))
+            }
+            Some('{') => {
+                self.position += 1;
+                let mut members = Vec::new();
+                if !self.eat('}') {
+                    loop {
+                        self.whitespace();
+                        let key = self.string()?;
+                        self.expect(':')?;
+                        members.push((key, self.value()?));
+                        if self.eat('}') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                Ok(Value::Object(members))
+            }
+            _ if self.json[self.position..].starts_with("null") => {
+                self.position += "null".len();
+                Ok(Value::Null)
+            }
+            _ => Err(self.error("a string, an array, an object or null"))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        if self.next() != Some('"') {
+            return Err(self.error("a string"));
+        }
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('/') => value.push('/'),
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => value.push('\u{8}'),
+                    Some('f') => value.push('\u{c}'),
+                    Some('u') => {
+                        let digits = self.json.get(self.position..self.position + 4)
+                            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| self.error("four hexadecimal digits"))?;
+                        self.position += 4;
+                        value.push(digits);
+                    }
+                    _ => return Err(self.error("an escape sequence"))
+                },
+                Some(c) => value.push(c),
+                None => return Err(self.error("the end of the string"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{JsonDefinition, JsonError, StateMachineFactory};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    enum Events {
+        Add,
+        Say(&'static str)
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_initial(States::Idle)
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Say("\"quoted\"\n\\"), Any, Same)
+            .with_named_predicated_transition_effect("equals", States::Adding, States::Idle, |_| true, |_| Ok(()))
+            .with_auto_transition(States::Adding, crate::ToState::Calc(Box::new(|d: crate::StateTransitionToStateData<_, States, _>| *d.from)))
+            .with_state_metadata(States::Adding, "color", "blue");
+
+        let json = factory.to_json_definition();
+        let document = JsonDefinition::parse(&json).expect("unexpected error");
+        assert_eq!(factory.json_definition(), document);
+        assert_eq!(json, document.to_json());
+        assert_eq!(Some("Say(\"\\\"quoted\\\"\\n\\\\\")".to_string()), document.transitions[1].event);
+        assert_eq!(None, document.transitions.last().and_then(|transition| transition.to.clone()));
+
+        // Strings are named by their value
+        let strings = StateMachineFactory::<&str, String, ()>::new()
+            .with_initial("say \"hi\"".to_string())
+            .with_event_transition(&"go\n", "say \"hi\"".to_string(), "done".to_string())
+            .json_definition();
+        assert_eq!(Some("say \"hi\"".to_string()), strings.initial);
+        assert_eq!(vec!["go\n"], strings.events);
+        assert_eq!(Some("done".to_string()), strings.transitions[0].to);
+
+        let empty = JsonDefinition::parse(&StateMachineFactory::<Events, States, ()>::new().to_json_definition()).expect("unexpected error");
+        assert_eq!(JsonDefinition::default(), empty);
+    }
+
+    #[test]
+    fn test_json_parse_errors() {
+        assert_eq!(Some(JsonError::Malformed("expected \',\' at byte 14".to_string())), JsonDefinition::parse(r#"{"states": [] "events": []}"#).err());
+        assert_eq!(Some(JsonError::Malformed("expected a field for \"initial\"".to_string())), JsonDefinition::parse(r#"{"states": [], "events": [], "transitions": []}"#).err());
+        assert_eq!(Some(JsonError::Malformed("expected a string for \"states\"".to_string())), JsonDefinition::parse(r#"{"initial": null, "states": [null], "events": [], "transitions": []}"#).err());
+    }
+}
@@ -8,7 +8,9 @@
 //!
 //! In this State Machine implementation, the State Machine is operated by providing it with
 //! Events through the [StateMachine::handle_event] method. Events can be anything, but it is common
-//! to represent them with an Enum.
+//! to represent them with an Enum. [StateMachine::handle_event] takes ownership of the Event, while
+//! [StateMachine::handle_event_ref] borrows it, which avoids cloning Events that are large or owned
+//! elsewhere. Either way, effects and predicates only ever see the Event by reference.
 //!
 //! States themselves are very restricted to allow for Any / AllOf matching, and typically should
 //! also be implemented with an enum that derives Copy, Clone, Eq, PartialEq, and Debug.
@@ -25,8 +27,16 @@
 //!     - [StateMachineFactory::with_event_transition_effect]
 //!     - [StateMachineFactory::with_auto_transition]
 //!     - [StateMachineFactory::with_custom_transition]
-//! 3. Lock your factory into a [LockedStateMachineFactory] by calling [StateMachineFactory::lock]
-//! 4. Create a state machine by calling [LockedStateMachineFactory::build]
+//!     - [StateMachineFactory::transition], which names each part of an event Transition in turn
+//! 3. Optionally add entry effects using [StateMachineFactory::with_entry_effect] or
+//!    [StateMachineFactory::with_entry_effect_from], pre-transition hooks using
+//!    [StateMachineFactory::with_pre_hook], and listeners using [StateMachineFactory::with_listener]
+//!    or [StateMachineFactory::with_listener_priority]
+//! 4. Lock your factory into a [LockedStateMachineFactory] by calling [StateMachineFactory::lock],
+//!    or [StateMachineFactory::compile] to also precompute which Transitions apply from each State
+//! 5. Create a state machine by calling [LockedStateMachineFactory::build], or
+//!    [LockedStateMachineFactory::build_initial] if an initial state was recorded with
+//!    [StateMachineFactory::with_initial]
 //!
 //! # Transitions
 //!
@@ -34,8 +44,9 @@
 //! of initial states (as a [FromState]) that may trigger them:
 //! - [FromState::Any]: Any starting state - this Transition will be evaluated for all events.
 //! - [FromState::AnyOf]: Any starting state in the provided list.
+//! - [FromState::Not]: Any starting state except those in the provided list.
 //! - [FromState::From]: The specific provided started state. FromState implements [From] for this
-//! variant, so the variant can be elided for the common case.
+//!   variant, so the variant can be elided for the common case.
 //!
 //! Triggers may also optionally provide a predicate to apply custom logic to decide whether the
 //! Transition is applied. Transitions may also be triggered from any ([FromState::Any]) state,
@@ -44,44 +55,321 @@
 //! Transitions must also describe the state that they transition the State Machine into. The to_state
 //! of a transition can be represented as one of the following:
 //! - [To]: A specific, pre-defined state. ToState implements [From] for this variant, so the variant
-//! can be elided for the common case.
+//!   can be elided for the common case.
 //! - [Same]: Whatever state the transition started from; this makes the transition a no-op for the
-//! state machine, but side effects may still be executed. This is useful in some cases, such as in
-//! transition loggers.
+//!   state machine, but side effects may still be executed. This is useful in some cases, such as in
+//!   transition loggers.
 //! - [Calc]: Allows for dynamic target state calculation, when a given transition may result in
-//! more than one target states. This is something of an antipattern; these should preferentially
-//! be represented as multiple transitions with different predicates.
+//!   more than one target states. This is something of an antipattern; these should preferentially
+//!   be represented as multiple transitions with different predicates.
 //!
 //! # Event Lifecycle
 //!
-//! 1. Handle event called.
+//! 1. Handle event called. The hooks registered with [StateMachineFactory::with_before_dispatch] run.
 //! 2. For each defined transition:
 //!
-//!     2a. Determine if the from_state of the transition matches the current state.
-//!         If false, break and move on to the next transition.
+//!     2a. Determine if the from_state of the transition matches the current state. If false, break and move on to the next transition.
 //!
 //!     2b. Determine the to_state of the transition.
 //!
-//!     2c. Run the transition's predicate, if any.
-//!         If false (or no predicate), break and move on to the next transition.
+//!     2c. Run the pre-transition hooks, in registration order. If any returns false, stop with a [StateMachineError::HookRejected] error.
 //!
-//!     2d. Run the transition's effect, if any.
+//!     2d. Run the transition's predicate, if any. If false, run the transition's else effect, if any (see [StateMachineFactory::with_predicated_transition_effect_else]), and move on to the next transition.
 //!
-//!     2e. Transition the state machine to the to_state determined in 2b above.
+//!     2e. Run the transition's effect, if any.
 //!
-//! 3. If the State Machine has cycle set to true, return to 2.
+//!     2f. If the to_state differs from the current state, run the region exit effects of the sets of states it leaves (see [StateMachineFactory::with_region_exit_effect]), the transit effects registered for the change (see [StateMachineFactory::with_transit_effect]), the region entry effects of the sets it enters, then the entry effects registered for it. Then check the invariants (see [StateMachineFactory::with_invariant]); if any fails, stop with a [StateMachineError::InvariantViolated] error.
+//!
+//!     2g. Run the transition loggers (see [StateMachineFactory::with_global_transition_logger]), then the listeners, highest priority first.
+//!
+//!     2h. Transition the state machine to the to_state determined in 2b above.
+//!
+//! 3. If the State Machine has cycle set to true, return to 2. Otherwise, if no Transition matched the Event, go through 2 once more for the fallback Transitions (see [StateMachineFactory::with_fallback_transition_effect]), which are skipped until then.
+//! 4. The hooks registered with [StateMachineFactory::with_after_dispatch] run with the outcome,
+//!    even if no transition matched or handling failed.
+//!
+//! Sticky Events (see [StateMachine::set_sticky]) go through this lifecycle first, each on its
+//! own, before the Event passed in by the caller. Events deferred in the current state (see
+//! [StateMachineFactory::with_deferred_event]) skip it entirely, and go through it once an Event
+//! changes the state.
+//!
+//! # Matching Multiple Transitions
+//!
+//! Every Transition that matches an Event runs; evaluation doesn't stop at the first match. Each
+//! state-changing Transition (one whose to_state is [To] or [Calc]) moves the State Machine as soon
+//! as its effect has run, so the Transitions registered after it are matched against the *new*
+//! state. In practice this means that, among the state-changing Transitions that match a given
+//! state, the first one registered wins: it moves the machine out of that state before the others
+//! are considered.
+//!
+//! [Same] Transitions never change the state, so every matching [Same] Transition runs. This makes
+//! them a good fit for cross-cutting side effects such as loggers.
+//!
+//! Transitions registered with [StateMachineFactory::with_exclusive_transition_effect] go one step
+//! further: once one of them fires, no further state-changing Transitions are considered for the
+//! Event (and the machine does not cycle), while the remaining matching [Same] Transitions still run.
+//!
+//! Because all matching effects run, it is easy to register two broad effects that both fire by
+//! accident. [StateMachineFactory::strict] makes [StateMachineFactory::try_lock] reject definitions
+//! where two effects apply to the same State and Event, unless they are marked as deliberately
+//! overlapping with [StateMachineFactory::with_cross_cutting_effect] or are exclusive.
+//!
+//! To let an early, specific handler win outright, register it with
+//! [StateMachineFactory::with_consuming_transition_effect]. Once it fires, the Event is consumed:
+//! unlike an exclusive Transition, not even the remaining [Same] Transitions run.
+//!
+//! Transitions are considered in registration order, unless they declare dependencies on one
+//! another: a Transition marked with [StateMachineFactory::after] is moved after the Transition
+//! given the same id with [StateMachineFactory::effect_id] when the factory is locked.
+//!
+//! # Raising Events from Effects
+//!
+//! Effects may raise follow-up events with [StateTransitionEffectData::enqueue]. These are queued
+//! rather than handled immediately, and are handled once the State Machine has finished with the
+//! current event, before [StateMachine::handle_event] returns. Events enqueued with
+//! [StateTransitionEffectData::enqueue_priority] jump ahead of lower-priority queued events, which
+//! allows an urgent follow-up (such as an abort) to preempt work that is already queued.
+//! [StateMachineFactory::with_entry_event] enqueues an Event whenever a state is entered, and
+//! [StateMachineFactory::with_queue_limit] bounds how many queued Events one call may handle.
+//! [StateTransitionEffectData::depth] tells effects how deep in a chain of enqueued Events they
+//! run, so that a cascade can stop itself before reaching that limit.
+//!
+//! Effects may also publish domain events with [StateTransitionEffectData::publish], or by
+//! returning them from an effect registered with
+//! [StateMachineFactory::with_publishing_transition_effect]. These are outbound facts rather than
+//! Events for the State Machine: [StateMachine::handle_event_publishing] returns them in a
+//! [TransitionOutcome], for example to be persisted to an event store. A [Pipeline] feeds the
+//! domain events of one State Machine into another as its Events.
+//!
+//! # Data and External Services
+//!
+//! The Data type of a State Machine can be anything, including a reference. This makes it a
+//! natural place to hand effects and predicates the services they need, such as a database
+//! connection pool or an HTTP client, bundled into an application context:
+//! - `&AppContext`: [LockedStateMachineFactory::build] borrows the context, so the State Machine
+//!   can't outlive the stack frame that owns it. This is the simplest option when a machine is
+//!   created and driven within one function.
+//! - `Arc<AppContext>`: the State Machine shares ownership of the context, so it can be stored,
+//!   returned, or moved to another thread independently of whoever created the context.
+//! - `AppContext`: the State Machine owns the context outright.
+//!
+//! Effects only receive a shared reference to the Data, so services must be callable through `&self`
+//! (as connection pools and clients typically are).
+//!
+//! # Configuration and Working Data
+//!
+//! Effects receive a shared reference to the State Machine's data. Machines that need both an
+//! immutable configuration and a mutable working set can use [SplitData] as their data type and
+//! build instances with [LockedStateMachineFactory::build_split]; effects and predicates then reach
+//! each half through [StateTransitionEffectData::config] and [StateTransitionEffectData::working].
+//!
+//! Alternatively, a [DataStore] holds one value of each type stored in it, which lets
+//! independently-developed effects keep their own data without a shared Data type. Build
+//! instances with [LockedStateMachineFactory::build_with_store], and reach each value through
+//! [StateTransitionEffectData::get] and [StateTransitionEffectData::get_mut].
+//!
+//! # Sharing a State Machine Between Threads
+//!
+//! [StateMachine::into_shared] wraps a machine in a [SharedStateMachine], a cheaply cloneable
+//! handle backed by an `Arc<RwLock<..>>`. Any number of threads may read its current state at once,
+//! while events are handled one at a time. This requires the State and Data types to be `Send` and
+//! `Sync`; all predicates, effects and calculated targets are already required to be.
+//!
+//! Alternatively, [StateMachine::into_actor] moves a machine onto a thread of its own, driven
+//! through a mailbox: [ActorHandle::send] hands it an Event without waiting, and
+//! [ActorHandle::ask] waits for the outcome.
+//!
+//! # Orthogonal Regions
+//!
+//! A [CompositeMachine] groups several State Machines into regions that all handle the same
+//! Events. Effects and predicates in one region can read the state of another with
+//! [StateTransitionEffectData::region_state], but cannot change it.
+//!
+//! # Timeouts
+//!
+//! [StateMachineFactory::with_timeout] raises an Event once a machine has stayed in a state for a
+//! given duration. Timeouts are checked whenever [StateMachine::tick] is called, which lets tests
+//! control time through a [MockClock] set with [StateMachineFactory::with_clock]. Effects can also
+//! schedule an Event to be handled after a delay with [StateTransitionEffectData::schedule], which
+//! `tick` handles once it comes due.
+//!
+//! Rather than calling `tick` in a loop, a [SharedStateMachine] can drive its own timeouts and
+//! scheduled Events from a background thread started with `SharedStateMachine::spawn_timer`, which
+//! runs until the returned `TimerHandle` is dropped. The background timer is available with the
+//! `async` feature, along with `SharedStateMachine::spawn_timer_on`, which runs the timer as a task
+//! on an async runtime through the `Executor` trait instead. Machines that only move on when Events arrive can instead enable
+//! [StateMachineFactory::lazy_timeouts], which checks the timeout whenever an Event is handled.
+//! [StateMachineFactory::with_global_inactivity_timeout] moves a machine that hasn't received an
+//! Event for a while into a given state, whatever state it is in, such as an expired session.
+//! The `async` feature also adds `StateMachineFactory::with_async_guarded_transition_effect`, for
+//! guards that await IO, which `StateMachine::handle_event_async` awaits before handling an Event.
+//!
+//! # Analysing a Definition
+//!
+//! A [StateMachineFactory] can be inspected before it is locked. [StateMachineFactory::find_cycles]
+//! reports the groups of States that can lead back to one another, and
+//! [StateMachineFactory::find_self_loops] reports the States with Transitions back into themselves.
+//! [StateMachineFactory::states_reaching] lists the States with a Transition into a given State,
+//! and [StateMachineFactory::state_space_iter] lazily explores the States reachable from a given
+//! State, for definitions too large to analyse as a whole. [StateMachineFactory::validate_workflow]
+//! combines these into one check for workflows: final States reachable, no dead ends, and no
+//! Transitions into unknown States.
+//! Analysis only considers Transitions with a fixed target; [Calc] Transitions can't be followed
+//! without running them. [FromState::Any] Transitions are expanded to every known State: those
+//! registered with [StateMachineFactory::with_states], plus any State named by a Transition; and
+//! [FromState::Not] Transitions to every known State except those listed.
+//! [StateMachineFactory::events_accepted_in] lists the Events a State has Transitions for, and
+//! [StateMachineFactory::assert_all_events_handled] checks in a test that every Event is handled in
+//! every State, apart from the pairs that are intentionally left unhandled.
+//!
+//! [StateMachineFactory::to_dot] and [StateMachineFactory::to_mermaid] render a definition as a
+//! diagram. Both are built on [StateMachineFactory::visit], which walks the States and Transitions
+//! of a definition with a user-supplied [TransitionVisitor], for exporting to other formats.
+//! [StateMachineFactory::to_matrix] gives a more compact overview: a table of the State each Event
+//! leads to from each State. [StateMachineFactory::to_json_definition] serializes the structure of
+//! a definition (but not its closures) to JSON, for external tooling.
+//! [StateMachineFactory::structural_hash] and [StateMachineFactory::diff] compare that structure,
+//! for example to fail a test when a definition changes unintentionally.
+//!
+//! # Metrics
+//!
+//! With the `metrics` feature enabled, each [StateMachine] keeps count of the Events it receives.
+//! [StateMachine::metrics] exposes these counts, broken down by the state the machine was in and by
+//! the kind (enum variant) of Event, including Events that matched no Transition at all.
+//!
+//! With the `coverage` feature enabled, each [StateMachine] also records which Transitions it has
+//! taken. `StateMachine::coverage` reports the Transitions a test suite never exercised, and
+//! combined with [StateMachineFactory::assert_all_events_handled] helps test a definition
+//! thoroughly.
+//!
+//! With the `binary-log` feature enabled, State Machines built from a factory with
+//! `StateMachineFactory::record_event_log` log the Events they handle.
+//! `StateMachine::export_log_binary` writes the log as compact length-prefixed records of Events
+//! encoded with `BinaryEvent`, and `StateMachine::replay_log_binary` reconstructs a State Machine
+//! from one.
+//!
+//! With the `tracing` feature enabled, every decision taken while handling an Event is logged as
+//! `tracing` events at trace level: the Transitions considered and why each was or wasn't taken,
+//! the effects run and the resulting State. `StateMachineFactory::with_traced_events` adds the
+//! Events themselves to the log.
+//!
+//! # Advanced: State Identifiers
+//!
+//! The full set of States a machine can occupy may be registered up front with
+//! [StateMachineFactory::with_states]. Each registered State is then assigned a compact
+//! [StateId], in registration order, which can be looked up with [StateMachineFactory::state_id]
+//! and resolved back into a State with [StateMachineFactory::id_to_state]. This is an opt-in,
+//! advanced API intended for tooling that prefers to work with integers rather than States;
+//! machines that never register their States behave exactly as before. States only need
+//! [PartialEq], so looking up an id compares the State with each registered one in turn; it
+//! doesn't speed up dispatch, which [StateMachineFactory::compile] does for registered States.
 //!
 #![deny(missing_docs)]
 
-use std::fmt::{Debug};
-use std::ops::Deref;
+/// Emits a trace event of the decision log with the `tracing` feature, and nothing without it
+macro_rules! trace_decision {
+    ($($field:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "statement", $($field)*);
+    };
+}
+
+mod actor;
+mod analysis;
+#[cfg(feature = "async")]
+mod async_guard;
+mod audit;
+#[cfg(feature = "binary-log")]
+mod binary_log;
+mod builder;
+mod cancel;
+mod clock;
+mod composite;
+mod configuration;
+mod defer;
+#[cfg(feature = "async")]
+mod executor;
+#[cfg(feature = "coverage")]
+mod coverage;
+mod diff;
+mod dispatch;
+mod dynamic;
+mod export;
+mod fork;
+mod guard;
+mod guard_cache;
+mod json;
+mod group;
+mod metadata;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod order;
+mod pipeline;
+mod privilege;
+mod publish;
+mod queue;
+mod region_effect;
+mod shared;
+mod snapshot;
+mod split;
+mod state_space;
+mod stepper;
+mod store;
+pub mod testing;
+mod timer;
+#[cfg(feature = "tracing")]
+mod trace;
+mod visit;
+mod weak;
+
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::RangeBounds;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use crate::queue::DispatchContext;
+use crate::timer::{ScheduledEvents, Timeout};
 use crate::ToState::{Calc, Same, To};
 
+pub use actor::ActorHandle;
+pub use analysis::WorkflowReport;
+pub use audit::Audit;
+pub use cancel::CancellationToken;
+#[cfg(feature = "binary-log")]
+pub use binary_log::{BinaryEvent, BinaryLogError};
+pub use builder::{TransitionBuilder, TransitionEventBuilder, TransitionFromBuilder, TransitionTargetBuilder};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use composite::{CompositeMachine, RegionId};
+#[cfg(feature = "coverage")]
+pub use coverage::{CoverageReport, TransitionCoverage};
+pub use diff::{DefinitionChange, TransitionShape};
+pub use dispatch::DispatchStats;
+pub use dynamic::{DynMachine, DynMachineFactory, EffectRegistry};
+#[cfg(feature = "async")]
+pub use executor::{BoxFuture, Executor, ExecutorTimerHandle};
+pub use guard::Guard;
+pub use json::{JsonDefinition, JsonError, JsonTransition};
+#[cfg(feature = "metrics")]
+pub use metrics::{EventCounts, EventKind, Metrics};
+pub use pipeline::{Pipeline, PipelineError, PipelineOutcome};
+pub use privilege::TransitionToken;
+pub use publish::TransitionOutcome;
+pub use queue::DEFAULT_PRIORITY;
+pub use shared::SharedStateMachine;
+pub use snapshot::{SnapshotDiff, StateSnapshot};
+pub use split::SplitData;
+#[cfg(feature = "derive")]
+pub use statement_derive::EventConstructors;
+pub use state_space::StateSpaceIter;
+pub use stepper::{StepperEvent, StepperMachine};
+pub use store::DataStore;
+#[cfg(feature = "async")]
+pub use timer::{TimerHandle, TIMER_ERROR_BACKOFF};
+pub use visit::{Trigger, TransitionVisitor};
+
 /// State Machine instance, usually created by calling create on a [LockedStateMachineFactory]
 #[derive(Default, Clone)]
-pub struct StateMachine<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+pub struct StateMachine<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData = ()> {
     /// The current state of the `StateMachine`
     pub state: TState,
     /// All of the transitions that are valid for this state machine. Note that this list may be
@@ -94,6 +382,49 @@ pub struct StateMachine<'a, TEvent, TState: PartialEq<TState> + Clone + Send + '
     /// True if this state machine automatically re-runs evaluation after a transition, potentially
     /// executing multiple state transitions for one event.
     pub cycle: bool,
+    definition: Arc<Definition<'a, TEvent, TState, TData>>,
+    /// When the current state was entered, according to the definition's [Clock]
+    entered_at: Option<Instant>,
+    /// When the last external Event was handled, or the State Machine was built if none has been
+    last_event_at: Option<Instant>,
+    /// Events scheduled with [StateTransitionEffectData::schedule] that have yet to be handled
+    scheduled: ScheduledEvents<TEvent>,
+    /// The state left by the most recent change of state
+    previous_state: Option<TState>,
+    /// The last Event handled, if recorded; see [StateMachineFactory::record_last_event]
+    last_event: Option<TEvent>,
+    /// How many Events in a row were handled without changing state
+    consecutive_same: usize,
+    /// True if the state changed since the last external Event started being handled
+    state_changed: bool,
+    /// How many Transitions were taken since the State Machine was built or the count was reset
+    transitions_total: usize,
+    /// True while replaying Events, when side-effecting effects are skipped
+    replay_mode: bool,
+    /// Events handled before every external Event; see [StateMachine::set_sticky]
+    sticky: Vec<TEvent>,
+    /// Events buffered by [StateMachineFactory::with_deferred_event], in the order they arrived
+    deferred: Vec<TEvent>,
+    /// How often the Event of each counted Transition occurred in the current state, by position
+    event_counts: Vec<usize>,
+    /// Tokens handed out with [StateTransitionEffectData::exit_token] for the current state
+    exit_tokens: Vec<CancellationToken>,
+    /// Listeners added with [StateMachine::add_weak_listener], which return false once their
+    /// observer has been dropped
+    weak_listeners: weak::WeakListeners<'a, TEvent, TState, TData>,
+    /// True while [StateMachine::with_observers_suppressed] runs, when listeners and transition
+    /// loggers don't run
+    observers_suppressed: bool,
+    /// Predicate outcomes cached by [StateMachineFactory::with_idempotent_guard_cache]
+    guard_cache: guard_cache::GuardCache<TEvent, TState>,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics<TEvent, TState>,
+    /// How often each Transition was taken, by position
+    #[cfg(feature = "coverage")]
+    coverage: Vec<u64>,
+    /// The Events handled, if logged; see `StateMachineFactory::record_event_log`
+    #[cfg(feature = "binary-log")]
+    event_log: Vec<TEvent>,
 }
 
 impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
@@ -103,7 +434,90 @@ impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + Partia
             state: initial_state,
             data: initial_data,
             transitions: Arc::new(Vec::new()),
+            definition: Arc::new(Definition::default()),
+            entered_at: None,
+            last_event_at: None,
+            scheduled: ScheduledEvents::new(),
+            previous_state: None,
+            last_event: None,
+            consecutive_same: 0,
+            state_changed: false,
+            transitions_total: 0,
+            replay_mode: false,
+            sticky: Vec::new(),
+            deferred: Vec::new(),
+            event_counts: Vec::new(),
+            exit_tokens: Vec::new(),
+            weak_listeners: weak::WeakListeners::default(),
+            observers_suppressed: false,
+            guard_cache: guard_cache::GuardCache::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::default(),
+            #[cfg(feature = "coverage")]
+            coverage: Vec::new(),
+            #[cfg(feature = "binary-log")]
+            event_log: Vec::new(),
+        }
+    }
+
+    fn with_definition(mut self, definition: Arc<Definition<'a, TEvent, TState, TData>>) -> Self {
+        self.entered_at = Some(definition.clock.now());
+        self.last_event_at = self.entered_at;
+        self.definition = definition;
+        self
+    }
+
+    /// Runs the entry effects registered for a state change from the current state into `to_state`
+    fn run_entry_effects(&self, event: &TEvent, to_state: &TState, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        self.run_state_change_effects(&self.definition.entry_effects, "entry effect", event, to_state, context)
+    }
+
+    /// Runs the transit effects registered for a state change from the current state into
+    /// `to_state`; see [StateMachineFactory::with_transit_effect]
+    fn run_transit_effects(&self, event: &TEvent, to_state: &TState, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        self.run_state_change_effects(&self.definition.transit_effects, "transit effect", event, to_state, context)
+    }
+
+    /// Runs those of `effects` that apply to a state change from the current state into `to_state`
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn run_state_change_effects(&self, effects: &[EntryEffect<'a, TEvent, TState, TData>], kind: &'static str, event: &TEvent, to_state: &TState, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        for entry_effect in effects {
+            if self.definition.same_state(&entry_effect.to_state, to_state) && self.definition.state_matches(&entry_effect.from_state, &self.state) {
+                let entry_effect_data = StateTransitionEffectData {
+                    data: &self.data,
+                    event,
+                    from: &self.state,
+                    to: to_state,
+                    context
+                };
+                let result = (entry_effect.effect)(entry_effect_data);
+                trace_decision!(effect = kind, ok = result.is_ok(), "effect run");
+                result.map_err(|e| StateMachineError::EffectError(self.state.clone(), to_state.clone(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the listeners registered with [StateMachineFactory::with_listener] for a Transition
+    /// from the current state into `to_state`
+    fn run_listeners(&self, event: &TEvent, to_state: &TState, context: &DispatchContext<TEvent, TState>) {
+        if self.observers_suppressed {
+            return;
+        }
+        for logger in &self.definition.transition_loggers {
+            logger(&self.state, to_state, event);
+        }
+        let transition_effect_data = StateTransitionEffectData {
+            data: &self.data,
+            event,
+            from: &self.state,
+            to: to_state,
+            context
+        };
+        for (_, listener) in &self.definition.listeners {
+            listener(&transition_effect_data);
         }
+        self.weak_listeners.run(&transition_effect_data);
     }
 
     /// Creates a `StateMachine` from a pre-existing set of transitions.
@@ -112,100 +526,762 @@ impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + Partia
         self
     }
 
+    /// Recovers an editable [StateMachineFactory] from this State Machine, so that its Transitions
+    /// can be changed at runtime (for example, to load a new rule set) before building a new
+    /// machine. The factory's initial state is set to this machine's current state, so
+    /// [LockedStateMachineFactory::build_initial] carries on from where this machine left off; the
+    /// machine's data is returned alongside it.
+    ///
+    /// Events enqueued by effects are always handled before [StateMachine::handle_event] returns,
+    /// so no queued Events are lost. Events scheduled with [StateTransitionEffectData::schedule]
+    /// that haven't come due yet are discarded, as are any metrics, and timeouts start over in the
+    /// rebuilt machine.
+    ///
+    /// The definition of a State Machine is shared with its [LockedStateMachineFactory] and every
+    /// other machine built from it, so it can only be recovered once they have all been dropped.
+    /// Otherwise the State Machine is handed back unchanged as the error.
+    // Handing back the State Machine itself is the point of the error, however large it is
+    #[allow(clippy::result_large_err)]
+    pub fn unlock(mut self) -> Result<(StateMachineFactory<'a, TEvent, TState, TData>, TData), Self> {
+        if Arc::get_mut(&mut self.transitions).is_none() || Arc::get_mut(&mut self.definition).is_none() {
+            return Err(self);
+        }
+        let StateMachine { state, transitions, data, cycle, definition, .. } = self;
+        let factory = StateMachineFactory {
+            cycle,
+            transitions: Arc::into_inner(transitions).expect("checked to be unique"),
+            definition: Arc::into_inner(definition).expect("checked to be unique"),
+            initial_state: Some(state),
+            strict: false,
+        };
+        Ok((factory, data))
+    }
+
+    /// Returns the candidate Events that the State Machine would accept in its current state and
+    /// with its current data, for example to enable only the relevant buttons of a user interface.
+    /// An Event is accepted when an event Transition from the current state matches it, or when
+    /// the predicate of a guarded Transition from the current state passes for it, provided that
+    /// the pre-transition hooks allow that Transition. Transitions that apply to every Event
+    /// without a predicate, such as loggers, don't count.
+    ///
+    /// Predicates, hooks and calculated targets run as they would while handling the Event, but
+    /// no effects run and the State Machine is left unchanged. Events enqueued or scheduled by
+    /// predicates are discarded. Pure predicates may be cached across calls with
+    /// [StateMachineFactory::with_idempotent_guard_cache].
+    pub fn accepted_events(&self, candidates: &[TEvent]) -> Vec<TEvent>
+    where TEvent: Clone
+    {
+        candidates.iter()
+            .filter(|event| self.accepts(event))
+            .cloned()
+            .collect()
+    }
+
+    /// Determines whether a Transition specifically applies to `event` in the current state
+    fn accepts(&self, event: &TEvent) -> bool {
+        let context = DispatchContext::new();
+        self.transitions.iter().enumerate().any(|(index, transition)| {
+            match transition.event {
+                Some(expected) if !self.definition.event_matches(expected, event) => return false,
+                None if transition.event_predicate.is_none() => return false,
+                _ => {}
+            }
+            if !self.definition.state_matches(&transition.from_state, &self.state) {
+                return false;
+            }
+
+            let to_state = transition.to_state(event, &self.state, &self.data);
+            let transition_effect_data = StateTransitionEffectData {
+                data: &self.data,
+                event,
+                from: &self.state,
+                to: &to_state,
+                context: &context
+            };
+            transition.event_predicate.as_ref().is_none_or(|predicate| self.cached_guard(index, event, || predicate(&transition_effect_data)))
+                && self.definition.pre_hooks.iter().all(|pre_hook| pre_hook(&transition_effect_data))
+        })
+    }
+
+    /// Turns replay mode on or off. While it is on, the effects of Transitions marked with
+    /// [StateMachineFactory::side_effecting] are skipped, while the Transitions themselves are
+    /// still taken and all other effects still run. This lets a State Machine be reconstructed by
+    /// replaying an event log without repeating its effects on the outside world, such as sending
+    /// emails. Replay mode is off by default.
+    pub fn set_replay_mode(&mut self, replay_mode: bool) {
+        self.replay_mode = replay_mode;
+    }
+
+    /// Determines whether replay mode is on; see [StateMachine::set_replay_mode].
+    pub fn is_replaying(&self) -> bool {
+        self.replay_mode
+    }
+
+    /// Reconstructs the State Machine from an event log, handling each of `events` in turn in
+    /// replay mode (see [StateMachine::set_replay_mode]) and returning the state after the last
+    /// one. The log can be decoded from whatever format it was stored in, one Event at a time, as
+    /// the Events are handled. Replay mode is restored to what it was afterwards, including when
+    /// handling an Event fails, in which case the Events after it aren't handled, or panics.
+    pub fn replay_log(&mut self, events: impl IntoIterator<Item = TEvent>) -> Result<&TState, StateMachineError<TState>> {
+        let replay_mode = std::mem::replace(&mut self.replay_mode, true);
+        let restore = Restore::new(self, replay_mode, |sm, replay_mode| sm.replay_mode = replay_mode);
+        let result = events.into_iter().try_for_each(|event| restore.machine.handle_external_event(&event).map(|_| ()));
+        drop(restore);
+        result.map(|()| &self.state)
+    }
+
+    /// Makes `event` sticky: from now on, it is handled before every Event passed to
+    /// [StateMachine::handle_event] and the other ways of handling an Event, until it is cleared
+    /// with [StateMachine::clear_sticky]. This models latched inputs such as a held modifier key.
+    /// Sticky Events are matched like any other Event, in the order they were made sticky, and
+    /// each one is handled along with the Events it enqueues before the next; the incoming Event
+    /// comes last. Events enqueued or scheduled by effects aren't preceded by sticky Events.
+    pub fn set_sticky(&mut self, event: TEvent) {
+        self.sticky.push(event);
+    }
+
+    /// Stops handling `event` before every Event, after it was made sticky with
+    /// [StateMachine::set_sticky]. Does nothing if it isn't sticky.
+    pub fn clear_sticky(&mut self, event: &TEvent)
+    where TEvent: PartialEq
+    {
+        self.sticky.retain(|sticky| sticky != event);
+    }
+
+    /// The sticky Events, in the order they are handled; see [StateMachine::set_sticky].
+    pub fn sticky_events(&self) -> &[TEvent] {
+        &self.sticky
+    }
+
+    /// Runs `batch` with this State Machine's observers suppressed: Transitions taken meanwhile
+    /// don't run the listeners (see [StateMachineFactory::with_listener]) or the transition loggers
+    /// (see [StateMachineFactory::with_global_transition_logger]), so that bulk operations such as
+    /// restoring from an event log don't flood them. Effects, including entry effects, still run,
+    /// as do the dispatch hooks, and metrics are still collected. Returns what `batch` returns.
+    /// Observers are restored afterwards, even if `batch` panics.
+    pub fn with_observers_suppressed<TResult>(&mut self, batch: impl FnOnce(&mut Self) -> TResult) -> TResult {
+        let suppressed = std::mem::replace(&mut self.observers_suppressed, true);
+        let restore = Restore::new(self, suppressed, |sm, suppressed| sm.observers_suppressed = suppressed);
+        batch(restore.machine)
+    }
+
+    /// The number of Transitions taken since the State Machine was built, or since
+    /// [StateMachine::reset_transitions_taken]. As for [DispatchOutcome::Handled], [Same]
+    /// Transitions that don't consume the Event aren't counted. See also
+    /// [StateMachineFactory::with_max_transitions].
+    pub fn transitions_taken(&self) -> usize {
+        self.transitions_total
+    }
+
+    /// Resets [StateMachine::transitions_taken] to zero, closing the circuit breaker of
+    /// [StateMachineFactory::with_max_transitions].
+    pub fn reset_transitions_taken(&mut self) {
+        self.transitions_total = 0;
+    }
+
+    /// The number of Events in a row that ran at least one Transition without changing state, for
+    /// example to detect a State Machine that is stuck. Events that ran [Same] Transitions only,
+    /// including loggers, count; Events that matched no Transition at all don't, and the first
+    /// change of state resets the count to zero. See also
+    /// [StateMachineFactory::with_self_transition_limit].
+    pub fn consecutive_same_count(&self) -> usize {
+        self.consecutive_same
+    }
+
+    /// Determines whether handling the most recent Event changed state, for example to only
+    /// re-render a UI when something changed. Events that only ran [Same] Transitions, including
+    /// [FromState::Any] effects such as loggers, that matched no Transition, or whose Transitions
+    /// kept the state's key (see [StateMachineFactory::with_state_key]) don't count as changing
+    /// state. A change made while handling the Events it enqueued does count, even if the State
+    /// Machine ended up back in the state it started from. False until the first Event is handled.
+    pub fn state_changed(&self) -> bool {
+        self.state_changed
+    }
+
     /// Handles an Event, causing the state machine to execute one or more Transitions.
+    ///
+    /// Any events enqueued by effects while handling the Event (see
+    /// [StateTransitionEffectData::enqueue]) are handled before this method returns. If an effect
+    /// fails, the error is returned straight away, and any events still queued or scheduled while
+    /// handling the Event are discarded. With [StateMachineFactory::lazy_timeouts], an elapsed
+    /// timeout of the current state is handled before the Event.
     pub fn handle_event(&mut self, event: TEvent) -> Result<&TState, StateMachineError<TState>> {
+        self.handle_event_ref(&event)
+    }
+
+    /// Shorthand for [StateMachine::handle_event], reading well with Event constructors such as
+    /// those derived with `EventConstructors`: `sm.fire(Events::digit(2))`.
+    pub fn fire(&mut self, event: TEvent) -> Result<&TState, StateMachineError<TState>> {
+        self.handle_event_ref(&event)
+    }
+
+    /// Handles each of `events` in turn, as with [StateMachine::handle_event], returning the state
+    /// after the last one, as in `sm.fire_all([Events::digit(2), Events::Add, Events::digit(3)])`.
+    /// Handling stops at the first Event that fails, and the Events after it aren't handled.
+    pub fn fire_all(&mut self, events: impl IntoIterator<Item = TEvent>) -> Result<&TState, StateMachineError<TState>> {
+        for event in events {
+            self.handle_external_event(&event)?;
+        }
+        Ok(&self.state)
+    }
+
+    /// Handles a borrowed Event, like [StateMachine::handle_event]. Transitions are matched against
+    /// the Event and effects receive it by reference, so there is no need to clone an Event with a
+    /// large payload, or one borrowed from elsewhere, just to hand it over. Events enqueued by
+    /// effects are still owned by the queue.
+    pub fn handle_event_ref(&mut self, event: &TEvent) -> Result<&TState, StateMachineError<TState>> {
+        self.handle_external_event(event)?;
+        Ok(&self.state)
+    }
+
+    /// Handles an Event passed in by the caller, returning the context it was handled in
+    fn handle_external_event(&mut self, event: &TEvent) -> Result<DispatchContext<TEvent, TState>, StateMachineError<TState>> {
+        self.handle_external_event_in(event, DispatchContext::new())
+    }
+
+    /// Handles an Event passed in by the caller within `context`, returning the context
+    fn handle_external_event_in(&mut self, event: &TEvent, mut context: DispatchContext<TEvent, TState>) -> Result<DispatchContext<TEvent, TState>, StateMachineError<TState>> {
+        let now = self.definition.clock.now();
+        context.since_last_event = self.time_since_last_event();
+        self.last_event_at = Some(now);
+        self.state_changed = false;
+        self.expire_if_inactive(context.since_last_event)?;
+        if self.definition.lazy_timeouts {
+            self.fire_elapsed_timeout(now)?;
+        }
+        if !self.sticky.is_empty() {
+            // Async guards were awaited for the incoming Event only
+            let async_guards = context.async_guards.take();
+            let sticky = std::mem::take(&mut self.sticky);
+            // Put back even if an effect panics, as the sticky Events aren't handled otherwise
+            let restore = Restore::new(self, sticky, |sm, sticky| sm.sticky = sticky);
+            let result = restore.saved.iter().flatten().try_for_each(|sticky_event| restore.machine.handle_event_in(sticky_event, &context));
+            drop(restore);
+            result?;
+            context.async_guards.replace(async_guards);
+        }
+        self.handle_event_in(event, &context)?;
+        #[cfg(feature = "binary-log")]
+        self.log_event(event);
+        Ok(context)
+    }
+
+    /// Handles an Event and then every Event enqueued while doing so, within `context`
+    fn handle_event_in(&mut self, event: &TEvent, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        context.depth.set(0);
+        self.dispatch_or_defer(event, context)?;
+        context.async_guards.take();
+        let mut queued_handled = 0;
+        while let Some(queued_event) = context.next_event() {
+            if self.definition.queue_limit.is_some_and(|limit| queued_handled >= limit) {
+                return Err(StateMachineError::QueueLimitExceeded(self.state.clone(), queued_handled));
+            }
+            queued_handled += 1;
+            self.dispatch_or_defer(&queued_event, context)?;
+        }
+        self.schedule_events(context.scheduled.take());
+        Ok(())
+    }
+
+    /// Evaluates the transitions for a single Event
+    fn dispatch(&mut self, event: &TEvent, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        for hook in &self.definition.before_dispatch {
+            hook(event, &self.state);
+        }
+        // Effects may change the data that cached predicate outcomes depend on
+        if self.definition.guard_cache {
+            self.invalidate_guard_cache();
+        }
+        #[cfg(feature = "metrics")]
+        self.metrics.record_event(&self.state, event);
+        self.record_event(event);
+
+        let mut guards_evaluated = 0;
+        let mut transitions_taken = 0;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(target: "statement", "dispatch", event = self.definition.describe_event(event).as_deref(), state = ?self.state).entered();
+        let result = self.check_exclusive_groups(event, context)
+            .and_then(|()| self.evaluate_transitions(event, context, &mut guards_evaluated, &mut transitions_taken));
+        // Tokens handed out by a Transition that failed are for a state that was never entered
+        for token in context.exit_tokens.take() {
+            token.cancel();
+        }
+        trace_decision!(state = ?self.state, transitions_taken, ok = result.is_ok(), "event handled");
+        #[cfg(feature = "metrics")]
+        self.metrics.record_guards(guards_evaluated);
+
+        if !self.definition.after_dispatch.is_empty() {
+            let outcome = match &result {
+                Ok(()) if transitions_taken == 0 => DispatchOutcome::Rejected,
+                Ok(()) => DispatchOutcome::Handled(transitions_taken),
+                Err(error) => DispatchOutcome::Failed(error)
+            };
+            for hook in &self.definition.after_dispatch {
+                hook(event, &self.state, &outcome);
+            }
+        }
+        result
+    }
+
+    /// Evaluates the transitions for a single Event, counting the predicates evaluated and the
+    /// Transitions other than [Same] Transitions that were taken
+    fn evaluate_transitions(&mut self, event: &TEvent, context: &DispatchContext<TEvent, TState>, guards_evaluated: &mut usize, transitions_taken: &mut usize) -> Result<(), StateMachineError<TState>> {
+        // Set once an exclusive Transition fires; from then on only Same transitions are considered
+        let mut exclusive_fired = false;
+        // Set once a consuming Transition fires; no further Transitions are considered at all
+        let mut consumed = false;
+        let mut any_transition = false;
+        let mut any_state_change = false;
+        // The overriding Transitions taken so far, whose shadowed Transitions are skipped
+        let mut overrides_taken = Vec::new();
+        // Set for the last pass, over the fallback Transitions only, once no other Transition
+        // matched the Event
+        let mut fallbacks = false;
+        // Set once a Transition other than a fallback matches the state, the Event and its
+        // predicate, whether or not it changes state
+        let mut matched_non_fallback = false;
+        let definition = self.definition.clone();
         loop {
             let mut transition_occurred = false;
-            for transition in self.transitions.deref() {
+            // Only the Transitions that may apply from the current State are considered, if the
+            // definition was compiled
+            let mut candidates = definition.candidates(&self.state);
+            let mut next = 0;
+            while let Some(index) = dispatch::next_candidate(candidates, next, self.transitions.len()) {
+                next = index + 1;
+                let transition = &self.transitions[index];
+                if transition.fallback != fallbacks {
+                    continue;
+                }
+                if exclusive_fired && !matches!(transition.get_to_state, Target::Same) {
+                    trace_decision!(position = index, outcome = "after exclusive", "candidate transition");
+                    continue;
+                }
+                if transition.shadowed_by.iter().any(|overriding| overrides_taken.contains(overriding)) {
+                    trace_decision!(position = index, outcome = "shadowed", "candidate transition");
+                    continue;
+                }
 
-                // Determine if the current state matches the from_state of the transition
-                let from_state_matches = match &transition.from_state {
-                    FromState::Any => true,
-                    FromState::AnyOf(states) => states.iter().any(|s|s == &self.state),
-                    FromState::From(state) => state == &self.state
-                };
+                // Event transitions only apply to matching Events
+                if let Some(expected) = transition.event {
+                    if !self.definition.event_matches(expected, event) {
+                        trace_decision!(position = index, outcome = "event mismatch", "candidate transition");
+                        continue;
+                    }
+                }
 
                 // If the from_state matches, we need to consider whether this transition should execute
-                if from_state_matches {
+                if !self.definition.state_matches(&transition.from_state, &self.state) {
+                    trace_decision!(position = index, outcome = "state mismatch", "candidate transition");
+                } else {
 
                     // Determine the result state and whether we need to proceed after this transition
                     // If proceed is true OR this transition changes the state, we will continue to
                     // evaluate further transitions after executing this one.
-                    let to_state = match &transition.get_to_state {
-                        To(to_state) => to_state.clone(),
-                        Calc(get_to_state) => {
-                            let data = StateTransitionToStateData {
-                                data: &mut self.data,
-                                event: &event,
-                                from: &self.state,
-                            };
-                            get_to_state.deref()(data)
-                        },
-                        Same => self.state.clone()
-                    };
+                    let to_state = transition.to_state(event, &self.state, &self.data);
+                    let skip_effects = self.replay_mode && transition.side_effecting;
 
                     // This sets up a data item to pass to the Predicate method (if any) and the
                     // Effect method (if any)
                     let transition_effect_data = StateTransitionEffectData {
                         data: &mut self.data,
-                        event: &event,
+                        event,
                         from: &self.state,
-                        to: &to_state
+                        to: &to_state,
+                        context
                     };
 
                     // If there is a Predicate on this Transition, execute it and if it returns
-                    // false, skip to the next Transition
+                    // false, run the else effect (if any) and skip to the next Transition
                     if let Some(predicate) = &transition.event_predicate {
-                        if !predicate(&transition_effect_data) {
+                        if self.definition.guard_budget.is_some_and(|budget| *guards_evaluated >= budget) {
+                            return Err(StateMachineError::GuardBudgetExceeded(self.state.clone(), *guards_evaluated));
+                        }
+                        *guards_evaluated += 1;
+                        context.in_guard.set(true);
+                        let passed = predicate(&transition_effect_data);
+                        context.in_guard.set(false);
+                        if !passed {
+                            trace_decision!(position = index, outcome = "guard failed", "candidate transition");
+                            if let Some(else_effect) = transition.else_effect.as_ref().filter(|_| !skip_effects) {
+                                let result = else_effect(StateTransitionEffectData { to: &self.state, ..transition_effect_data });
+                                trace_decision!(position = index, effect = "else effect", ok = result.is_ok(), "effect run");
+                                result.map_err(|e| StateMachineError::EffectError(self.state.clone(), self.state.clone(), e))?;
+                            }
+                            continue;
+                        }
+                    }
+                    if !fallbacks {
+                        matched_non_fallback = true;
+                    }
+
+                    // Counted Transitions only fire once their Event has occurred often enough
+                    if let Some(required) = transition.required_count {
+                        if self.event_counts.len() <= index {
+                            self.event_counts.resize(index + 1, 0);
+                        }
+                        self.event_counts[index] += 1;
+                        if self.event_counts[index] < required {
+                            trace_decision!(position = index, outcome = "counted", count = self.event_counts[index], "candidate transition");
                             continue;
                         }
+                        self.event_counts[index] = 0;
+                    }
+
+                    // Pre-transition hooks may veto the Transition now that it would fire
+                    for pre_hook in &self.definition.pre_hooks {
+                        if !pre_hook(&transition_effect_data) {
+                            return Err(StateMachineError::HookRejected(self.state.clone(), to_state.clone()));
+                        }
+                    }
+                    trace_decision!(position = index, outcome = "matched", to = ?to_state, "candidate transition");
+
+                    if !transition.authorised(context) {
+                        return Err(StateMachineError::Unauthorized(self.state.clone(), to_state));
                     }
 
-                    // If there is an Effect on this Transition, execute it
-                    if let Some(effect) = &transition.effect {
-                        effect(transition_effect_data)
-                            .map_err(|e| StateMachineError::EffectError(self.state.clone(), to_state.clone(), e))?;
+                    // The circuit breaker stops the Transition before it has any effect
+                    let counted = !matches!(transition.get_to_state, Target::Same) || transition.consuming;
+                    if counted && self.definition.max_transitions.is_some_and(|limit| self.transitions_total >= limit) {
+                        return Err(StateMachineError::CircuitOpen(self.state.clone(), self.transitions_total));
+                    }
+
+                    // If there is an Effect on this Transition, execute it, unless it is skipped
+                    // while replaying
+                    if let Some(effect) = transition.effect.as_ref().filter(|_| !skip_effects) {
+                        let result = effect(transition_effect_data);
+                        trace_decision!(position = index, effect = "effect", ok = result.is_ok(), "effect run");
+                        result.map_err(|e| StateMachineError::EffectError(self.state.clone(), to_state.clone(), e))?;
                     }
 
                     // If proceed is false or we changed state, mark transition_occurred as true so
                     // that we evaluate all of the transitions again.
-                    if &self.state != &to_state {
-                        self.state = to_state;
+                    let state_changed = !self.definition.same_state(&self.state, &to_state);
+                    if state_changed {
+                        // Exit, transit and entry effects run before the state changes, so that
+                        // a failing one leaves the State Machine where it was
+                        self.run_region_effects(false, event, &to_state, context)?;
+                        self.run_transit_effects(event, &to_state, context)?;
+                        self.run_region_effects(true, event, &to_state, context)?;
+                        self.run_entry_effects(event, &to_state, context)?;
+                    }
+                    for invariant in &self.definition.invariants {
+                        invariant(&to_state, &self.data)
+                            .map_err(|violation| StateMachineError::InvariantViolated(self.state.clone(), to_state.clone(), violation))?;
+                    }
+                    self.run_listeners(event, &to_state, context);
+                    any_transition = true;
+                    #[cfg(feature = "coverage")]
+                    coverage::record(&mut self.coverage, index);
+                    if state_changed {
+                        self.previous_state = Some(std::mem::replace(&mut self.state, to_state));
+                        self.entered_at = Some(self.definition.clock.now());
+                        self.event_counts.clear();
+                        // Descend into initial substates for as long as there are any; each
+                        // can be entered at most once, so that cycles can't loop forever
+                        for _ in 0..self.definition.initial_substates.len() {
+                            let Some((_, child)) = self.definition.initial_substates.iter()
+                                .find(|(parent, _)| self.definition.same_state(parent, &self.state)) else {
+                                break;
+                            };
+                            self.run_entry_effects(event, child, context)?;
+                            self.state = child.clone();
+                        }
+                        // Tokens handed out for the state left are cancelled
+                        for token in self.exit_tokens.drain(..) {
+                            token.cancel();
+                        }
+                        candidates = definition.candidates(&self.state);
                         transition_occurred = true;
+                        any_state_change = true;
+                    } else if self.state != to_state {
+                        // The state keeps its key, but carries different data
+                        self.state = to_state;
+                    }
+                    self.exit_tokens.extend(context.exit_tokens.take());
+
+                    if counted {
+                        *transitions_taken += 1;
+                        self.transitions_total += 1;
+                    }
+                    if !transition.overrides.is_empty() {
+                        overrides_taken.push(index);
+                    }
+                    if transition.exclusive {
+                        exclusive_fired = true;
+                    }
+                    if transition.consuming {
+                        consumed = true;
+                        break;
                     }
                 }
             }
 
             // If no transition occurred, we can end evaluation
-            if !self.cycle || !transition_occurred {
+            if fallbacks || !self.cycle || !transition_occurred || exclusive_fired || consumed {
+                if !fallbacks && !matched_non_fallback && definition.has_fallbacks {
+                    fallbacks = true;
+                    continue;
+                }
                 break;
             }
         }
-        Ok(&self.state)
+
+        if any_state_change {
+            self.consecutive_same = 0;
+            self.state_changed = true;
+        } else if any_transition {
+            self.consecutive_same += 1;
+            if self.definition.self_transition_limit.is_some_and(|limit| self.consecutive_same > limit) {
+                return Err(StateMachineError::SelfTransitionLimitExceeded(self.state.clone(), self.consecutive_same));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Puts a value taken out of a State Machine back once dropped, including while unwinding from a
+/// panic in an effect, for updates that must be undone however the operation ends
+struct Restore<'m, TMachine, T> {
+    machine: &'m mut TMachine,
+    saved: Option<T>,
+    restore: fn(&mut TMachine, T),
+}
+
+impl <'m, TMachine, T> Restore<'m, TMachine, T> {
+    fn new(machine: &'m mut TMachine, saved: T, restore: fn(&mut TMachine, T)) -> Self {
+        Self {
+            machine,
+            saved: Some(saved),
+            restore,
+        }
+    }
+}
+
+impl <TMachine, T> Drop for Restore<'_, TMachine, T> {
+    fn drop(&mut self) {
+        if let Some(saved) = self.saved.take() {
+            (self.restore)(self.machine, saved);
+        }
     }
 }
 
 /// Locked Factory for StateMachines. This struct is created by calling .lock() on a
 /// StateMachineFactory, usually after defining all transitions needed.
+///
+/// A locked factory's definition is sealed: it only offers ways to build State Machines, so
+/// registering a Transition after locking is a compile error rather than a silent no-op.
+///
+/// ```compile_fail
+/// # use statement::StateMachineFactory;
+/// #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+/// enum States { Off, On }
+///
+/// let locked = StateMachineFactory::<(), States, ()>::new()
+///     .with_auto_transition(States::Off, States::On)
+///     .lock();
+/// let locked = locked.with_auto_transition(States::On, States::Off);
+/// ```
 pub struct LockedStateMachineFactory<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData = ()> {
     transitions: Arc<Vec<StateMachineTransition<'a, TEvent, TState, TData>>>,
+    definition: Arc<Definition<'a, TEvent, TState, TData>>,
     cycle: bool,
+    initial_state: Option<TState>,
 }
 
 impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> LockedStateMachineFactory<'a, TEvent, TState, TData> {
     /// Builds a StateMachine with a specified initial state and initial data.
     pub fn build(&self, initial_state: TState, initial_data: TData) -> StateMachine<'a, TEvent, TState, TData> {
-        StateMachine::new(self.cycle, initial_state, initial_data).with_transitions(self.transitions.clone())
+        StateMachine::new(self.cycle, initial_state, initial_data)
+            .with_transitions(self.transitions.clone())
+            .with_definition(self.definition.clone())
+    }
+
+    /// Builds a StateMachine in the initial state recorded with [StateMachineFactory::with_initial],
+    /// with the specified initial data. Use [LockedStateMachineFactory::build] to start from a
+    /// different state.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no initial state was recorded.
+    pub fn build_initial(&self, initial_data: TData) -> StateMachine<'a, TEvent, TState, TData> {
+        let initial_state = self.initial_state.clone()
+            .expect("build_initial requires an initial state recorded with StateMachineFactory::with_initial");
+        self.build(initial_state, initial_data)
+    }
+
+    /// The initial state recorded with [StateMachineFactory::with_initial], if any.
+    pub fn initial_state(&self) -> Option<&TState> {
+        self.initial_state.as_ref()
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a> LockedStateMachineFactory<'a, TEvent, TState, ()> {
+    /// Builds a StateMachine without any data, for factories created with
+    /// [StateMachineFactory::new_no_data].
+    pub fn build_no_data(&self, initial_state: TState) -> StateMachine<'a, TEvent, TState> {
+        self.build(initial_state, ())
+    }
+}
+
+/// Behaviour shared by every StateMachine built from the same factory, beyond its list of
+/// Transitions.
+struct Definition<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    /// The States registered with [StateMachineFactory::with_states], in registration order
+    states: Vec<TState>,
+    /// Parent States along with the substate they are entered through
+    initial_substates: Vec<(TState, TState)>,
+    /// Substates declared with [StateMachineFactory::with_substate], along with their parent
+    parent_states: Vec<(TState, TState)>,
+    /// Groups of Transitions of which exactly one applies; see [StateMachineFactory::exclusive_group]
+    exclusive_groups: Vec<group::ExclusiveGroup>,
+    entry_effects: Vec<EntryEffect<'a, TEvent, TState, TData>>,
+    /// Effects run between leaving one state and entering another
+    transit_effects: Vec<EntryEffect<'a, TEvent, TState, TData>>,
+    /// Effects run on entering or leaving a set of states
+    region_effects: Vec<region_effect::RegionEffect<'a, TEvent, TState, TData>>,
+    pre_hooks: Vec<EventPredicate<'a, TEvent, TState, TData>>,
+    /// Listeners along with their priority, highest priority first and in registration order
+    /// within a priority
+    listeners: Vec<(u32, TransitionListener<'a, TEvent, TState, TData>)>,
+    transition_loggers: Vec<TransitionLogger<'a, TEvent, TState>>,
+    invariants: Vec<Invariant<'a, TState, TData>>,
+    before_dispatch: Vec<BeforeDispatchHook<'a, TEvent, TState>>,
+    after_dispatch: Vec<AfterDispatchHook<'a, TEvent, TState>>,
+    /// Compares Events by key, if a key function was set
+    event_key: Option<EventComparator<'a, TEvent>>,
+    /// Compares Events with [PartialEq]; set as soon as an event Transition is added, as only
+    /// those require Events to implement it
+    event_eq: Option<EventComparator<'a, TEvent>>,
+    /// Clones Events; set as soon as an Event is deferred or the last Event is recorded, as only
+    /// those require Events to implement [Clone]
+    event_clone: Option<EventCloner<'a, TEvent>>,
+    /// True if State Machines remember the last Event handled, see
+    /// [StateMachineFactory::record_last_event]
+    record_last_event: bool,
+    /// True if State Machines log the Events they handle, see `StateMachineFactory::record_event_log`
+    #[cfg(feature = "binary-log")]
+    record_event_log: bool,
+    /// The Events deferred in each State, see [StateMachineFactory::with_deferred_event]
+    deferred_events: Vec<(FromState<TState>, &'a TEvent)>,
+    /// Compares States by key, if a key function was set
+    state_key: Option<StateComparator<'a, TState>>,
+    /// The most predicates evaluated for one Event, if limited
+    guard_budget: Option<usize>,
+    /// The most enqueued Events handled for one external Event, if limited
+    queue_limit: Option<usize>,
+    /// The most Events in a row handled without changing state, if limited
+    self_transition_limit: Option<usize>,
+    /// The most Transitions a State Machine may take over its lifetime, if limited
+    max_transitions: Option<usize>,
+    clock: Arc<dyn Clock>,
+    timeouts: Vec<Timeout<TEvent, TState>>,
+    /// True if timeouts are checked whenever an Event is handled
+    lazy_timeouts: bool,
+    /// The state entered after a period without Events, if set
+    inactivity_timeout: Option<timer::InactivityTimeout<'a, TState, TData>>,
+    /// The Transitions that may apply from each registered State, if compiled
+    dispatch_index: Option<dispatch::DispatchIndex>,
+    /// True if [StateMachine::accepted_events] caches predicate outcomes
+    guard_cache: bool,
+    /// How long cached predicate outcomes are kept, if limited
+    guard_cache_ttl: Option<Duration>,
+    /// True if any Transition was added with [StateMachineFactory::with_fallback_transition_effect]
+    has_fallbacks: bool,
+    /// Metadata attached to States with [StateMachineFactory::with_state_metadata]
+    state_metadata: Vec<(TState, Vec<(String, String)>)>,
+    /// Describes Events in the decision log, if set with [StateMachineFactory::with_traced_events]
+    #[cfg(feature = "tracing")]
+    event_format: Option<EventFormatter<'a, TEvent>>,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Default for Definition<'a, TEvent, TState, TData> {
+    fn default() -> Self {
+        Self {
+            states: Vec::new(),
+            initial_substates: Vec::new(),
+            parent_states: Vec::new(),
+            exclusive_groups: Vec::new(),
+            entry_effects: Vec::new(),
+            transit_effects: Vec::new(),
+            region_effects: Vec::new(),
+            pre_hooks: Vec::new(),
+            listeners: Vec::new(),
+            transition_loggers: Vec::new(),
+            invariants: Vec::new(),
+            before_dispatch: Vec::new(),
+            after_dispatch: Vec::new(),
+            event_key: None,
+            event_eq: None,
+            event_clone: None,
+            record_last_event: false,
+            #[cfg(feature = "binary-log")]
+            record_event_log: false,
+            deferred_events: Vec::new(),
+            state_key: None,
+            guard_budget: None,
+            queue_limit: None,
+            self_transition_limit: None,
+            max_transitions: None,
+            clock: Arc::new(SystemClock),
+            timeouts: Vec::new(),
+            lazy_timeouts: false,
+            inactivity_timeout: None,
+            dispatch_index: None,
+            guard_cache: false,
+            guard_cache_ttl: None,
+            has_fallbacks: false,
+            state_metadata: Vec::new(),
+            #[cfg(feature = "tracing")]
+            event_format: None,
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Definition<'a, TEvent, TState, TData> {
+    /// Determines whether `event` matches the Event `expected` by an event Transition
+    fn event_matches(&self, expected: &TEvent, event: &TEvent) -> bool {
+        let compare = self.event_key.as_ref()
+            .or(self.event_eq.as_ref())
+            .expect("event comparison is set whenever an event transition is added");
+        compare(expected, event)
+    }
+
+    /// Determines whether two States are the same, by key if a key function was set
+    fn same_state(&self, a: &TState, b: &TState) -> bool {
+        match &self.state_key {
+            Some(compare) => compare(a, b),
+            None => a == b
+        }
+    }
+
+    /// Determines whether `state` is one of the States described by `from_state`, comparing States
+    /// with [Definition::same_state]
+    fn state_matches(&self, from_state: &FromState<TState>, state: &TState) -> bool {
+        match &self.state_key {
+            Some(compare) => from_state.matches_by(state, compare.as_ref()),
+            None => from_state.matches(state)
+        }
     }
 }
 
+/// An Effect that runs whenever the State Machine enters `to_state` from a matching state
+struct EntryEffect<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    to_state: TState,
+    from_state: FromState<TState>,
+    effect: TransitionEffect<'a, TEvent, TState, TData>,
+}
+
 /// Factory for StateMachines. This struct can be used to define a series of Transitions that
 /// may be subsequently used to create multiple state machine instances with those same
 /// transitions.
 #[derive(Default)]
-pub struct StateMachineFactory<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+pub struct StateMachineFactory<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData = ()> {
     cycle: bool,
     transitions: Vec<StateMachineTransition<'a, TEvent, TState, TData>>,
+    definition: Definition<'a, TEvent, TState, TData>,
+    initial_state: Option<TState>,
+    strict: bool,
 }
 
 impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
@@ -214,6 +1290,9 @@ impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + Partia
         Self {
             cycle: false,
             transitions: Vec::new(),
+            definition: Definition::default(),
+            initial_state: None,
+            strict: false,
         }
     }
 
@@ -221,17 +1300,199 @@ impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + Partia
     pub fn cycle(self, cycle: bool) -> Self {
         Self {
             cycle,
-            transitions: self.transitions
+            ..self
+        }
+    }
+
+    /// Sets the key function used to decide whether an Event matches the Event of a Transition
+    /// added with [StateMachineFactory::with_event_transition] or
+    /// [StateMachineFactory::with_event_transition_effect]. Events match when their keys are equal,
+    /// which lets the key ignore an Event's payload or group related Events together. The key
+    /// applies to all event Transitions, whether they were added before or after calling this.
+    pub fn with_event_key<TKey: Eq>(mut self, key: impl Fn(&TEvent) -> TKey + Send + Sync + 'a) -> Self {
+        self.definition.event_key = Some(Arc::new(move |a, b| key(a) == key(b)));
+        self
+    }
+
+    /// Sets the key function used to decide whether two States are the same while handling Events,
+    /// for States that carry data (such as `Connected { peer: SocketAddr }`) where Transitions should
+    /// only depend on the kind of State, for example its enum variant through
+    /// [std::mem::discriminant]. The State Machine still holds the full State, data included.
+    ///
+    /// The from_state of a Transition and the State of an entry effect or timeout then only need
+    /// the right key: any data they carry is ignored. A [Calc] Transition is the way to construct
+    /// the full target State, for example from the Event's payload. A Transition into a State with
+    /// the same key as the current one replaces the current State without counting as a change of
+    /// State, so it doesn't run entry effects or restart timeouts. Analysis and exports still tell
+    /// States apart by their full value.
+    pub fn with_state_key<TKey: Eq>(mut self, key: impl Fn(&TState) -> TKey + Send + Sync + 'a) -> Self {
+        self.definition.state_key = Some(Arc::new(move |a, b| key(a) == key(b)));
+        self
+    }
+
+    /// Sets the function used to decide whether two States are the same while handling Events,
+    /// instead of [PartialEq], as in `.with_state_eq(|a, b| a.id == b.id)`. This behaves like
+    /// [StateMachineFactory::with_state_key] for States whose sameness isn't easily expressed as a
+    /// key; `eq` should be an equivalence relation. Setting one replaces the other.
+    pub fn with_state_eq(mut self, eq: impl Fn(&TState, &TState) -> bool + Send + Sync + 'a) -> Self {
+        self.definition.state_key = Some(Arc::new(eq));
+        self
+    }
+
+    /// Limits the number of predicates evaluated while handling a single Event (each Event enqueued
+    /// by an effect has a budget of its own). Once `budget` predicates have been evaluated,
+    /// handling fails with [StateMachineError::GuardBudgetExceeded] instead of evaluating another.
+    /// This is a safety valve against expensive predicates in hot paths; by default the number of
+    /// predicates is unbounded. With the `metrics` feature, `Metrics::max_guards_per_event` helps
+    /// to choose a budget.
+    pub fn with_guard_budget(mut self, budget: usize) -> Self {
+        self.definition.guard_budget = Some(budget);
+        self
+    }
+
+    /// Limits the number of enqueued Events (see [StateTransitionEffectData::enqueue] and
+    /// [StateMachineFactory::with_entry_event]) handled on behalf of a single call to
+    /// [StateMachine::handle_event]. Handling another one fails with
+    /// [StateMachineError::QueueLimitExceeded], and the Events still queued are discarded. This
+    /// stops effects that keep raising Events for one another from looping forever; by default the
+    /// number is unbounded.
+    pub fn with_queue_limit(mut self, limit: usize) -> Self {
+        self.definition.queue_limit = Some(limit);
+        self
+    }
+
+    /// Limits the total number of Transitions a State Machine may take over its lifetime, as
+    /// counted by [StateMachine::transitions_taken], including those taken automatically while
+    /// cycling or for enqueued Events. Once the limit is reached, taking another Transition fails
+    /// with [StateMachineError::CircuitOpen] before its effect runs, until the count is reset with
+    /// [StateMachine::reset_transitions_taken]. This is a circuit breaker for runaway loops that a
+    /// limit on a single call might miss; by default the number is unbounded.
+    pub fn with_max_transitions(mut self, limit: usize) -> Self {
+        self.definition.max_transitions = Some(limit);
+        self
+    }
+
+    /// Adds an invariant, which checks the State and data every Transition leads to, for
+    /// properties that must hold whichever Transition was taken, such as "the stored value is
+    /// never NaN". Where a predicate decides whether a Transition applies, an invariant validates
+    /// its result: it runs after the Transition's effect and any entry effects, before the
+    /// listeners. If it returns an error, handling fails with
+    /// [StateMachineError::InvariantViolated] and the State Machine stays in the state it was in,
+    /// although changes the effects made to the data aren't undone. Invariants run in registration
+    /// order, and the first one to fail stops the others.
+    pub fn with_invariant(mut self, invariant: impl Fn(&TState, &TData) -> Result<(), String> + Send + Sync + 'a) -> Self {
+        self.definition.invariants.push(Arc::new(invariant));
+        self
+    }
+
+    /// Limits the number of Events in a row that may be handled without changing state, as counted
+    /// by [StateMachine::consecutive_same_count]. Handling an Event that takes the count above
+    /// `limit` fails with [StateMachineError::SelfTransitionLimitExceeded], after its effects have
+    /// run; so does every following Event until the state changes.
+    pub fn with_self_transition_limit(mut self, limit: usize) -> Self {
+        self.definition.self_transition_limit = Some(limit);
+        self
+    }
+
+    /// Records the state that State Machines built from this factory start in, so that they can be
+    /// built with [LockedStateMachineFactory::build_initial] without repeating it. The initial state
+    /// is also available to tooling through [StateMachineFactory::initial_state].
+    pub fn with_initial(self, initial_state: TState) -> Self {
+        Self {
+            initial_state: Some(initial_state),
+            ..self
+        }
+    }
+
+    /// The initial state recorded with [StateMachineFactory::with_initial], if any.
+    pub fn initial_state(&self) -> Option<&TState> {
+        self.initial_state.as_ref()
+    }
+
+    /// Registers the States this State Machine can occupy, assigning each one a [StateId] in
+    /// registration order. States that are already registered keep their existing [StateId].
+    pub fn with_states(mut self, states: impl IntoIterator<Item = TState>) -> Self
+    {
+        for state in states {
+            if !self.definition.states.contains(&state) {
+                self.definition.states.push(state);
+            }
+        }
+        self
+    }
+
+    /// Returns the [StateId] assigned to a State registered with [StateMachineFactory::with_states],
+    /// or `None` if the State was never registered. This compares `state` with each registered
+    /// State in turn, so it takes time proportional to the number of States; resolving an id with
+    /// [StateMachineFactory::id_to_state] takes constant time.
+    pub fn state_id(&self, state: &TState) -> Option<StateId> {
+        self.definition.states.iter().position(|s| s == state).map(StateId)
+    }
+
+    /// Returns the State that was assigned the provided [StateId], or `None` if no State was
+    /// registered with that id.
+    pub fn id_to_state(&self, id: StateId) -> Option<&TState> {
+        self.definition.states.get(id.0)
+    }
+
+    /// Enables strict mode, in which [StateMachineFactory::try_lock] refuses a definition where
+    /// more than one effect applies to the same State and Event. Since every matching Transition
+    /// runs, two broad effects (such as two [FromState::Any] effects) can otherwise both fire and
+    /// cause a side effect twice.
+    ///
+    /// Effects apply to the same Event when both are registered for Events that match, or when
+    /// either Transition applies to every Event. Predicates can't be evaluated statically, so two
+    /// guarded effects from the same State always overlap. Effects registered with
+    /// [StateMachineFactory::with_cross_cutting_effect],
+    /// [StateMachineFactory::with_exclusive_transition_effect] or
+    /// [StateMachineFactory::with_consuming_transition_effect] are deliberate about overlapping,
+    /// and aren't checked. Only the States known to the factory are checked, as for
+    /// [StateMachineFactory::find_cycles].
+    pub fn strict(self) -> Self {
+        Self {
+            strict: true,
+            ..self
         }
     }
 
     /// Creates a LockedStateMachineFactory which can be used to build StateMachine instances
-    /// with the Transitions defined in this StateMachineFactory.
+    /// with the Transitions defined in this StateMachineFactory. This consumes the factory, so no
+    /// Transitions can be added once it is locked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [StateMachineFactory::try_lock] fails: if [StateMachineFactory::strict] is enabled
+    /// and the definition has overlapping effects, if the dependencies declared with
+    /// [StateMachineFactory::after] can't be met, or if a Transition was registered by mistake.
+    /// Use [StateMachineFactory::try_lock] to handle the error instead.
     pub fn lock(self) -> LockedStateMachineFactory<'a, TEvent, TState, TData> {
-        LockedStateMachineFactory {
-            cycle: self.cycle,
-            transitions: Arc::new(self.transitions)
+        self.try_lock().unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /// Creates a LockedStateMachineFactory like [StateMachineFactory::lock], but returns an error if
+    /// [StateMachineFactory::strict] is enabled and the definition has overlapping effects, or if
+    /// the dependencies declared with [StateMachineFactory::after] can't be met.
+    ///
+    /// Registration mistakes that would otherwise go unnoticed are reported here too, rather than
+    /// by each `with_*` method, so that defining a factory doesn't need `?` on every call: a
+    /// Transition from an empty [FromState::AnyOf] fails with [DefinitionError::EmptyAnyOf].
+    pub fn try_lock(mut self) -> Result<LockedStateMachineFactory<'a, TEvent, TState, TData>, DefinitionError<TState>> {
+        if let Some(position) = self.transitions.iter().position(|transition| matches!(&transition.from_state, FromState::AnyOf(states) if states.is_empty())) {
+            return Err(DefinitionError::EmptyAnyOf(position));
         }
+        if self.strict {
+            if let Some((state, first, second)) = self.find_overlapping_effects() {
+                return Err(DefinitionError::OverlappingEffects(state, first, second));
+            }
+        }
+        order::order_by_dependencies(&mut self.transitions)?;
+        self.definition.resolve_exclusive_groups(&self.transitions)?;
+        Ok(LockedStateMachineFactory {
+            cycle: self.cycle,
+            transitions: Arc::new(self.transitions),
+            definition: Arc::new(self.definition),
+            initial_state: self.initial_state,
+        })
     }
 
     /// Adds an externally-created transition to this `StateMachineFactory`
@@ -255,27 +1516,304 @@ impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + Partia
     /// this State Machine has cycle enabled, this transition will execute automatically, essentially
     /// skipping the From state after executing the side effect. If Cycle is not enabled, the State
     /// Machine will transition to the To state with any future event.
-    pub fn with_transition_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + 'a) -> Self
+    pub fn with_transition_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
     {
-        self.transitions.push(StateMachineTransition::new(None, from_state.into(), get_to_state.into(), Some(Box::new(effect))));
+        self.transitions.push(StateMachineTransition::new(None, from_state.into(), get_to_state.into(), Some(Arc::new(effect))));
         self
     }
 
-    /// Adds a Transition to the State Machine definition with a predicate and no Side Effect. This
+    /// Adds a Transition with no predicate, like `.with_transition_effect(..)`, whose effect is
+    /// passed `param` along with the effect data, as in
+    /// `.with_bound_effect(precision.clone(), Any, Same, |precision, d| ..)`. Effects sharing
+    /// configuration, such as a rounding precision, can each be bound to the same [Arc] instead of
+    /// every closure capturing its own copy.
+    pub fn with_bound_effect<TParam: Send + Sync + 'a>(mut self, param: Arc<TParam>, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(&TParam, StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.transitions.push(StateMachineTransition::new(None, from_state.into(), get_to_state.into(), Some(Arc::new(move |d| effect(&param, d)))));
+        self
+    }
+
+    /// Adds a fallback Transition with a side effect and no predicate, which is only considered
+    /// once no other Transition matched the Event from the current state, giving a State a default
+    /// for the Events it has no specific handling for, such as moving to an error State. A
+    /// Transition matches once its state, Event and predicate do, including [Same] Transitions
+    /// such as `(Any, Same)` effects, so observe Events with
+    /// [StateMachineFactory::with_global_transition_logger] or a listener instead, as those don't
+    /// prevent fallbacks. Fallback Transitions are then
+    /// evaluated in registration order, in a single pass, even if the State Machine has cycle
+    /// enabled.
+    pub fn with_fallback_transition_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut transition = StateMachineTransition::new(None, from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.fallback = true;
+        self.transitions.push(transition);
+        self.definition.has_fallbacks = true;
+        self
+    }
+
+    /// Adds one Transition with a side effect and no predicate for each `(from_state, to_state)`
+    /// pair, all sharing the same effect. This behaves like calling
+    /// `.with_transition_effect(..)` once per pair, but registering the effect once means the
+    /// Transitions can't drift apart when it is edited.
+    pub fn with_transition_effect_for<TFrom: Into<FromState<TState>>, TTo: Into<ToState<TEvent, TState, TData>>>(mut self, pairs: impl IntoIterator<Item = (TFrom, TTo)>, effect: Arc<impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a>) -> Self
+    {
+        for (from_state, get_to_state) in pairs {
+            self.transitions.push(StateMachineTransition::new(None, from_state.into(), get_to_state.into(), Some(effect.clone())));
+        }
+        self
+    }
+
+    /// Marks the effects of the Transition added last as side-effecting, as in
+    /// `.with_transition_effect(..).side_effecting()`: they act on the outside world, for example by
+    /// sending an email, rather than deriving state. Such effects are skipped while a State Machine
+    /// is in replay mode (see [StateMachine::set_replay_mode]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if no Transition has been added yet.
+    pub fn side_effecting(mut self) -> Self {
+        self.transitions.last_mut().expect("side_effecting() marks the Transition added before it").side_effecting = true;
+        self
+    }
+
+    /// Adds a Transition with a side effect and no predicate, like
+    /// `.with_transition_effect(..)`, that is expected to run alongside other effects, such as a
+    /// logger. Such effects are exempt from the checks of [StateMachineFactory::strict].
+    pub fn with_cross_cutting_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut transition = StateMachineTransition::new(None, from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.cross_cutting = true;
+        self.transitions.push(transition);
+        self
+    }
+
+    /// Adds a Transition to the State Machine definition with a predicate and no Side Effect. This
     /// transition will test the predicate for any event and move to the To state if the Predicate
     /// returns true.
-    pub fn with_predicated_transition(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + 'a) -> Self
+    pub fn with_predicated_transition(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a) -> Self
     {
-        self.transitions.push(StateMachineTransition::new(Some(Box::new(event_predicate)), from_state.into(), get_to_state.into(), None));
+        self.transitions.push(StateMachineTransition::new(Some(Arc::new(event_predicate)), from_state.into(), get_to_state.into(), None));
         self
     }
 
     /// Adds a Transition to the State Machine definition with a predicate and a Side Effect. This
     /// transition will test the predicate for any event and execute the Side Effect then move to
     /// the To state if the Predicate returns true.
-    pub fn with_predicated_transition_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + 'a, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + 'a) -> Self
+    pub fn with_predicated_transition_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.transitions.push(StateMachineTransition::new(Some(Arc::new(event_predicate)), from_state.into(), get_to_state.into(), Some(Arc::new(effect))));
+        self
+    }
+
+    /// Adds a Transition with a Side Effect that applies to the Events for which `matcher` returns
+    /// true, such as a range of `Digit` values. This is a predicate that only looks at the Event, so
+    /// it behaves like `.with_predicated_transition_effect(..)`, but states the intent more clearly
+    /// when the match depends on the shape of the Event alone. If `matcher` is a named function,
+    /// exporters such as [StateMachineFactory::to_dot] label the Transition with its name.
+    pub fn with_matching_transition_effect<TMatcher: Fn(&TEvent) -> bool + Send + Sync + 'a>(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, matcher: TMatcher, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut transition = StateMachineTransition::new(Some(Arc::new(move |d| matcher(d.event))), from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.name = function_name::<TMatcher>();
+        self.transitions.push(transition);
+        self
+    }
+
+    /// Adds a Transition with a Side Effect for the Events whose ordinal, as extracted by `ordinal`,
+    /// falls within `range`, as in `.with_event_range_transition_effect(Any, Entering, |e| e.digit_value(), 0..=9, effect)`;
+    /// Events for which `ordinal` returns `None` don't match. This is finer than matching Events by
+    /// key with [StateMachineFactory::with_event_key], which ignores their payload, and coarser than
+    /// matching one Event exactly. Like `.with_matching_transition_effect(..)`, it is a predicated
+    /// Transition, so it is evaluated in registration order along with event Transitions, however
+    /// those match; where a range Transition and an event Transition both move the State Machine
+    /// out of a State for an Event, the one registered first wins (see Matching Multiple
+    /// Transitions). Exporters such as [StateMachineFactory::to_dot] label the Transition with the
+    /// range.
+    pub fn with_event_range_transition_effect<TOrdinal: PartialOrd + 'a, TRange: RangeBounds<TOrdinal> + Debug + Send + Sync + 'a>(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, ordinal: impl Fn(&TEvent) -> Option<TOrdinal> + Send + Sync + 'a, range: TRange, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let name = format!("{:?}", range);
+        let mut transition = StateMachineTransition::new(Some(Arc::new(move |d| ordinal(d.event).is_some_and(|value| range.contains(&value)))), from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.name = Some(name);
+        self.transitions.push(transition);
+        self
+    }
+
+    /// Adds a Transition with a predicate and two Side Effects. This behaves like
+    /// `.with_predicated_transition_effect(..)` when the predicate returns true, running
+    /// `effect` and moving to the To state. When the predicate returns false, `else_effect` runs
+    /// instead and the state doesn't change, so the effect data's `to` is the current state. This
+    /// keeps the handling of a rejected guard (such as counting denied attempts) next to the guard.
+    pub fn with_predicated_transition_effect_else(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a, else_effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut transition = StateMachineTransition::new(Some(Arc::new(event_predicate)), from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.else_effect = Some(Arc::new(else_effect));
+        self.transitions.push(transition);
+        self
+    }
+
+    /// Adds a Transition with a predicate and a Side Effect, like
+    /// `.with_predicated_transition_effect(..)`, and gives the predicate a name. Exporters such as
+    /// [StateMachineFactory::to_dot] label the Transition with its name instead of "pred", and
+    /// [TransitionVisitor]s receive it as the Transition's label.
+    pub fn with_named_predicated_transition_effect(mut self, name: impl Into<String>, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut transition = StateMachineTransition::new(Some(Arc::new(event_predicate)), from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.name = Some(name.into());
+        self.transitions.push(transition);
+        self
+    }
+
+    /// Adds an Effect that runs whenever the State Machine enters `to_state` from any other state,
+    /// after the effect of the Transition that caused the state change.
+    pub fn with_entry_effect(self, to_state: TState, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.with_entry_effect_from(to_state, FromState::Any, effect)
+    }
+
+    /// Adds an Effect that runs whenever the State Machine enters `to_state` from a state matching
+    /// `from_state`, after the effect of the Transition that caused the state change. This allows an
+    /// entry action to depend on where the State Machine came from; the effect receives both the
+    /// `from` and the `to` state.
+    pub fn with_entry_effect_from(mut self, to_state: TState, from_state: impl Into<FromState<TState>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.definition.entry_effects.push(EntryEffect {
+            to_state,
+            from_state: from_state.into(),
+            effect: Arc::new(effect),
+        });
+        self
+    }
+
+    /// Adds an Effect that runs whenever the State Machine moves from a state matching
+    /// `from_state` into `to_state`, once the effect of the Transition that caused the state
+    /// change has run and before the entry effects of `to_state`. This gives logic that belongs
+    /// between two states, such as handing a resource over from one to the other, a slot of its
+    /// own, rather than folding it into the Transition's effect or an entry effect. Transit
+    /// effects don't run when descending into initial substates.
+    pub fn with_transit_effect(mut self, from_state: impl Into<FromState<TState>>, to_state: TState, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.definition.transit_effects.push(EntryEffect {
+            to_state,
+            from_state: from_state.into(),
+            effect: Arc::new(effect),
+        });
+        self
+    }
+
+    /// Declares `child` as the initial substate of `parent`: whenever a Transition enters `parent`,
+    /// the State Machine carries on into `child` before the Transition completes, and into the
+    /// initial substate of `child` if it has one. The entry effects of `parent` run first, followed
+    /// by those of each substate in turn, which see the state they descended from as their `from`.
+    /// If the entry effect of a substate fails, the State Machine stays in the state it descended
+    /// from. Listeners observe the Transition into `parent`. [StateMachine::active_states] reports
+    /// the states the State Machine is nested in. Declaring another initial substate for the same
+    /// parent replaces the previous one.
+    pub fn with_initial_substate(mut self, parent: TState, child: TState) -> Self {
+        self.definition.initial_substates.retain(|(existing, _)| *existing != parent);
+        self.definition.initial_substates.push((parent, child));
+        self
+    }
+
+    /// Enqueues `event` whenever the State Machine enters `to_state` from any other state, so that
+    /// entering a state can immediately move it on (for example, entering `Loading` fires `Start`).
+    /// The Event is enqueued as if by [StateTransitionEffectData::enqueue] from an entry effect,
+    /// so it is handled once the current Event has been handled, like any other enqueued Event.
+    /// State Machines don't enqueue the Event for the state they are built in.
+    ///
+    /// Unlike an auto Transition with [StateMachineFactory::cycle], which moves on within the
+    /// handling of the current Event, the entry Event goes through the whole Event Lifecycle, and
+    /// only the Transitions for that Event apply. Entry Events that lead back into one another
+    /// would never stop; [StateMachineFactory::with_queue_limit] guards against this.
+    pub fn with_entry_event(self, to_state: TState, event: TEvent) -> Self
+    where TEvent: Clone + Send + Sync + 'a
+    {
+        self.with_entry_effect(to_state, move |d| {
+            d.enqueue(event.clone());
+            Ok(())
+        })
+    }
+
+    /// Adds a pre-transition hook, which can veto any Transition. Hooks run in registration order
+    /// for every Transition that would fire, once it matches the current state and Event and its
+    /// predicate has passed, but before its effect. If a hook returns false, the Transition isn't
+    /// taken and
+    /// [StateMachine::handle_event] fails with [StateMachineError::HookRejected]. This keeps
+    /// machine-wide policy out of individual predicates.
+    pub fn with_pre_hook(mut self, pre_hook: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a) -> Self
     {
-        self.transitions.push(StateMachineTransition::new(Some(Box::new(event_predicate)), from_state.into(), get_to_state.into(), Some(Box::new(effect))));
+        self.definition.pre_hooks.push(Arc::new(pre_hook));
+        self
+    }
+
+    /// Adds a logger that is called exactly once for every Transition taken, with its source and
+    /// target States and the Event, once its effect and any entry effects have succeeded and
+    /// before the listeners run. Unlike an effect on an `(Any, Same)` Transition, which only runs
+    /// when the evaluation reaches it, a logger sees every Transition, wherever it is registered;
+    /// like a listener, it doesn't run for Events that no Transition was taken for, or for
+    /// Transitions whose effect failed.
+    pub fn with_global_transition_logger(mut self, logger: impl Fn(&TState, &TState, &TEvent) + Send + Sync + 'a) -> Self
+    {
+        self.definition.transition_loggers.push(Arc::new(logger));
+        self
+    }
+
+    /// Adds a listener that observes every Transition taken, once its effect and any entry effects
+    /// have run. Listeners can't affect the outcome of a Transition.
+    pub fn with_listener(self, listener: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) + Send + Sync + 'a) -> Self
+    {
+        self.with_listener_priority(DEFAULT_PRIORITY, listener)
+    }
+
+    /// Adds a listener with a priority. Listeners run highest priority first, and in the order
+    /// they were registered within the same priority.
+    pub fn with_listener_priority(mut self, priority: u32, listener: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) + Send + Sync + 'a) -> Self
+    {
+        let position = self.definition.listeners.iter()
+            .position(|(p, _)| *p < priority)
+            .unwrap_or(self.definition.listeners.len());
+        self.definition.listeners.insert(position, (priority, Arc::new(listener)));
+        self
+    }
+
+    /// Adds a consuming Transition to the State Machine definition with a predicate and a Side
+    /// Effect. This behaves like `.with_predicated_transition_effect(..)`, except that once it fires
+    /// the Event counts as fully handled: no further Transitions are considered for it, including
+    /// [Same] Transitions such as loggers, and the State Machine doesn't cycle. The Event is
+    /// reported as handled to [StateMachineFactory::with_after_dispatch] hooks even if the
+    /// Transition is into [Same]. Register it before the Transitions it should take precedence over.
+    pub fn with_consuming_transition_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut transition = StateMachineTransition::new(Some(Arc::new(event_predicate)), from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.consuming = true;
+        self.transitions.push(transition);
+        self
+    }
+
+    /// Adds a hook that runs before every Event is dispatched, with the Event and the current state.
+    /// Unlike an effect on a [FromState::Any] / [Same] Transition, it runs whether or not any
+    /// Transition matches, so it suits cross-cutting concerns such as logging every Event. Events
+    /// enqueued by effects are dispatched, and so observed, one at a time.
+    pub fn with_before_dispatch(mut self, hook: impl Fn(&TEvent, &TState) + Send + Sync + 'a) -> Self {
+        self.definition.before_dispatch.push(Arc::new(hook));
+        self
+    }
+
+    /// Adds a hook that runs after every Event has been dispatched, with the Event, the resulting
+    /// state and the [DispatchOutcome], including when no Transition matched the Event or handling
+    /// it failed. This makes it possible to log rejected Events uniformly.
+    pub fn with_after_dispatch(mut self, hook: impl Fn(&TEvent, &TState, &DispatchOutcome) + Send + Sync + 'a) -> Self {
+        self.definition.after_dispatch.push(Arc::new(hook));
+        self
+    }
+
+    /// Adds an exclusive Transition to the State Machine definition with a predicate and a Side
+    /// Effect. This behaves like `.with_predicated_transition_effect(..)`, except that once it fires
+    /// no further state-changing Transitions are considered for the current Event. Matching
+    /// Transitions into [Same] still run, so cross-cutting effects such as loggers are unaffected.
+    pub fn with_exclusive_transition_effect(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, event_predicate: impl Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut transition = StateMachineTransition::new(Some(Arc::new(event_predicate)), from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        transition.exclusive = true;
+        self.transitions.push(transition);
         self
     }
 }
@@ -283,69 +1821,352 @@ impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + Partia
 impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData>
 where TEvent: PartialEq<TEvent> + Sync
 {
-    /// Adds a Transition to the State Machine definition whose predicate checks for equality with a
-    /// provided Event reference. This is syntactic sugar for `.with_predicated_transition(..)` with
-    /// an equality Predicate.
-    pub fn with_event_transition<'b>(mut self, event: &'a TEvent, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>) -> Self
-    {
-        self.transitions.push(
-            StateMachineTransition::new(
-                Some(Box::new(|e| *event == *e.event)),
-                from_state.into(),
-                get_to_state.into(),
-                None
-            )
-        );
+    /// Adds a Transition to the State Machine definition that applies to Events matching a provided
+    /// Event reference. Events match if they are equal or, if a key function was set with
+    /// [StateMachineFactory::with_event_key], if their keys are equal.
+    pub fn with_event_transition(self, event: &'a TEvent, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>) -> Self
+    {
+        self.with_matched_event_transition(event, from_state.into(), get_to_state.into(), None)
+    }
+
+    /// Adds a Transition with a side effect to the State Machine definition that applies to Events
+    /// matching a provided Event reference. Events match if they are equal or, if a key function was
+    /// set with [StateMachineFactory::with_event_key], if their keys are equal.
+    pub fn with_event_transition_effect(self, event: &'a TEvent, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.with_matched_event_transition(event, from_state.into(), get_to_state.into(), Some(Arc::new(effect)))
+    }
+
+    /// Adds a Transition with a side effect from any State to `to_state` for Events matching
+    /// `event`, for recovering from wherever the State Machine is on a signal such as `Reset`, as
+    /// in `.with_recovery_transition(&Clear, Idle, |d| ..)` with an effect that resets the data.
+    /// This is the same as `.with_event_transition_effect(event, Any, to_state, effect)`.
+    pub fn with_recovery_transition(self, event: &'a TEvent, to_state: TState, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.with_matched_event_transition(event, FromState::Any, To(to_state), Some(Arc::new(effect)))
+    }
+
+    /// Declares that Events matching `event` are deliberately ignored in `from_state`, such as
+    /// `Equals` while a calculator is `Idle`, rather than left unhandled by accident. An ignored
+    /// Event is consumed (see [StateMachineFactory::with_consuming_transition_effect]) without any
+    /// effect or change of state, and counts as handled: [StateMachineFactory::with_after_dispatch]
+    /// hooks see [DispatchOutcome::Handled], [StateMachineFactory::unhandled_events] doesn't list
+    /// it, and [StateMachineFactory::to_matrix] shows `ignored` for it. Register it before any
+    /// Transition that should not see the Event, such as a logger.
+    pub fn with_ignored_event(mut self, from_state: impl Into<FromState<TState>>, event: &'a TEvent) -> Self
+    {
+        self = self.with_matched_event_transition(event, from_state.into(), Same, None);
+        let transition = self.transitions.last_mut().expect("a Transition was just added");
+        transition.consuming = true;
+        transition.ignored = true;
         self
     }
 
-    /// Adds a Transition with a side effect to the State Machine definition whose predicate checks
-    /// for equality with a provided Event reference. This is syntactic sugar for
-    /// `.with_predicated_transition(..)` with an equality Predicate.
-    pub fn with_event_transition_effect(mut self, event: &'a TEvent, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + 'a) -> Self
+    /// Adds a Transition with a side effect for Events matching `event` that only fires the
+    /// `count`th time such an Event occurs in `from_state`, such as locking an account after 3
+    /// failed attempts, without keeping the count in the data. The occurrences are counted per
+    /// State Machine, and only once the Transition's predicate would be evaluated, so Events
+    /// handled in other States don't count. The count starts over whenever the State Machine
+    /// changes state, and once the Transition fires, so that it fires again after another `count`
+    /// occurrences if it doesn't leave `from_state`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is 0.
+    pub fn with_counted_transition_effect(self, from_state: impl Into<FromState<TState>>, event: &'a TEvent, count: usize, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
     {
-        self.transitions.push(
-            StateMachineTransition::new(
-                Some(Box::new(|e| *event == *e.event)),
-                from_state.into(),
-                get_to_state.into(),
-                Some(Box::new(effect))
-            )
-        );
+        assert!(count > 0, "a counted Transition has to fire after at least one occurrence");
+        let mut factory = self.with_matched_event_transition(event, from_state.into(), get_to_state.into(), Some(Arc::new(effect)));
+        factory.transitions.last_mut().expect("a Transition was just added").required_count = Some(count);
+        factory
+    }
+
+    /// Adds an effect that runs when an Event matching `event` is handled in `from_state`,
+    /// whatever Transition then determines the target, for example to log or validate leaving a
+    /// State through a [Calc] Transition. The effect runs as a [Same] Transition, so it sees the
+    /// State being left as both `from` and `to`, and, like
+    /// [StateMachineFactory::with_cross_cutting_effect], it is exempt from the checks of
+    /// [StateMachineFactory::strict]. As Transitions are evaluated in order, it has to be
+    /// registered before the Transitions that leave `from_state` for the Event.
+    pub fn with_source_event_effect(self, from_state: impl Into<FromState<TState>>, event: &'a TEvent, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        let mut factory = self.with_matched_event_transition(event, from_state.into(), Same, Some(Arc::new(effect)));
+        factory.transitions.last_mut().expect("a Transition was just added").cross_cutting = true;
+        factory
+    }
+
+    /// Adds a Transition with a side effect for Events matching `event` whose target State is
+    /// carried by the Event itself, as in `GoTo { screen: Screen::Settings }`, which `target`
+    /// extracts; for example `.with_event_to_payload_transition_effect(&GoTo { screen: Home }, Any, |e| e.screen(), effect)`.
+    /// This usually goes with [StateMachineFactory::with_event_key], so that `event` only selects
+    /// the kind of Event while its payload selects the destination. Like the callback of a [Calc]
+    /// Transition, `target` can't borrow anything.
+    pub fn with_event_to_payload_transition_effect(self, event: &'a TEvent, from_state: impl Into<FromState<TState>>, target: impl Fn(&TEvent) -> TState + Send + Sync + 'static, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.with_matched_event_transition(event, from_state.into(), Calc(Box::new(move |d| target(d.event))), Some(Arc::new(effect)))
+    }
+
+    fn with_matched_event_transition(mut self, event: &'a TEvent, from_state: FromState<TState>, get_to_state: ToState<TEvent, TState, TData>, effect: Option<TransitionEffect<'a, TEvent, TState, TData>>) -> Self
+    {
+        if self.definition.event_eq.is_none() {
+            self.definition.event_eq = Some(Arc::new(|a, b| a == b));
+        }
+        let mut transition = StateMachineTransition::new(None, from_state, get_to_state, effect);
+        transition.event = Some(event);
+        self.transitions.push(transition);
         self
     }
 }
 
-/// Basic error type for [StateMachine]
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a> StateMachineFactory<'a, TEvent, TState> {
+    /// Creates a new `StateMachineFactory` for State Machines without any data, whose behaviour
+    /// depends only on their state and the Events they receive (such as traffic lights or simple
+    /// protocols). Effects and predicates receive `()` as their data.
+    pub fn new_no_data() -> Self {
+        Self::new()
+    }
+}
+
+/// The name of a named function type, without its path, or `None` for closures
+fn function_name<F>() -> Option<String> {
+    let name = std::any::type_name::<F>();
+    if name.contains("{{closure}}") {
+        return None;
+    }
+    Some(name.rsplit("::").next().unwrap_or(name).to_string())
+}
+
+/// Compact identifier for a State registered with [StateMachineFactory::with_states]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct StateId(usize);
+
+impl StateId {
+    /// The position of the State in registration order.
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+/// Basic error type for [StateMachine]. New kinds of error may be added in minor releases, so
+/// matches on it need a catch-all arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum StateMachineError<TState: Debug + Send + Clone + Eq + PartialEq> {
     /// Basic error type for [StateMachine::handle_event]
     #[error("error running effect moving from state {0:?} to {1:?}: {2:?}")]
-    EffectError(TState, TState, Box<dyn std::error::Error + Send>)
+    EffectError(TState, TState, Box<dyn std::error::Error + Send>),
+    /// A pre-transition hook (see [StateMachineFactory::with_pre_hook]) rejected a Transition from
+    /// the first state into the second
+    #[error("pre-transition hook rejected moving from state {0:?} to {1:?}")]
+    HookRejected(TState, TState),
+    /// Handling an Event in the given state would have evaluated more predicates than the budget
+    /// set with [StateMachineFactory::with_guard_budget], which is the given number
+    #[error("guard budget of {1} exceeded in state {0:?}")]
+    GuardBudgetExceeded(TState, usize),
+    /// The State Machine handled more Events in a row without leaving the given state than allowed
+    /// by [StateMachineFactory::with_self_transition_limit]; the number is how many it handled
+    #[error("{1} events in a row handled without leaving state {0:?}")]
+    SelfTransitionLimitExceeded(TState, usize),
+    /// The given number of enqueued Events had been handled, the limit set with
+    /// [StateMachineFactory::with_queue_limit], and another was still queued in the given state
+    #[error("limit of {1} enqueued events exceeded in state {0:?}")]
+    QueueLimitExceeded(TState, usize),
+    /// The given number of Transitions had been taken, the limit set with
+    /// [StateMachineFactory::with_max_transitions], and another was about to be taken in the given
+    /// state
+    #[error("circuit open: limit of {1} transitions reached in state {0:?}")]
+    CircuitOpen(TState, usize),
+    /// Moving from the first state into the second broke an invariant added with
+    /// [StateMachineFactory::with_invariant], which gave the reason
+    #[error("invariant violated moving from state {0:?} to {1:?}: {2}")]
+    InvariantViolated(TState, TState, String),
+    /// The guards of the given number of Transitions in the exclusive group with the given ids
+    /// passed in the given state, rather than exactly one; see [StateMachineFactory::exclusive_group]
+    #[error("{2} transitions of exclusive group {1:?} apply in state {0:?}, rather than exactly one")]
+    ExclusiveGroupViolated(TState, Vec<String>, usize),
+    /// A privileged Transition from the first state into the second applied, but no token of its
+    /// kind was presented; see [StateMachineFactory::with_privileged_transition_effect]
+    #[error("moving from state {0:?} to {1:?} requires a token")]
+    Unauthorized(TState, TState),
+}
+
+/// The outcome of dispatching an Event, passed to hooks registered with
+/// [StateMachineFactory::with_after_dispatch]
+#[derive(Debug)]
+pub enum DispatchOutcome<'o> {
+    /// The Event was handled by the given number of Transitions, not counting [Same] Transitions
+    Handled(usize),
+    /// No Transition matched the Event, other than any [Same] Transitions such as loggers
+    Rejected,
+    /// Handling the Event failed with the given [StateMachineError]
+    Failed(&'o dyn std::error::Error)
 }
 
+/// Error locking a [StateMachineFactory] with [StateMachineFactory::try_lock]. New kinds of error
+/// may be added in minor releases, so matches on it need a catch-all arm.
+#[derive(Error, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum DefinitionError<TState: Debug> {
+    /// In strict mode (see [StateMachineFactory::strict]), the effects of two Transitions apply to
+    /// the same Event in the given state. The Transitions are identified by their position in
+    /// registration order.
+    #[error("effects of transitions {1} and {2} overlap in state {0:?}")]
+    OverlappingEffects(TState, usize, usize),
+    /// A Transition is ordered with [StateMachineFactory::after] after an id that no Transition
+    /// was given with [StateMachineFactory::effect_id]
+    #[error("no transition has the effect id {0:?}")]
+    UnknownEffectId(String),
+    /// The dependencies declared with [StateMachineFactory::after] form a cycle between the
+    /// Transitions with the given ids, so they can't all be met
+    #[error("effect dependencies form a cycle between {0:?}")]
+    EffectDependencyCycle(Vec<String>),
+    /// The Transition at the given position in registration order starts from an empty
+    /// [FromState::AnyOf], so it can never be taken
+    #[error("transition {0} starts from an empty AnyOf, which matches nothing; use Any or list the states")]
+    EmptyAnyOf(usize),
+    /// A Transition added with [StateMachineFactory::with_dyn_transition] names an effect that
+    /// isn't in its [EffectRegistry]
+    #[error("no effect is registered as {0:?}")]
+    UnknownEffect(String),
+    /// The Transitions of the exclusive group with the given ids don't all start from the same
+    /// States on the same Event; see [StateMachineFactory::exclusive_group]
+    #[error("transitions of exclusive group {0:?} don't share their states and event")]
+    InconsistentExclusiveGroup(Vec<String>),
+}
+
+/// Boxed Predicate deciding whether a Transition applies
+pub(crate) type EventPredicate<'a, TEvent, TState, TData> = Arc<dyn Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a>;
+
+/// Boxed Effect executed when a Transition applies
+pub(crate) type TransitionEffect<'a, TEvent, TState, TData> = Arc<dyn Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a>;
+
+/// Boxed listener observing a Transition that has been taken
+type TransitionListener<'a, TEvent, TState, TData> = Arc<dyn Fn(&StateTransitionEffectData<TEvent, TState, TData>) + Send + Sync + 'a>;
+
+/// Boxed logger observing the source and target States, and the Event, of a Transition that has
+/// been taken
+type TransitionLogger<'a, TEvent, TState> = Arc<dyn Fn(&TState, &TState, &TEvent) + Send + Sync + 'a>;
+
+/// Boxed check of the State and data a Transition leads to
+type Invariant<'a, TState, TData> = Arc<dyn Fn(&TState, &TData) -> Result<(), String> + Send + Sync + 'a>;
+
+/// Boxed hook observing an Event before it is dispatched
+type BeforeDispatchHook<'a, TEvent, TState> = Arc<dyn Fn(&TEvent, &TState) + Send + Sync + 'a>;
+
+/// Boxed hook observing the outcome of dispatching an Event
+type AfterDispatchHook<'a, TEvent, TState> = Arc<dyn Fn(&TEvent, &TState, &DispatchOutcome) + Send + Sync + 'a>;
+
+/// Boxed comparison of two Events
+type EventComparator<'a, TEvent> = Arc<dyn Fn(&TEvent, &TEvent) -> bool + Send + Sync + 'a>;
+
+/// Boxed description of an Event for the decision log
+#[cfg(feature = "tracing")]
+type EventFormatter<'a, TEvent> = Arc<dyn Fn(&TEvent) -> String + Send + Sync + 'a>;
+
+/// Boxed clone of an Event
+type EventCloner<'a, TEvent> = Arc<dyn Fn(&TEvent) -> TEvent + Send + Sync + 'a>;
+
+/// Boxed comparison of two States
+type StateComparator<'a, TState> = Arc<dyn Fn(&TState, &TState) -> bool + Send + Sync + 'a>;
+
+/// Boxed callback calculating the target State of a [Calc] Transition
+type ToStateCalculator<TEvent, TState, TData> = Box<dyn Fn(StateTransitionToStateData<TEvent, TState, TData>) -> TState + Send + Sync>;
+
+/// Callback calculating the target State of a registered [Calc] Transition, shared with the
+/// factories forked from the one it was registered with
+type SharedToStateCalculator<TEvent, TState, TData> = Arc<dyn Fn(StateTransitionToStateData<TEvent, TState, TData>) -> TState + Send + Sync>;
+
 /// Describes a Transition between States, potentially with a Predicate and/or Effect
 pub struct StateMachineTransition<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData>
 {
     from_state: FromState<TState>,
-    get_to_state: ToState<TEvent, TState, TData>,
-    event_predicate: Option<Box<dyn Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + 'a>>,
-    effect: Option<Box<dyn Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + 'a>>
+    get_to_state: Target<TEvent, TState, TData>,
+    event_predicate: Option<EventPredicate<'a, TEvent, TState, TData>>,
+    effect: Option<TransitionEffect<'a, TEvent, TState, TData>>,
+    /// Runs instead of the effect when the predicate returns false
+    else_effect: Option<TransitionEffect<'a, TEvent, TState, TData>>,
+    /// The Event this Transition applies to, for event Transitions
+    event: Option<&'a TEvent>,
+    exclusive: bool,
+    /// Stops all further evaluation for the Event once taken; see
+    /// [StateMachineFactory::with_consuming_transition_effect]
+    consuming: bool,
+    /// Set for the Transitions added with [StateMachineFactory::with_ignored_event]
+    ignored: bool,
+    /// Set for effects that are expected to overlap with others; see [StateMachineFactory::strict]
+    cross_cutting: bool,
+    /// Set for effects that act on the outside world, which are skipped in replay mode; see
+    /// [StateMachineFactory::side_effecting]
+    side_effecting: bool,
+    /// The name given to the Transition's predicate, if any
+    name: Option<String>,
+    /// The Event and effect named by [StateMachineFactory::with_dyn_transition], for exports
+    dyn_names: Option<(String, Option<String>)>,
+    /// The id other Transitions refer to in [StateMachineFactory::after]
+    effect_id: Option<String>,
+    /// The ids of the Transitions this one is ordered after
+    after: Vec<String>,
+    /// The ids of the Transitions this one shadows; see [StateMachineFactory::overrides]
+    overrides: Vec<String>,
+    /// The positions of the locked Transitions that shadow this one once taken
+    shadowed_by: Vec<usize>,
+    /// True if the Transition is only considered once no other Transition handled the Event
+    fallback: bool,
+    /// How many times the Event has to occur before the Transition fires; see
+    /// [StateMachineFactory::with_counted_transition_effect]
+    required_count: Option<usize>,
+    /// The check of the token presented, for privileged Transitions; see
+    /// [StateMachineFactory::with_privileged_transition_effect]
+    privilege: Option<privilege::PrivilegeCheck<'a>>,
+    /// The guard awaited by [StateMachine::handle_event_async], along with its id
+    #[cfg(feature = "async")]
+    async_guard: Option<(usize, async_guard::AsyncGuard<'a, TEvent, TData>)>,
 }
 
 impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> StateMachineTransition<'a, TEvent, TState, TData> {
+    /// Determines the state this Transition leads to from `from`
+    fn to_state(&self, event: &TEvent, from: &TState, data: &TData) -> TState {
+        match &self.get_to_state {
+            Target::To(to_state) => to_state.clone(),
+            Target::Calc(get_to_state) => get_to_state(StateTransitionToStateData {
+                data,
+                event,
+                from,
+            }),
+            Target::Same => from.clone()
+        }
+    }
+
     fn new(
-        event_predicate: Option<Box<dyn Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + 'a>>,
+        event_predicate: Option<EventPredicate<'a, TEvent, TState, TData>>,
         from_state: FromState<TState>,
         get_to_state: ToState<TEvent, TState, TData>,
-        effect: Option<Box<dyn Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + 'a>>,
+        effect: Option<TransitionEffect<'a, TEvent, TState, TData>>,
     ) -> Self
     {
         Self {
             event_predicate,
             from_state,
-            get_to_state,
-            effect
+            get_to_state: get_to_state.into(),
+            effect,
+            else_effect: None,
+            event: None,
+            exclusive: false,
+            consuming: false,
+            ignored: false,
+            cross_cutting: false,
+            side_effecting: false,
+            name: None,
+            dyn_names: None,
+            effect_id: None,
+            after: Vec::new(),
+            overrides: Vec::new(),
+            shadowed_by: Vec::new(),
+            fallback: false,
+            required_count: None,
+            privilege: None,
+            #[cfg(feature = "async")]
+            async_guard: None,
         }
     }
 }
@@ -355,18 +2176,84 @@ impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> StateMac
 pub enum FromState<TState: PartialEq<TState> + Clone> {
     /// Indicates that a Transition is valid from any State
     Any,
-    /// Indicates that a Transition is valid from any State in the provided Vector
+    /// Indicates that a Transition is valid from any State in the provided Vector. Arrays and slices
+    /// of States convert into this variant, so `[A, B]` can be passed wherever a
+    /// `FromState` is expected. The States are only copied once, when the Transition is registered.
     AnyOf(Vec<TState>),
+    /// Indicates that a Transition is valid from any State except those in the provided Vector
+    Not(Vec<TState>),
     /// Indicates that a Transition is valid only from the specified State
     From(TState)
 }
 
+impl <TState: PartialEq<TState> + Clone> FromState<TState> {
+    /// Determines whether `state` is one of the States described by this `FromState`
+    fn matches(&self, state: &TState) -> bool {
+        self.matches_by(state, |a, b| a == b)
+    }
+
+    /// Determines whether `state` is one of the States described by this `FromState`, comparing
+    /// States with `same_state`
+    fn matches_by(&self, state: &TState, same_state: impl Fn(&TState, &TState) -> bool) -> bool {
+        match self {
+            FromState::Any => true,
+            FromState::AnyOf(states) => states.iter().any(|s| same_state(s, state)),
+            FromState::Not(states) => !states.iter().any(|s| same_state(s, state)),
+            FromState::From(from) => same_state(from, state)
+        }
+    }
+}
+
+/// Renders [FromState::Any] as `*`, [FromState::AnyOf] as `{A|B|C}`, [FromState::Not] as `!A` (or
+/// `!{A|B}` for several States), and [FromState::From] as the State itself.
+impl <TState: PartialEq<TState> + Clone + Display> Display for FromState<TState> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn write_set<TState: Display>(f: &mut Formatter<'_>, states: &[TState]) -> fmt::Result {
+            write!(f, "{{")?;
+            for (index, state) in states.iter().enumerate() {
+                if index > 0 {
+                    write!(f, "|")?;
+                }
+                write!(f, "{}", state)?;
+            }
+            write!(f, "}}")
+        }
+
+        match self {
+            FromState::Any => write!(f, "*"),
+            FromState::AnyOf(states) => write_set(f, states),
+            FromState::Not(states) if states.len() == 1 => write!(f, "!{}", states[0]),
+            FromState::Not(states) => {
+                write!(f, "!")?;
+                write_set(f, states)
+            }
+            FromState::From(state) => write!(f, "{}", state)
+        }
+    }
+}
+
 impl <TState: PartialEq<TState> + Clone> From<TState> for FromState<TState> {
     fn from(value: TState) -> Self {
         FromState::From(value)
     }
 }
 
+/// Allows a Transition to be valid from any of a fixed set of States without building a [Vec]
+/// first, as in `[States::Adding, States::Subtracting]`. This becomes [FromState::AnyOf].
+impl <TState: PartialEq<TState> + Clone, const N: usize> From<[TState; N]> for FromState<TState> {
+    fn from(value: [TState; N]) -> Self {
+        FromState::AnyOf(value.into())
+    }
+}
+
+/// Allows a Transition to be valid from any State in a slice, such as a `const` list of States,
+/// cloning them into a [FromState::AnyOf].
+impl <TState: PartialEq<TState> + Clone> From<&[TState]> for FromState<TState> {
+    fn from(value: &[TState]) -> Self {
+        FromState::AnyOf(value.to_vec())
+    }
+}
+
 /// Indicates how a result State is determined after transitioning
 pub enum ToState<TEvent, TState: PartialEq<TState> + Clone + Send, TData> {
     /// Indicates that a Transition should be applied without changing state.
@@ -378,7 +2265,34 @@ pub enum ToState<TEvent, TState: PartialEq<TState> + Clone + Send, TData> {
     /// Specifies that a Transition will cause the State Machine to move to the specified State.
     To(TState),
     /// Allows a Transition to provide bespoke logic for determining which State to transition into.
-    Calc(Box<dyn Fn(StateTransitionToStateData<TEvent, TState, TData>) -> TState>)
+    Calc(ToStateCalculator<TEvent, TState, TData>)
+}
+
+/// How a registered Transition determines its target State, as given by a [ToState]
+pub(crate) enum Target<TEvent, TState, TData> {
+    Same,
+    To(TState),
+    Calc(SharedToStateCalculator<TEvent, TState, TData>)
+}
+
+impl <TEvent, TState: Clone, TData> Clone for Target<TEvent, TState, TData> {
+    fn clone(&self) -> Self {
+        match self {
+            Target::Same => Target::Same,
+            Target::To(to_state) => Target::To(to_state.clone()),
+            Target::Calc(get_to_state) => Target::Calc(get_to_state.clone())
+        }
+    }
+}
+
+impl <TEvent, TState: PartialEq<TState> + Clone + Send, TData> From<ToState<TEvent, TState, TData>> for Target<TEvent, TState, TData> {
+    fn from(value: ToState<TEvent, TState, TData>) -> Self {
+        match value {
+            Same => Target::Same,
+            To(to_state) => Target::To(to_state),
+            Calc(get_to_state) => Target::Calc(Arc::from(get_to_state))
+        }
+    }
 }
 
 impl <TEvent, TState: PartialEq<TState> + Clone + Send, TData> From<TState> for ToState<TEvent, TState, TData> {
@@ -387,6 +2301,18 @@ impl <TEvent, TState: PartialEq<TState> + Clone + Send, TData> From<TState> for
     }
 }
 
+/// Renders [Same] as `=`, [To] as the State itself, and [Calc] as `?`, since its State is only known
+/// when the Transition is taken.
+impl <TEvent, TState: PartialEq<TState> + Clone + Send + Display, TData> Display for ToState<TEvent, TState, TData> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Same => write!(f, "="),
+            To(state) => write!(f, "{}", state),
+            Calc(_) => write!(f, "?")
+        }
+    }
+}
+
 /// Data passed to a Transition Effect callback.
 #[derive(Clone)]
 pub struct StateTransitionEffectData<'a, TEvent, TState, TData> {
@@ -397,7 +2323,8 @@ pub struct StateTransitionEffectData<'a, TEvent, TState, TData> {
     /// The state that is being transitioned from.
     pub from: &'a TState,
     /// The state that is being transitioned into.
-    pub to: &'a TState
+    pub to: &'a TState,
+    context: &'a DispatchContext<TEvent, TState>,
 }
 
 /// Data passed to a Transition ToState callback.
@@ -413,12 +2340,13 @@ pub struct StateTransitionToStateData<'a, TEvent, TState, TData> {
 
 #[cfg(test)]
 mod unit_tests {
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use anyhow::{anyhow};
     use thiserror::Error;
-    use crate::{StateMachineFactory, StateMachineError};
-    use crate::FromState::From;
-    use crate::ToState::To;
+    use crate::{DefinitionError, StateMachineFactory, StateMachineError, StateId, StateTransitionEffectData};
+    use crate::FromState::{Any, AnyOf, From};
+    use crate::ToState::{Same, To};
 
     #[test]
     fn test_state_machine() {
@@ -472,7 +2400,7 @@ mod unit_tests {
     }
 
     #[test]
-    fn test_double_transition<'a>() -> anyhow::Result<()> {
+    fn test_double_transition() -> anyhow::Result<()> {
         #[derive(Eq, PartialEq)]
         enum StateMachineMessage {
             GoToTwo
@@ -515,6 +2443,9 @@ mod unit_tests {
             Err(StateMachineError::EffectError(from, to, e)) => {
                 return Err(anyhow!("error changing state from {} to {}: {}", from, to, e));
             }
+            Err(e) => {
+                return Err(anyhow!("unexpected error: {}", e));
+            }
         };
 
         // Because of the two transitions that we defined,
@@ -555,6 +2486,1612 @@ mod unit_tests {
                 assert_eq!(2, to);
                 Ok(())
             }
+            Err(e) => {
+                Err(anyhow!("unexpected error: {}", e))
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_ids() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Running,
+            Stopped
+        }
+
+        let factory = StateMachineFactory::<(), States, ()>::new()
+            .with_states([States::Idle, States::Running])
+            // Registering a State twice keeps its original id
+            .with_states([States::Running, States::Stopped]);
+
+        assert_eq!(Some(0), factory.state_id(&States::Idle).map(|id| id.index()));
+        assert_eq!(Some(1), factory.state_id(&States::Running).map(|id| id.index()));
+        assert_eq!(Some(2), factory.state_id(&States::Stopped).map(|id| id.index()));
+
+        let running = factory.state_id(&States::Running).expect("Running should be registered");
+        assert_eq!(Some(&States::Running), factory.id_to_state(running));
+
+        // Ids that were never assigned don't resolve to a State
+        let unassigned = StateId(3);
+        assert_eq!(None, factory.id_to_state(unassigned));
+    }
+
+    #[test]
+    fn test_exclusive_transition() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding,
+            Subtracting
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Add,
+            Subtract
+        }
+
+        let logged = AtomicUsize::new(0);
+        let factory = StateMachineFactory::new()
+            .with_exclusive_transition_effect(
+                States::Adding,
+                States::Idle,
+                |d| *d.event == Events::Subtract,
+                |_| Ok(())
+            )
+            // Without exclusivity this would immediately move the machine on to Subtracting
+            .with_event_transition(&Events::Subtract, States::Idle, States::Subtracting)
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_transition_effect(
+                Any,
+                Same,
+                |_| {
+                    logged.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            )
+            .lock();
+
+        let mut sm = factory.build(States::Adding, ());
+        assert_eq!(&States::Idle, sm.handle_event(Events::Subtract).expect("unexpected error"));
+        // The Same logger still ran after the exclusive transition fired
+        assert_eq!(1, logged.load(Ordering::SeqCst));
+
+        // Non-exclusive transitions keep the existing semantics
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+        assert_eq!(2, logged.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_entry_effect_from() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding,
+            Dividing
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Add,
+            Divide,
+            Equals
+        }
+
+        let entered_from_adding = AtomicUsize::new(0);
+        let entered_from_dividing = AtomicUsize::new(0);
+        let entered_at_all = AtomicUsize::new(0);
+        let mut sm = StateMachineFactory::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Divide, States::Idle, States::Dividing)
+            .with_event_transition(&Events::Equals, AnyOf(vec![States::Adding, States::Dividing]), States::Idle)
+            .with_entry_effect_from(States::Idle, States::Adding, |d| {
+                assert_eq!(&States::Adding, d.from);
+                assert_eq!(&States::Idle, d.to);
+                entered_from_adding.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_entry_effect_from(States::Idle, States::Dividing, |_| {
+                entered_from_dividing.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_entry_effect(States::Idle, |_| {
+                entered_at_all.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock().build(States::Idle, ());
+
+        sm.handle_event(Events::Add).expect("unexpected error");
+        sm.handle_event(Events::Equals).expect("unexpected error");
+        assert_eq!(1, entered_from_adding.load(Ordering::SeqCst));
+        assert_eq!(0, entered_from_dividing.load(Ordering::SeqCst));
+
+        sm.handle_event(Events::Divide).expect("unexpected error");
+        sm.handle_event(Events::Equals).expect("unexpected error");
+        assert_eq!(1, entered_from_adding.load(Ordering::SeqCst));
+        assert_eq!(1, entered_from_dividing.load(Ordering::SeqCst));
+        assert_eq!(2, entered_at_all.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_enqueue_priority() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Entering
+        }
+
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Start,
+            Digit(u8),
+            Clear
+        }
+
+        let handled = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::new()
+            .with_event_transition_effect(&Events::Start, States::Idle, States::Entering, |d| {
+                d.enqueue(Events::Digit(1));
+                d.enqueue(Events::Digit(2));
+                d.enqueue_priority(Events::Clear, 10);
+                d.enqueue(Events::Digit(3));
+                Ok(())
+            })
+            .with_event_transition(&Events::Clear, States::Entering, States::Idle)
+            .with_transition_effect(Any, Same, |d| {
+                handled.lock().unwrap().push(format!("{:?} in {:?}", d.event, d.from));
+                Ok(())
+            })
+            .lock().build(States::Idle, ());
+
+        assert_eq!(&States::Idle, sm.handle_event(Events::Start).expect("unexpected error"));
+        assert_eq!(
+            vec![
+                "Start in Entering",
+                // The high-priority Clear preempts the digits that were queued before it
+                "Clear in Idle",
+                "Digit(1) in Idle",
+                "Digit(2) in Idle",
+                "Digit(3) in Idle",
+            ],
+            *handled.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_with_initial() {
+        #[derive(Eq, PartialEq)]
+        enum StateMachineMessage {
+            GoToTwo
         }
+
+        let factory = StateMachineFactory::new()
+            .with_initial(1)
+            .with_event_transition(&StateMachineMessage::GoToTwo, 1, 2);
+        assert_eq!(Some(&1), factory.initial_state());
+
+        let factory = factory.lock();
+        let mut sm = factory.build_initial(());
+        assert_eq!(1, sm.state);
+        assert_eq!(&2, sm.handle_event(StateMachineMessage::GoToTwo).expect("unexpected error"));
+
+        // The recorded initial state can still be overridden
+        assert_eq!(2, factory.build(2, ()).state);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_event_key() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Entering
+        }
+
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Digit { digit: u8 },
+            Clear
+        }
+
+        let digits_entered = AtomicUsize::new(0);
+        let mut sm = StateMachineFactory::new()
+            // Any Digit matches, whatever its value
+            .with_event_transition_effect(&Events::Digit { digit: 0 }, Any, States::Entering, |_| {
+                digits_entered.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_event_transition(&Events::Clear, States::Entering, States::Idle)
+            // The key applies to transitions added before it, too
+            .with_event_key(std::mem::discriminant)
+            .lock().build(States::Idle, ());
+
+        assert_eq!(&States::Entering, sm.handle_event(Events::Digit { digit: 4 }).expect("unexpected error"));
+        assert_eq!(&States::Entering, sm.handle_event(Events::Digit { digit: 2 }).expect("unexpected error"));
+        assert_eq!(2, digits_entered.load(Ordering::SeqCst));
+        assert_eq!(&States::Idle, sm.handle_event(Events::Clear).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_unlock() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Green,
+            Yellow,
+            Red
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Next
+        }
+
+        let locked = StateMachineFactory::new()
+            .with_states([States::Green, States::Yellow, States::Red])
+            .with_event_transition(&Events::Next, States::Green, States::Red)
+            .lock();
+        let mut sm = locked.build(States::Green, 7);
+        assert_eq!(&States::Red, sm.handle_event(Events::Next).expect("unexpected error"));
+
+        // The locked factory still shares the definition
+        let sm = sm.unlock().err().expect("definition is still shared");
+        drop(locked);
+
+        let (factory, data) = sm.unlock().ok().expect("definition is no longer shared");
+        assert_eq!(Some(&States::Red), factory.initial_state());
+        assert_eq!(Some(StateId(2)), factory.state_id(&States::Red));
+        assert_eq!(7, data);
+
+        let mut sm = factory
+            .with_event_transition(&Events::Next, States::Red, States::Yellow)
+            .lock()
+            .build_initial(data);
+        assert_eq!(&States::Yellow, sm.handle_event(Events::Next).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_pre_hook() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding,
+            Dividing
+        }
+
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Add,
+            Divide
+        }
+
+        let observed = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Divide, States::Idle, States::Dividing)
+            .with_pre_hook(|d| !matches!(d.event, Events::Divide))
+            .with_listener(|d| observed.lock().unwrap().push(format!("low {:?}", d.to)))
+            .with_listener_priority(10, |d| observed.lock().unwrap().push(format!("high {:?}", d.to)))
+            .lock().build(States::Idle, ());
+
+        match sm.handle_event(Events::Divide) {
+            Err(StateMachineError::HookRejected(from, to)) => {
+                assert_eq!(States::Idle, from);
+                assert_eq!(States::Dividing, to);
+            }
+            _ => panic!("expected the pre-hook to reject Divide")
+        }
+        assert_eq!(States::Idle, sm.state);
+        assert!(observed.lock().unwrap().is_empty());
+
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+        assert_eq!(vec!["high Adding", "low Adding"], *observed.lock().unwrap());
+    }
+
+    #[test]
+    fn test_pre_hook_after_guard() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding,
+            Dividing
+        }
+
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Add,
+            Divide
+        }
+
+        let mut sm = StateMachineFactory::new()
+            .with_predicated_transition(States::Idle, States::Dividing, |d| matches!(d.event, Events::Divide))
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_pre_hook(|d| *d.to != States::Dividing)
+            .lock().build(States::Idle, ());
+
+        // The hook only sees the guarded Transition once its guard passes
+        assert!(matches!(sm.handle_event(Events::Divide), Err(StateMachineError::HookRejected(States::Idle, States::Dividing))));
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_guard_budget() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Go
+        }
+
+        let guards_run = AtomicUsize::new(0);
+        // A factory with a guard for every state below `states`, of which only the last one
+        // registered matches state 0
+        let factory = |states: usize| {
+            let guards_run = &guards_run;
+            let mut factory = StateMachineFactory::<Events, usize, ()>::new();
+            for state in (0..states).rev() {
+                factory = factory.with_predicated_transition(Any, state + 1, move |d| {
+                    guards_run.fetch_add(1, Ordering::SeqCst);
+                    *d.from == state
+                });
+            }
+            factory.with_guard_budget(50).lock()
+        };
+
+        let mut sm = factory(100).build(0, ());
+        match sm.handle_event(Events::Go) {
+            Err(StateMachineError::GuardBudgetExceeded(state, budget)) => {
+                assert_eq!(0, state);
+                assert_eq!(50, budget);
+            }
+            _ => panic!("expected the guard budget to be exceeded")
+        }
+        assert_eq!(50, guards_run.load(Ordering::SeqCst));
+        assert_eq!(0, sm.state);
+
+        let mut sm = factory(10).build(0, ());
+        assert_eq!(&1, sm.handle_event(Events::Go).expect("unexpected error"));
+        #[cfg(feature = "metrics")]
+        assert_eq!(10, sm.metrics().max_guards_per_event());
+    }
+
+    #[test]
+    fn test_accepted_events() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding
+        }
+
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum Events {
+            Digit,
+            Add,
+            Equals
+        }
+
+        let effects_run = AtomicUsize::new(0);
+        let mut sm = StateMachineFactory::new()
+            // Loggers apply to every Event, so they don't make an Event accepted
+            .with_transition_effect(Any, Same, |_| {
+                effects_run.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_predicated_transition_effect(Any, Same, |d| matches!(d.event, Events::Digit), |_| {
+                effects_run.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .lock().build(States::Idle, ());
+
+        let candidates = [Events::Digit, Events::Add, Events::Equals];
+        assert_eq!(vec![Events::Digit, Events::Add], sm.accepted_events(&candidates));
+        assert_eq!(0, effects_run.load(Ordering::SeqCst));
+
+        sm.handle_event(Events::Add).expect("unexpected error");
+        assert_eq!(vec![Events::Digit, Events::Equals], sm.accepted_events(&candidates));
+    }
+
+    #[test]
+    fn test_transition_effect_for() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Next
+        }
+
+        let effect_runs = Arc::new(AtomicUsize::new(0));
+        let counter = effect_runs.clone();
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_transition_effect_for([(1, 2), (3, 4)], Arc::new(move |_: StateTransitionEffectData<_, _, _>| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }))
+            .lock();
+
+        let mut sm = factory.build(1, ());
+        assert_eq!(&2, sm.handle_event(Events::Next).expect("unexpected error"));
+        assert_eq!(&2, sm.handle_event(Events::Next).expect("unexpected error"));
+        let mut sm = factory.build(3, ());
+        assert_eq!(&4, sm.handle_event(Events::Next).expect("unexpected error"));
+        assert_eq!(2, effect_runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_display_from_and_to_state() {
+        assert_eq!("*", Any::<u32>.to_string());
+        assert_eq!("{1|2|3}", AnyOf(vec![1, 2, 3]).to_string());
+        assert_eq!("!1", crate::FromState::Not(vec![1]).to_string());
+        assert_eq!("!{1|2}", crate::FromState::Not(vec![1, 2]).to_string());
+        assert_eq!("4", From(4).to_string());
+
+        assert_eq!("=", Same::<(), u32, ()>.to_string());
+        assert_eq!("4", To::<(), u32, ()>(4).to_string());
+        assert_eq!("?", crate::ToState::<(), u32, ()>::Calc(Box::new(|_| 4)).to_string());
+    }
+
+    #[test]
+    fn test_strict() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Start,
+            Stop
+        }
+
+        let overlapping = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition_effect(&Events::Start, 1, 2, |_| Ok(()))
+            .with_transition_effect(Any, Same, |_| Ok(()))
+            .strict();
+        assert_eq!(Some(DefinitionError::OverlappingEffects(1, 0, 1)), overlapping.try_lock().err());
+
+        // Effects for different Events don't overlap, and the logger is marked as cross-cutting
+        let mut sm = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition_effect(&Events::Start, 1, 2, |_| Ok(()))
+            .with_event_transition_effect(&Events::Stop, AnyOf(vec![1, 2]), 1, |_| Ok(()))
+            .with_cross_cutting_effect(Any, Same, |_| Ok(()))
+            .strict()
+            .try_lock()
+            .expect("effects don't overlap")
+            .build(1, ());
+        assert_eq!(&2, sm.handle_event(Events::Start).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_empty_any_of() {
+        let factory = StateMachineFactory::<(), u32, ()>::new()
+            .with_auto_transition(1, 2)
+            .with_predicated_transition_effect(AnyOf(vec![]), To(3), |_| true, |_| Ok(()));
+        let error = factory.try_lock().err().expect("an empty AnyOf is a mistake");
+        assert_eq!(DefinitionError::EmptyAnyOf(1), error);
+        assert!(error.to_string().contains("use Any or list the states"));
+    }
+
+    #[test]
+    fn test_handle_event_ref() {
+        // Deliberately not Clone, so the Event can only be borrowed
+        #[derive(Eq, PartialEq)]
+        struct Upload {
+            payload: Vec<u8>
+        }
+
+        let uploaded = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Upload, u32, ()>::new()
+            .with_transition_effect(1, 2, |d| {
+                uploaded.fetch_add(d.event.payload.len(), Ordering::SeqCst);
+                Ok(())
+            })
+            .lock();
+
+        let upload = Upload { payload: vec![0; 1024] };
+        let mut sm = factory.build(1, ());
+        assert_eq!(&2, sm.handle_event_ref(&upload).expect("unexpected error"));
+        assert_eq!(1024, uploaded.load(Ordering::SeqCst));
+        assert_eq!(1024, upload.payload.len());
+    }
+
+    #[test]
+    fn test_dispatch_hooks() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Start,
+            Stop
+        }
+
+        let before = AtomicUsize::new(0);
+        let rejected = AtomicUsize::new(0);
+        let handled = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition(&Events::Start, 1, 2)
+            .with_transition_effect(Any, Same, |_| Ok(()))
+            .with_before_dispatch(|_, _| {
+                before.fetch_add(1, Ordering::SeqCst);
+            })
+            .with_after_dispatch(|_, _, outcome| match outcome {
+                crate::DispatchOutcome::Rejected => { rejected.fetch_add(1, Ordering::SeqCst); }
+                crate::DispatchOutcome::Handled(_) => { handled.fetch_add(1, Ordering::SeqCst); }
+                crate::DispatchOutcome::Failed(_) => panic!("unexpected failure")
+            })
+            .lock();
+
+        let mut sm = factory.build(1, ());
+        sm.handle_event(Events::Start).expect("unexpected error");
+        // Only the logger matches Stop, so it counts as rejected
+        sm.handle_event(Events::Stop).expect("unexpected error");
+        sm.handle_event(Events::Start).expect("unexpected error");
+
+        assert_eq!(3, before.load(Ordering::SeqCst));
+        assert_eq!(1, handled.load(Ordering::SeqCst));
+        assert_eq!(2, rejected.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_state_key() {
+        #[derive(Clone, Debug, Eq, PartialEq)]
+        enum States {
+            Disconnected,
+            Connected { peer: u16 }
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Connect(u16),
+            Ping
+        }
+
+        let entries = AtomicUsize::new(0);
+        let pings = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_state_key(std::mem::discriminant)
+            .with_predicated_transition(Any, crate::ToState::Calc(Box::new(|d: crate::StateTransitionToStateData<_, States, _>| match d.event {
+                Events::Connect(peer) => States::Connected { peer: *peer },
+                Events::Ping => d.from.clone()
+            })), |d| matches!(d.event, Events::Connect(_)))
+            // The peer of the from_state is ignored; only the variant is matched
+            .with_predicated_transition_effect(States::Connected { peer: 0 }, Same, |d| matches!(d.event, Events::Ping), |_| {
+                pings.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_entry_effect(States::Connected { peer: 0 }, |_| {
+                entries.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(States::Disconnected, ());
+        sm.handle_event(Events::Ping).expect("unexpected error");
+        assert_eq!(0, pings.load(Ordering::SeqCst));
+
+        assert_eq!(&States::Connected { peer: 7 }, sm.handle_event(Events::Connect(7)).expect("unexpected error"));
+        sm.handle_event(Events::Ping).expect("unexpected error");
+        assert_eq!(1, pings.load(Ordering::SeqCst));
+
+        // Reconnecting to another peer keeps the variant, so it isn't a change of State
+        assert_eq!(&States::Connected { peer: 9 }, sm.handle_event(Events::Connect(9)).expect("unexpected error"));
+        assert_eq!(1, entries.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_state_eq() {
+        #[derive(Clone, Debug, Eq, PartialEq)]
+        struct Stage {
+            name: &'static str,
+            entered_at: u64
+        }
+
+        fn stage(name: &'static str, entered_at: u64) -> Stage {
+            Stage { name, entered_at }
+        }
+
+        let entries = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<u64, Stage, ()>::new()
+            .with_state_eq(|a, b| a.name == b.name)
+            .with_predicated_transition(stage("draft", 0), crate::ToState::Calc(Box::new(|d: crate::StateTransitionToStateData<_, Stage, _>| stage("review", *d.event))), |d| *d.event > 0)
+            .with_entry_effect(stage("review", 0), |_| {
+                entries.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock();
+
+        // The timestamps of the from_state and the entry effect's State are ignored
+        let mut sm = factory.build(stage("draft", 5), ());
+        assert_eq!(&stage("review", 10), sm.handle_event(10).expect("unexpected error"));
+        assert_eq!(1, entries.load(Ordering::SeqCst));
+        assert_eq!(&stage("review", 10), sm.handle_event(20).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_consecutive_same_count() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Digit,
+            Add
+        }
+
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition(&Events::Digit, Any, Same)
+            .with_event_transition(&Events::Add, 1, 2)
+            .with_self_transition_limit(50);
+        let mut sm = factory.lock().build(1, ());
+
+        for _ in 0..50 {
+            sm.handle_event(Events::Digit).expect("unexpected error");
+        }
+        assert_eq!(50, sm.consecutive_same_count());
+        match sm.handle_event(Events::Digit) {
+            Err(StateMachineError::SelfTransitionLimitExceeded(1, 51)) => {}
+            _ => panic!("expected the limit to be exceeded")
+        }
+
+        sm.handle_event(Events::Add).expect("unexpected error");
+        assert_eq!(0, sm.consecutive_same_count());
+        // No Transition matches Add in state 2, so the count is unchanged
+        sm.handle_event(Events::Add).expect("unexpected error");
+        assert_eq!(0, sm.consecutive_same_count());
+    }
+
+    #[test]
+    fn test_any_of_array() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Equals,
+            Clear
+        }
+
+        const OPERATORS: &[u32] = &[2, 3];
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition(&Events::Equals, [2, 3], 1)
+            .with_event_transition(&Events::Clear, OPERATORS, 4)
+            .lock();
+
+        for operator in [2, 3] {
+            let mut sm = factory.build(operator, ());
+            assert_eq!(&1, sm.handle_event(Events::Equals).expect("unexpected error"));
+            let mut sm = factory.build(operator, ());
+            assert_eq!(&4, sm.handle_event(Events::Clear).expect("unexpected error"));
+        }
+        let mut sm = factory.build(1, ());
+        assert_eq!(&1, sm.handle_event(Events::Clear).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_entry_event() {
+        #[derive(Clone, Eq, PartialEq)]
+        enum Events {
+            Load,
+            Start,
+            Loop
+        }
+
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition(&Events::Load, 1, 2)
+            .with_event_transition(&Events::Start, 2, 3)
+            .with_entry_event(2, Events::Start)
+            // Entering 4 and 5 fire events leading into each other
+            .with_event_transition(&Events::Loop, 3, 4)
+            .with_event_transition(&Events::Loop, 4, 5)
+            .with_event_transition(&Events::Loop, 5, 4)
+            .with_entry_event(4, Events::Loop)
+            .with_entry_event(5, Events::Loop)
+            .with_queue_limit(10)
+            .lock();
+
+        let mut sm = factory.build(1, ());
+        assert_eq!(&3, sm.handle_event(Events::Load).expect("unexpected error"));
+        match sm.handle_event(Events::Loop) {
+            Err(StateMachineError::QueueLimitExceeded(_, 10)) => {}
+            _ => panic!("expected the queue limit to be exceeded")
+        }
+    }
+
+    #[test]
+    fn test_predicated_transition_effect_else() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Login { password: &'static str }
+        }
+
+        let denied = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_predicated_transition_effect_else(1, 2, |d| matches!(d.event, Events::Login { password: "secret" }), |_| Ok(()), |d| {
+                assert_eq!(d.from, d.to);
+                denied.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(1, ());
+        assert_eq!(&1, sm.handle_event(Events::Login { password: "guess" }).expect("unexpected error"));
+        assert_eq!(&1, sm.handle_event(Events::Login { password: "hunter2" }).expect("unexpected error"));
+        assert_eq!(&2, sm.handle_event(Events::Login { password: "secret" }).expect("unexpected error"));
+        assert_eq!(2, denied.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_initial_substate() {
+        #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+        enum States {
+            Off,
+            On,
+            Idle,
+            Ready
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            PowerOn
+        }
+
+        let entered = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::PowerOn, States::Off, States::On)
+            .with_initial_substate(States::On, States::Idle)
+            .with_initial_substate(States::Idle, States::Ready)
+            .with_entry_effect(States::On, |d| {
+                entered.lock().unwrap().push((*d.from, *d.to));
+                Ok(())
+            })
+            .with_entry_effect(States::Idle, |d| {
+                entered.lock().unwrap().push((*d.from, *d.to));
+                Ok(())
+            })
+            .with_entry_effect(States::Ready, |d| {
+                entered.lock().unwrap().push((*d.from, *d.to));
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(States::Off, ());
+        assert_eq!(&States::Ready, sm.handle_event(Events::PowerOn).expect("unexpected error"));
+        assert_eq!(vec![
+            (States::Off, States::On),
+            (States::On, States::Idle),
+            (States::Idle, States::Ready)
+        ], *entered.lock().unwrap());
+    }
+
+    #[test]
+    fn test_consuming_transition() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Escape,
+            Key
+        }
+
+        let logged = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_consuming_transition_effect(Any, Same, |d| matches!(d.event, Events::Escape), |_| Ok(()))
+            .with_transition_effect(Any, Same, |_| {
+                logged.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(1, ());
+        sm.handle_event(Events::Key).expect("unexpected error");
+        sm.handle_event(Events::Escape).expect("unexpected error");
+        sm.handle_event(Events::Key).expect("unexpected error");
+        assert_eq!(2, logged.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_no_data() {
+        #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+        enum Lights {
+            Red,
+            Green,
+            Amber
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Go,
+            Slow,
+            Stop
+        }
+
+        let factory = StateMachineFactory::new_no_data()
+            .with_event_transition(&Events::Go, Lights::Red, Lights::Green)
+            .with_event_transition(&Events::Slow, Lights::Green, Lights::Amber)
+            .with_event_transition(&Events::Stop, Lights::Amber, Lights::Red)
+            .lock();
+
+        let mut sm = factory.build_no_data(Lights::Red);
+        assert_eq!(&Lights::Green, sm.handle_event(Events::Go).expect("unexpected error"));
+        assert_eq!(&Lights::Amber, sm.handle_event(Events::Slow).expect("unexpected error"));
+        assert_eq!(&Lights::Red, sm.handle_event(Events::Stop).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_matching_transition_effect() {
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Digit(u8)
+        }
+
+        fn is_octal(event: &Events) -> bool {
+            matches!(event, Events::Digit(0..=7))
+        }
+
+        let digits = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_states([1])
+            .with_matching_transition_effect(Any, Same, is_octal, |d| {
+                let Events::Digit(digit) = d.event;
+                digits.lock().unwrap().push(*digit);
+                Ok(())
+            });
+        assert!(factory.to_mermaid().contains("1 --> 1 : is_octal"), "{}", factory.to_mermaid());
+
+        let mut sm = factory.lock().build(1, ());
+        for digit in [3, 9, 7, 8] {
+            sm.handle_event(Events::Digit(digit)).expect("unexpected error");
+        }
+        assert_eq!(vec![3, 7], *digits.lock().unwrap());
+    }
+
+    #[test]
+    fn test_global_transition_logger() {
+        #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+        enum Events {
+            Start,
+            Stop,
+            Fail
+        }
+
+        let log = Mutex::new(Vec::new());
+        let order = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition(&Events::Start, 1, 2)
+            .with_event_transition(&Events::Stop, 2, 1)
+            .with_event_transition_effect(&Events::Fail, 1, 3, |_| Err(Box::new(std::fmt::Error)))
+            .with_listener(|_| order.lock().unwrap().push("listener"))
+            .with_global_transition_logger(|from, to, event| {
+                log.lock().unwrap().push((*from, *to, *event));
+                order.lock().unwrap().push("logger");
+            })
+            .lock()
+            .build(1, ());
+
+        sm.handle_event(Events::Start).expect("unexpected error");
+        sm.handle_event(Events::Start).expect("unexpected error");
+        sm.handle_event(Events::Stop).expect("unexpected error");
+        assert!(sm.handle_event(Events::Fail).is_err());
+        assert_eq!(vec![(1, 2, Events::Start), (2, 1, Events::Stop)], *log.lock().unwrap());
+        assert_eq!(vec!["logger", "listener", "logger", "listener"], *order.lock().unwrap());
+    }
+
+    #[test]
+    fn test_replay_mode() {
+        #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+        enum Events {
+            Deposit(u32),
+            Close
+        }
+
+        let emails = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, u32, Mutex<u32>>::new()
+            .with_predicated_transition_effect(1, Same, |d| matches!(d.event, Events::Deposit(_)), |d| {
+                if let Events::Deposit(amount) = d.event {
+                    *d.data.lock().unwrap() += amount;
+                }
+                Ok(())
+            })
+            .with_event_transition_effect(&Events::Close, 1, 2, |_| {
+                emails.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .side_effecting()
+            .lock();
+
+        let log = [Events::Deposit(5), Events::Deposit(7), Events::Close];
+        let mut sm = factory.build(1, Mutex::new(0));
+        sm.set_replay_mode(true);
+        assert!(sm.is_replaying());
+        for event in log {
+            sm.handle_event(event).expect("unexpected error");
+        }
+        assert_eq!((2, 12), (sm.state, *sm.data.lock().unwrap()));
+        assert_eq!(0, emails.load(Ordering::SeqCst));
+
+        let mut sm = factory.build(1, Mutex::new(0));
+        for event in log {
+            sm.handle_event(event).expect("unexpected error");
+        }
+        assert_eq!(1, emails.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_max_transitions() {
+        let mut sm = StateMachineFactory::<(), u32, ()>::new()
+            .cycle(true)
+            .with_max_transitions(10)
+            .with_auto_transition(1, 2)
+            .with_auto_transition(2, 1)
+            .lock()
+            .build(1, ());
+
+        // The two auto transitions would otherwise loop forever
+        match sm.handle_event(()) {
+            Err(StateMachineError::CircuitOpen(1, 10)) => {}
+            other => panic!("expected the circuit to open, got {:?}", other.map_err(|e| e.to_string()))
+        }
+        assert_eq!(10, sm.transitions_taken());
+        assert!(sm.handle_event(()).is_err());
+
+        sm.reset_transitions_taken();
+        assert_eq!(0, sm.transitions_taken());
+        assert!(matches!(sm.handle_event(()), Err(StateMachineError::CircuitOpen(1, 10))));
+    }
+
+    #[test]
+    fn test_sticky_event() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Normal,
+            Shifted
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Shift,
+            Digit(u8)
+        }
+
+        let typed = Mutex::new(String::new());
+        let digit = |d: &StateTransitionEffectData<Events, States, ()>| matches!(d.event, Events::Digit(_));
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Shift, States::Normal, States::Shifted)
+            .with_predicated_transition_effect(States::Normal, Same, digit, |d| {
+                if let Events::Digit(digit) = d.event {
+                    typed.lock().unwrap().push((b'0' + digit) as char);
+                }
+                Ok(())
+            })
+            // A shifted Digit types a symbol and releases the modifier
+            .with_predicated_transition_effect(States::Shifted, States::Normal, digit, |d| {
+                if let Events::Digit(digit) = d.event {
+                    typed.lock().unwrap().push(b")!@#$%^&*("[*digit as usize] as char);
+                }
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(States::Normal, ());
+        sm.handle_event(Events::Shift).expect("unexpected error");
+        sm.handle_event(Events::Digit(1)).expect("unexpected error");
+        sm.handle_event(Events::Digit(1)).expect("unexpected error");
+        assert_eq!("!1", *typed.lock().unwrap());
+
+        // A held Shift applies before every Digit, until it is released
+        sm.set_sticky(Events::Shift);
+        sm.handle_event(Events::Digit(2)).expect("unexpected error");
+        sm.handle_event(Events::Digit(3)).expect("unexpected error");
+        sm.clear_sticky(&Events::Shift);
+        assert!(sm.sticky_events().is_empty());
+        sm.handle_event(Events::Digit(4)).expect("unexpected error");
+        assert_eq!("!1@#4", *typed.lock().unwrap());
+    }
+
+    #[test]
+    fn test_sticky_event_survives_panic() {
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Shift,
+            Panic
+        }
+
+        let mut sm = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition_effect(&Events::Shift, Any, Same, |_| Ok(()))
+            .with_event_transition_effect(&Events::Panic, Any, Same, |_| panic!("effect panicked"))
+            .lock()
+            .build(0, ());
+
+        sm.set_sticky(Events::Shift);
+        sm.set_sticky(Events::Panic);
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| sm.handle_event(Events::Shift).map(|_| ()))).is_err());
+        assert_eq!(&[Events::Shift, Events::Panic], sm.sticky_events());
+    }
+
+    #[test]
+    fn test_state_changed() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Digit,
+            Add
+        }
+
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition_effect(&Events::Digit, Any, Same, |_| Ok(()))
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .lock();
+
+        let mut sm = factory.build(States::Idle, ());
+        assert!(!sm.state_changed());
+        sm.handle_event(Events::Digit).expect("unexpected error");
+        assert!(!sm.state_changed());
+        sm.handle_event(Events::Add).expect("unexpected error");
+        assert!(sm.state_changed());
+        // Add is rejected in Adding
+        sm.handle_event(Events::Add).expect("unexpected error");
+        assert!(!sm.state_changed());
+    }
+
+    #[test]
+    fn test_source_event_effect() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Draft,
+            Approved,
+            Rejected
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Review(u32),
+            Edit
+        }
+
+        let left = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .strict()
+            .with_event_key(std::mem::discriminant)
+            .with_source_event_effect(States::Draft, &Events::Review(0), |d| {
+                left.lock().unwrap().push(*d.from);
+                Ok(())
+            })
+            .with_event_transition_effect(&Events::Review(0), States::Draft, crate::ToState::Calc(Box::new(|d: crate::StateTransitionToStateData<Events, States, ()>| match d.event {
+                Events::Review(score) if *score >= 5 => States::Approved,
+                _ => States::Rejected
+            })), |_| Ok(()))
+            .with_event_transition(&Events::Edit, Any, States::Draft)
+            .lock();
+
+        let mut sm = factory.build(States::Draft, ());
+        assert_eq!(&States::Approved, sm.handle_event(Events::Review(7)).expect("unexpected error"));
+        sm.handle_event(Events::Review(7)).expect("unexpected error");
+        sm.handle_event(Events::Edit).expect("unexpected error");
+        assert_eq!(&States::Rejected, sm.handle_event(Events::Review(2)).expect("unexpected error"));
+        assert_eq!(vec![States::Draft, States::Draft], *left.lock().unwrap());
+    }
+
+    #[test]
+    fn test_event_to_payload_transition_effect() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum Screens {
+            Home,
+            Settings,
+            Profile
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            GoTo(Screens),
+            Back
+        }
+
+        let visited = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<Events, Screens, ()>::new()
+            .with_event_key(std::mem::discriminant)
+            .with_event_to_payload_transition_effect(&Events::GoTo(Screens::Home), Any, |e| match e {
+                Events::GoTo(screen) => *screen,
+                Events::Back => unreachable!("only GoTo Events match")
+            }, |d| {
+                visited.lock().unwrap().push(*d.to);
+                Ok(())
+            })
+            .with_event_transition(&Events::Back, Any, Screens::Home)
+            .lock();
+
+        let mut sm = factory.build(Screens::Home, ());
+        assert_eq!(&Screens::Profile, sm.handle_event(Events::GoTo(Screens::Profile)).expect("unexpected error"));
+        assert_eq!(&Screens::Settings, sm.handle_event(Events::GoTo(Screens::Settings)).expect("unexpected error"));
+        assert_eq!(&Screens::Home, sm.handle_event(Events::Back).expect("unexpected error"));
+        assert_eq!(vec![Screens::Profile, Screens::Settings], *visited.lock().unwrap());
+    }
+
+    #[test]
+    fn test_with_observers_suppressed() {
+        let notified = AtomicUsize::new(0);
+        let logged = AtomicUsize::new(0);
+        let effects = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<(), u32, ()>::new()
+            .with_transition_effect(Any, crate::ToState::Calc(Box::new(|d: crate::StateTransitionToStateData<_, u32, _>| d.from + 1)), |_| {
+                effects.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_listener(|_| {
+                notified.fetch_add(1, Ordering::SeqCst);
+            })
+            .with_global_transition_logger(|_, _, _| {
+                logged.fetch_add(1, Ordering::SeqCst);
+            })
+            .lock();
+
+        let mut sm = factory.build(0, ());
+        let restored = sm.with_observers_suppressed(|sm| {
+            for _ in 0..3 {
+                sm.handle_event(()).expect("unexpected error");
+            }
+            sm.state
+        });
+        assert_eq!((3, 3), (restored, effects.load(Ordering::SeqCst)));
+        assert_eq!((0, 0), (notified.load(Ordering::SeqCst), logged.load(Ordering::SeqCst)));
+
+        sm.handle_event(()).expect("unexpected error");
+        assert_eq!((1, 1), (notified.load(Ordering::SeqCst), logged.load(Ordering::SeqCst)));
+
+        // A panicking batch doesn't leave observers suppressed
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| sm.with_observers_suppressed(|_| panic!("batch panicked")))).is_err());
+        sm.handle_event(()).expect("unexpected error");
+        assert_eq!((2, 2), (notified.load(Ordering::SeqCst), logged.load(Ordering::SeqCst)));
+    }
+
+    #[test]
+    fn test_invariant() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Entering,
+            Showing
+        }
+
+        let factory = StateMachineFactory::<f64, States, Mutex<f64>>::new()
+            .with_transition_effect(States::Entering, States::Showing, |d| {
+                let mut stored_value = d.data.lock().unwrap();
+                *stored_value /= *d.event;
+                Ok(())
+            })
+            .with_invariant(|_, data| if data.lock().unwrap().is_nan() {
+                Err("stored value is NaN".to_string())
+            } else {
+                Ok(())
+            })
+            .lock();
+
+        let mut sm = factory.build(States::Entering, Mutex::new(0.0));
+        match sm.handle_event(0.0) {
+            Err(StateMachineError::InvariantViolated(States::Entering, States::Showing, violation)) => assert_eq!("stored value is NaN", violation),
+            other => panic!("expected an invariant violation, got {:?}", other)
+        }
+        assert_eq!(States::Entering, sm.state);
+
+        *sm.data.lock().unwrap() = 6.0;
+        assert_eq!(&States::Showing, sm.handle_event(2.0).expect("unexpected error"));
+        assert_eq!(3.0, *sm.data.lock().unwrap());
+    }
+
+    #[test]
+    fn test_recovery_transition() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding,
+            Dividing
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Digit(u8),
+            Add,
+            Divide,
+            Clear
+        }
+
+        let factory = StateMachineFactory::<Events, States, AtomicUsize>::new()
+            .with_predicated_transition_effect(Any, Same, |d| matches!(d.event, Events::Digit(_)), |d| {
+                if let Events::Digit(digit) = d.event {
+                    d.data.store(d.data.load(Ordering::SeqCst) * 10 + *digit as usize, Ordering::SeqCst);
+                }
+                Ok(())
+            })
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Divide, States::Idle, States::Dividing)
+            .with_recovery_transition(&Events::Clear, States::Idle, |d| {
+                d.data.store(0, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock();
+
+        for operator in [Events::Add, Events::Divide] {
+            let mut sm = factory.build(States::Idle, AtomicUsize::new(0));
+            sm.handle_event(Events::Digit(4)).expect("unexpected error");
+            sm.handle_event(operator).expect("unexpected error");
+            sm.handle_event(Events::Digit(2)).expect("unexpected error");
+            assert_eq!(&States::Idle, sm.handle_event(Events::Clear).expect("unexpected error"));
+            assert_eq!(0, sm.data.load(Ordering::SeqCst));
+        }
+        let mut sm = factory.build(States::Idle, AtomicUsize::new(7));
+        assert_eq!(&States::Idle, sm.handle_event(Events::Clear).expect("unexpected error"));
+        assert_eq!(0, sm.data.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_fire_all() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Digit { digit: u8 },
+            Add,
+            Equals
+        }
+
+        let factory = StateMachineFactory::<Events, States, AtomicUsize>::new()
+            .with_predicated_transition_effect(Any, Same, |d| matches!(d.event, Events::Digit { .. }), |d| {
+                if let Events::Digit { digit } = d.event {
+                    d.data.fetch_add(*digit as usize, Ordering::SeqCst);
+                }
+                Ok(())
+            })
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition_effect(&Events::Equals, States::Idle, States::Idle, |_| Err(anyhow!("nothing to add").into()))
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .lock();
+
+        let mut sm = factory.build(States::Idle, AtomicUsize::new(0));
+        assert_eq!(&States::Adding, sm.fire_all([Events::Digit { digit: 2 }, Events::Add, Events::Digit { digit: 3 }]).expect("unexpected error"));
+        assert_eq!(&States::Idle, sm.fire(Events::Equals).expect("unexpected error"));
+        assert_eq!(&States::Idle, sm.fire_all([]).expect("unexpected error"));
+        // Handling stops at the first failing Event
+        assert!(sm.fire_all([Events::Equals, Events::Digit { digit: 4 }]).is_err());
+        assert_eq!(5, sm.data.load(Ordering::SeqCst));
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_event_constructors() {
+        use crate::EventConstructors;
+
+        #[derive(EventConstructors, Eq, PartialEq, Debug)]
+        enum Events {
+            Clear,
+            Digit { digit: u8 },
+            SetOperand(i64, bool)
+        }
+
+        assert_eq!(Events::Clear, Events::clear());
+        assert_eq!(Events::Digit { digit: 2 }, Events::digit(2));
+        assert_eq!(Events::SetOperand(-4, true), Events::set_operand(-4, true));
+
+        let mut sm = StateMachineFactory::<Events, u8, ()>::new()
+            .with_event_transition(&Events::Clear, Any, 0)
+            .with_event_key(std::mem::discriminant)
+            .with_event_to_payload_transition_effect(&Events::Digit { digit: 0 }, Any, |event| match event {
+                Events::Digit { digit } => *digit,
+                _ => unreachable!("only Digit Events match")
+            }, |_| Ok(()))
+            .lock()
+            .build(0, ());
+        assert_eq!(&7, sm.fire_all([Events::digit(3), Events::digit(7)]).expect("unexpected error"));
+        assert_eq!(&0, sm.fire(Events::clear()).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_counted_transition() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            LoggedOut,
+            LoggedIn,
+            Locked
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            FailedAttempt,
+            Login,
+            Logout
+        }
+
+        let locked = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_counted_transition_effect(States::LoggedOut, &Events::FailedAttempt, 3, States::Locked, |_| {
+                locked.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .with_event_transition(&Events::Login, States::LoggedOut, States::LoggedIn)
+            .with_event_transition(&Events::Logout, States::LoggedIn, States::LoggedOut)
+            .lock();
+
+        let mut sm = factory.build(States::LoggedOut, ());
+        assert_eq!(&States::LoggedOut, sm.handle_event(Events::FailedAttempt).expect("unexpected error"));
+        assert_eq!(&States::LoggedOut, sm.handle_event(Events::FailedAttempt).expect("unexpected error"));
+        assert_eq!(0, locked.load(Ordering::SeqCst));
+        assert_eq!(&States::Locked, sm.handle_event(Events::FailedAttempt).expect("unexpected error"));
+        assert_eq!(1, locked.load(Ordering::SeqCst));
+
+        // Leaving the State starts the count over
+        let mut sm = factory.build(States::LoggedOut, ());
+        sm.handle_event(Events::FailedAttempt).expect("unexpected error");
+        sm.handle_event(Events::FailedAttempt).expect("unexpected error");
+        sm.handle_event(Events::Login).expect("unexpected error");
+        sm.handle_event(Events::Logout).expect("unexpected error");
+        sm.handle_event(Events::FailedAttempt).expect("unexpected error");
+        assert_eq!(&States::LoggedOut, sm.handle_event(Events::FailedAttempt).expect("unexpected error"));
+        assert_eq!(&States::Locked, sm.handle_event(Events::FailedAttempt).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_fallback_transition() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding,
+            Error
+        }
+
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Add,
+            Equals,
+            Multiply
+        }
+
+        let unexpected = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_fallback_transition_effect(States::Idle, States::Error, |d| {
+                unexpected.lock().unwrap().push(format!("{:?} in {:?}", d.event, d.from));
+                Ok(())
+            })
+            .with_global_transition_logger(|_, _, _| {})
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .lock();
+
+        let mut sm = factory.build(States::Idle, ());
+        assert_eq!(&States::Adding, sm.handle_event(Events::Add).expect("unexpected error"));
+        // Adding has no fallback, so unknown Events are ignored there
+        assert_eq!(&States::Adding, sm.handle_event(Events::Multiply).expect("unexpected error"));
+        assert_eq!(&States::Idle, sm.handle_event(Events::Equals).expect("unexpected error"));
+        assert_eq!(&States::Error, sm.handle_event(Events::Multiply).expect("unexpected error"));
+        assert_eq!(vec!["Multiply in Idle"], *unexpected.lock().unwrap());
+    }
+
+    #[test]
+    fn test_fallback_after_same_transition() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum Events {
+            Digit(u8),
+            Multiply
+        }
+
+        let digits = Mutex::new(Vec::new());
+        let fallbacks = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, u8, ()>::new()
+            .with_predicated_transition_effect(0, Same, |d| matches!(d.event, Events::Digit(_)), |d| {
+                digits.lock().unwrap().push(*d.event);
+                Ok(())
+            })
+            .with_fallback_transition_effect(0, 1, |_| {
+                fallbacks.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock();
+
+        // The digit is handled without changing state, so the fallback doesn't run
+        let mut sm = factory.build(0, ());
+        assert_eq!(&0, sm.handle_event(Events::Digit(4)).expect("unexpected error"));
+        assert_eq!(0, fallbacks.load(Ordering::SeqCst));
+        assert_eq!(vec![Events::Digit(4)], *digits.lock().unwrap());
+        assert_eq!(&1, sm.handle_event(Events::Multiply).expect("unexpected error"));
+        assert_eq!(1, fallbacks.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_event_range_transition_effect() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Entering,
+            Invalid
+        }
+
+        #[derive(Eq, PartialEq, Debug)]
+        enum Events {
+            Digit(u8),
+            Add
+        }
+
+        impl Events {
+            fn digit_value(&self) -> Option<u8> {
+                match self {
+                    Events::Digit(digit) => Some(*digit),
+                    Events::Add => None
+                }
+            }
+        }
+
+        let entered = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_range_transition_effect(AnyOf(vec![States::Idle, States::Entering]), States::Entering, Events::digit_value, 0..=9, |d| {
+                entered.lock().unwrap().extend(d.event.digit_value());
+                Ok(())
+            })
+            .with_event_range_transition_effect(Any, States::Invalid, Events::digit_value, 10.., |_| Ok(()))
+            .with_event_transition(&Events::Add, States::Entering, States::Idle);
+        assert!(factory.to_dot().contains("\"Idle\" -> \"Entering\" [label=\"0..=9\"];"), "{}", factory.to_dot());
+
+        let mut sm = factory.lock().build(States::Idle, ());
+        assert_eq!(&States::Entering, sm.handle_event(Events::Digit(4)).expect("unexpected error"));
+        assert_eq!(&States::Entering, sm.handle_event(Events::Digit(2)).expect("unexpected error"));
+        assert_eq!(&States::Idle, sm.handle_event(Events::Add).expect("unexpected error"));
+        assert_eq!(&States::Invalid, sm.handle_event(Events::Digit(12)).expect("unexpected error"));
+        assert_eq!(vec![4, 2], *entered.lock().unwrap());
+    }
+
+    #[test]
+    fn test_replay_log() {
+        let emails = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<u32, u32, ()>::new()
+            .with_transition_effect(Any, To(2), |_| {
+                emails.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .side_effecting()
+            .lock();
+
+        let mut sm = factory.build(1, ());
+        // Any source of Events will do, such as an iterator decoding them as they are read
+        let log: &[u8] = &[3, 5];
+        assert_eq!(&2, sm.replay_log(log.iter().map(|&event| u32::from(event))).expect("unexpected error"));
+        assert!(!sm.is_replaying());
+        assert_eq!(0, emails.load(Ordering::SeqCst));
+
+        // Live Events aren't replayed after a panic while replaying
+        let mut sm = StateMachineFactory::<u32, u32, ()>::new()
+            .with_predicated_transition_effect(Any, Same, |d| *d.event == 0, |_| panic!("corrupt log"))
+            .lock()
+            .build(1, ());
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| sm.replay_log([0]).map(|_| ()))).is_err());
+        assert!(!sm.is_replaying());
+    }
+
+    #[test]
+    fn test_pending_events() {
+        #[derive(Clone, Eq, PartialEq, Debug)]
+        enum Events {
+            Start,
+            Digit(u8),
+            Clear
+        }
+
+        let entered = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition_effect(&Events::Start, Any, Same, |d| {
+                d.enqueue(Events::Digit(1));
+                d.enqueue(Events::Clear);
+                d.enqueue_priority(Events::Digit(2), 10);
+                assert_eq!(vec![Events::Digit(2), Events::Digit(1), Events::Clear], d.pending_events());
+                Ok(())
+            })
+            // Digits about to be cleared anyway aren't worth entering
+            .with_predicated_transition_effect(Any, Same, |d| matches!(d.event, Events::Digit(_)) && !d.pending_events().contains(&Events::Clear), |d| {
+                entered.lock().unwrap().push(d.event.clone());
+                Ok(())
+            })
+            .with_event_transition_effect(&Events::Clear, Any, Same, |d| {
+                d.enqueue(Events::Digit(3));
+                Ok(())
+            })
+            .lock().build(0, ());
+
+        sm.handle_event(Events::Start).expect("unexpected error");
+        assert_eq!(vec![Events::Digit(3)], *entered.lock().unwrap());
+    }
+
+    #[test]
+    fn test_bound_effect() {
+        fn round(value: f64, precision: &u32) -> f64 {
+            let scale = 10f64.powi(*precision as i32);
+            (value * scale).round() / scale
+        }
+
+        let precision = Arc::new(2);
+        let mut sm = StateMachineFactory::<f64, u32, Mutex<Vec<f64>>>::new()
+            .with_bound_effect(precision.clone(), Any, Same, |precision, d| {
+                d.data.lock().unwrap().push(round(1.0 / *d.event, precision));
+                Ok(())
+            })
+            .with_bound_effect(precision, Any, Same, |precision, d| {
+                d.data.lock().unwrap().push(round(2.0 / *d.event, precision));
+                Ok(())
+            })
+            .lock()
+            .build(0, Mutex::default());
+
+        sm.handle_event(3.0).expect("unexpected error");
+        assert_eq!(vec![0.33, 0.67], *sm.data.lock().unwrap());
+    }
+
+    #[test]
+    fn test_transit_effect() {
+        let order = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::<(), u32, ()>::new()
+            .with_transition_effect(1, To(2), |_| {
+                order.lock().unwrap().push("effect");
+                Ok(())
+            })
+            .with_entry_effect(2, |_| {
+                order.lock().unwrap().push("entry");
+                Ok(())
+            })
+            .with_transit_effect(1, 2, |d| {
+                assert_eq!((&1, &2), (d.from, d.to));
+                order.lock().unwrap().push("transit");
+                Ok(())
+            })
+            .with_transit_effect(3, 2, |_| {
+                order.lock().unwrap().push("unrelated transit");
+                Ok(())
+            })
+            .lock()
+            .build(1, ());
+
+        sm.handle_event(()).expect("unexpected error");
+        assert_eq!(vec!["effect", "transit", "entry"], *order.lock().unwrap());
+    }
+
+    #[test]
+    fn test_ignored_event() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Idle,
+            Adding
+        }
+
+        #[derive(Clone, Eq, PartialEq, Debug)]
+        enum Events {
+            Add,
+            Equals
+        }
+
+        let rejected = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_ignored_event(States::Idle, &Events::Equals)
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .with_after_dispatch(|_, _, outcome| {
+                if matches!(outcome, crate::DispatchOutcome::Rejected) {
+                    rejected.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        assert!(factory.unhandled_events(&[States::Idle], &[Events::Add, Events::Equals]).is_empty());
+        assert!(factory.to_matrix(&[Events::Equals]).contains("| Idle   | ignored |"));
+
+        let mut sm = factory.lock().build(States::Idle, ());
+        assert_eq!(&States::Idle, sm.handle_event(Events::Equals).expect("unexpected error"));
+        assert!(!sm.state_changed());
+        sm.handle_event(Events::Add).expect("unexpected error");
+        sm.handle_event(Events::Add).expect("unexpected error");
+        assert_eq!(1, rejected.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_enqueue_depth() {
+        let handled = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::<u32, u32, ()>::new()
+            .with_transition_effect(Any, Same, |d| {
+                handled.lock().unwrap().push((*d.event, d.depth()));
+                // Each Event cascades into two more, until the cascade is 2 deep
+                if d.depth() < 2 {
+                    d.enqueue(d.event * 10);
+                    d.enqueue(d.event * 10 + 1);
+                }
+                Ok(())
+            })
+            .lock()
+            .build(0, ());
+
+        sm.handle_event(1).expect("unexpected error");
+        assert_eq!(vec![(1, 0), (10, 1), (11, 1), (100, 2), (101, 2), (110, 2), (111, 2)], *handled.lock().unwrap());
+        handled.lock().unwrap().clear();
+        sm.handle_event(2).expect("unexpected error");
+        assert_eq!((2, 0), handled.lock().unwrap()[0]);
+    }
+}
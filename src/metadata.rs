@@ -0,0 +1,78 @@
+//! Metadata attached to States for external tooling.
+
+use std::fmt::Debug;
+use crate::StateMachineFactory;
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Attaches a `key` and `value` to `state`, such as `("category", "transient")`, for tools
+    /// rendering the definition to pick colors, descriptions or groupings from. Metadata is
+    /// included in [StateMachineFactory::to_dot] as node attributes and in
+    /// [StateMachineFactory::to_json_definition], but has no effect on the State Machines built
+    /// from the factory. Setting a key again replaces its value.
+    pub fn with_state_metadata(mut self, state: TState, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into(), value.into());
+        let entries = match self.definition.state_metadata.iter().position(|(annotated, _)| *annotated == state) {
+            Some(position) => &mut self.definition.state_metadata[position].1,
+            None => {
+                self.definition.state_metadata.push((state, Vec::new()));
+                &mut self.definition.state_metadata.last_mut().expect("an entry was just added").1
+            }
+        };
+        match entries.iter_mut().find(|(existing, _)| *existing == key) {
+            Some(entry) => entry.1 = value,
+            None => entries.push((key, value))
+        }
+        self
+    }
+
+    /// The metadata attached to `state` with [StateMachineFactory::with_state_metadata], as keys
+    /// and values in the order the keys were first set.
+    pub fn state_metadata(&self, state: &TState) -> &[(String, String)] {
+        self.definition.state_metadata.iter()
+            .find(|(annotated, _)| annotated == state)
+            .map_or(&[], |(_, entries)| entries.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::StateMachineFactory;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    enum Events {
+        Add,
+        Equals
+    }
+
+    #[test]
+    fn test_state_metadata() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .with_state_metadata(States::Idle, "category", "ready")
+            .with_state_metadata(States::Adding, "category", "operator")
+            .with_state_metadata(States::Adding, "description", "waiting for the \"second\" operand")
+            .with_state_metadata(States::Adding, "category", "transient");
+
+        assert_eq!(&[("category".to_string(), "ready".to_string())], factory.state_metadata(&States::Idle));
+        assert_eq!(2, factory.state_metadata(&States::Adding).len());
+        assert_eq!("digraph {
+    \"Idle\" [\"category\"=\"ready\"];
+    \"Adding\" [\"category\"=\"transient\", \"description\"=\"waiting for the \\\"second\\\" operand\"];
+    \"Idle\" -> \"Adding\" [label=\"Add\"];
+    \"Adding\" -> \"Idle\" [label=\"Equals\"];
+}
+", factory.to_dot());
+        assert!(factory.to_json_definition().contains(r#""state_metadata": {
+    "Idle": {"category": "ready"},
+    "Adding": {"category": "transient", "description": "waiting for the \"second\" operand"}
+  },"#));
+        assert!(!StateMachineFactory::<Events, States, ()>::new().to_json_definition().contains("state_metadata"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Runtime metrics collected by a [StateMachine], available with the `metrics` feature.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::mem::{discriminant, Discriminant};
+use crate::StateMachine;
+
+/// The kind of an Event: its enum variant, ignoring any payload.
+pub type EventKind<TEvent> = Discriminant<TEvent>;
+
+/// Number of Events received, by [EventKind].
+pub type EventCounts<TEvent> = HashMap<EventKind<TEvent>, u64>;
+
+/// Metrics collected while a [StateMachine] handles Events.
+#[derive(Clone)]
+pub struct Metrics<TEvent, TState> {
+    events_by_state: Vec<(TState, EventCounts<TEvent>)>,
+    guards_evaluated: u64,
+    max_guards_per_event: usize,
+}
+
+impl <TEvent, TState> Default for Metrics<TEvent, TState> {
+    fn default() -> Self {
+        Self {
+            events_by_state: Vec::new(),
+            guards_evaluated: 0,
+            max_guards_per_event: 0,
+        }
+    }
+}
+
+impl <TEvent, TState: PartialEq<TState> + Clone> Metrics<TEvent, TState> {
+    /// Every State that has received an Event, in the order each first did so, along with the
+    /// number of Events of each kind it received. Events are counted against the state the
+    /// machine was in when it received them, whether or not any Transition matched them; events
+    /// enqueued by effects are counted too.
+    pub fn events_by_state(&self) -> &[(TState, EventCounts<TEvent>)] {
+        &self.events_by_state
+    }
+
+    /// The number of Events of each kind received while in `state`.
+    pub fn events_in(&self, state: &TState) -> Option<&EventCounts<TEvent>> {
+        self.events_by_state.iter()
+            .find(|(s, _)| s == state)
+            .map(|(_, counts)| counts)
+    }
+
+    /// The total number of predicates evaluated while handling Events.
+    pub fn guards_evaluated(&self) -> u64 {
+        self.guards_evaluated
+    }
+
+    /// The most predicates evaluated while handling a single Event, for sizing the budget set with
+    /// [crate::StateMachineFactory::with_guard_budget].
+    pub fn max_guards_per_event(&self) -> usize {
+        self.max_guards_per_event
+    }
+
+    pub(crate) fn record_guards(&mut self, guards_evaluated: usize) {
+        self.guards_evaluated += guards_evaluated as u64;
+        self.max_guards_per_event = self.max_guards_per_event.max(guards_evaluated);
+    }
+
+    pub(crate) fn record_event(&mut self, state: &TState, event: &TEvent) {
+        let counts = match self.events_by_state.iter().position(|(s, _)| s == state) {
+            Some(position) => &mut self.events_by_state[position].1,
+            None => {
+                self.events_by_state.push((state.clone(), HashMap::new()));
+                &mut self.events_by_state.last_mut().expect("just pushed").1
+            }
+        };
+        *counts.entry(discriminant(event)).or_insert(0) += 1;
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// The metrics collected by this State Machine so far.
+    pub fn metrics(&self) -> &Metrics<TEvent, TState> {
+        &self.metrics
+    }
+}
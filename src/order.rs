@@ -0,0 +1,185 @@
+//! Ordering Transitions by the dependencies declared between their effects, and by the Transitions
+//! they override.
+
+use std::fmt::Debug;
+use crate::{DefinitionError, StateMachineFactory, StateMachineTransition};
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Identifies the Transition added last as `id`, as in `.with_transition_effect(..).effect_id("audit")`,
+    /// so that other Transitions can be ordered after it with [StateMachineFactory::after].
+    ///
+    /// # Panics
+    ///
+    /// Panics if no Transition has been added yet.
+    pub fn effect_id(mut self, id: impl Into<String>) -> Self {
+        self.transitions.last_mut().expect("effect_id() identifies the Transition added before it").effect_id = Some(id.into());
+        self
+    }
+
+    /// Orders the Transition added last after the one identified as `id` with
+    /// [StateMachineFactory::effect_id], wherever either was registered, as in
+    /// `.with_transition_effect(..).after("audit")`. When the factory is locked, Transitions are
+    /// sorted so that every Transition comes after those it depends on, and otherwise stay in
+    /// registration order. This suits ordering constraints between pairs of effects better than
+    /// rearranging a long list of registrations by hand.
+    ///
+    /// Locking fails with [DefinitionError::UnknownEffectId] if no Transition is identified as
+    /// `id`, and with [DefinitionError::EffectDependencyCycle] if the dependencies form a cycle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no Transition has been added yet.
+    pub fn after(mut self, id: impl Into<String>) -> Self {
+        self.transitions.last_mut().expect("after() orders the Transition added before it").after.push(id.into());
+        self
+    }
+
+    /// Makes the Transition added last override the one identified as `id` with
+    /// [StateMachineFactory::effect_id], as in `.with_predicated_transition_effect(..).overrides("divide")`,
+    /// for replacing specific behaviour of a base definition, such as one copied with
+    /// [StateMachineFactory::fork]. By default every matching Transition runs; instead, once the
+    /// overriding Transition has been taken for an Event (its predicate passed), the shadowed one
+    /// is skipped for that Event. If its predicate fails, the shadowed Transition runs as usual.
+    /// To make this possible, locking orders overriding Transitions before the ones they shadow,
+    /// as with [StateMachineFactory::after].
+    ///
+    /// Locking fails with [DefinitionError::UnknownEffectId] if no Transition is identified as
+    /// `id`, and with [DefinitionError::EffectDependencyCycle] if the ordering forms a cycle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no Transition has been added yet.
+    pub fn overrides(mut self, id: impl Into<String>) -> Self {
+        self.transitions.last_mut().expect("overrides() marks the Transition added before it").overrides.push(id.into());
+        self
+    }
+}
+
+/// The positions of the Transitions identified as `id`
+pub(crate) fn identified<TEvent, TState: PartialEq<TState> + Debug + Clone + Send, TData>(transitions: &[StateMachineTransition<'_, TEvent, TState, TData>], id: &String) -> Result<Vec<usize>, DefinitionError<TState>> {
+    let matching: Vec<usize> = transitions.iter()
+        .enumerate()
+        .filter(|(_, other)| other.effect_id.as_ref() == Some(id))
+        .map(|(position, _)| position)
+        .collect();
+    if matching.is_empty() {
+        return Err(DefinitionError::UnknownEffectId(id.clone()));
+    }
+    Ok(matching)
+}
+
+/// Sorts `transitions` so that each comes after the Transitions it is declared to depend on and
+/// the Transitions overriding it, keeping registration order wherever the dependencies allow it,
+/// and then records which Transitions shadow each one
+pub(crate) fn order_by_dependencies<TEvent, TState: PartialEq<TState> + Debug + Clone + Send, TData>(transitions: &mut Vec<StateMachineTransition<'_, TEvent, TState, TData>>) -> Result<(), DefinitionError<TState>> {
+    if transitions.iter().all(|transition| transition.after.is_empty() && transition.overrides.is_empty()) {
+        return Ok(());
+    }
+
+    // The positions of the Transitions each Transition has to come after
+    let mut dependencies = vec![Vec::new(); transitions.len()];
+    for (position, transition) in transitions.iter().enumerate() {
+        for id in &transition.after {
+            dependencies[position].extend(identified(transitions, id)?);
+        }
+        for id in &transition.overrides {
+            for shadowed in identified(transitions, id)? {
+                dependencies[shadowed].push(position);
+            }
+        }
+    }
+
+    // Repeatedly takes the earliest registered Transition whose dependencies have all been taken
+    let mut order = Vec::with_capacity(transitions.len());
+    let mut taken = vec![false; transitions.len()];
+    while order.len() < transitions.len() {
+        let Some(next) = (0..transitions.len())
+            .find(|&position| !taken[position] && dependencies[position].iter().all(|&dependency| taken[dependency])) else {
+            let cycle = (0..transitions.len())
+                .filter(|&position| !taken[position])
+                .filter_map(|position| transitions[position].effect_id.clone())
+                .collect();
+            return Err(DefinitionError::EffectDependencyCycle(cycle));
+        };
+        taken[next] = true;
+        order.push(next);
+    }
+
+    let mut unordered: Vec<_> = transitions.drain(..).map(Some).collect();
+    transitions.extend(order.into_iter().map(|position| unordered[position].take().expect("each position is taken once")));
+
+    for position in 0..transitions.len() {
+        let Some(id) = transitions[position].effect_id.clone() else {
+            continue;
+        };
+        transitions[position].shadowed_by = transitions.iter()
+            .enumerate()
+            .filter(|(_, other)| other.overrides.contains(&id))
+            .map(|(overriding, _)| overriding)
+            .collect();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Mutex;
+    use crate::{DefinitionError, StateMachineFactory};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[test]
+    fn test_effect_dependencies() {
+        let order = Mutex::new(Vec::new());
+        let factory = StateMachineFactory::<(), u32, ()>::new()
+            .with_transition_effect(Any, Same, |_| {
+                order.lock().unwrap().push("b");
+                Ok(())
+            })
+            .after("a")
+            .with_transition_effect(Any, Same, |_| {
+                order.lock().unwrap().push("a");
+                Ok(())
+            })
+            .effect_id("a")
+            .lock();
+
+        factory.build(1, ()).handle_event(()).expect("unexpected error");
+        assert_eq!(vec!["a", "b"], *order.lock().unwrap());
+
+        let cyclic = StateMachineFactory::<(), u32, ()>::new()
+            .with_transition_effect(Any, Same, |_| Ok(())).effect_id("a").after("b")
+            .with_transition_effect(Any, Same, |_| Ok(())).effect_id("b").after("a");
+        assert_eq!(Some(DefinitionError::EffectDependencyCycle(vec!["a".to_string(), "b".to_string()])), cyclic.try_lock().err());
+        let unknown = StateMachineFactory::<(), u32, ()>::new()
+            .with_transition_effect(Any, Same, |_| Ok(())).after("missing");
+        assert_eq!(Some(DefinitionError::UnknownEffectId("missing".to_string())), unknown.try_lock().err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let base = StateMachineFactory::<f64, u32, Mutex<Vec<String>>>::new()
+            .with_transition_effect(Any, Same, |d| {
+                d.data.lock().unwrap().push(format!("{}", 84.0 / *d.event));
+                Ok(())
+            })
+            .effect_id("divide");
+        let checked = base.fork()
+            .with_predicated_transition_effect(Any, Same, |d| *d.event == 0.0, |d| {
+                d.data.lock().unwrap().push("division by zero".to_string());
+                Ok(())
+            })
+            .overrides("divide")
+            .lock();
+
+        let mut sm = checked.build(0, Mutex::default());
+        sm.handle_event(2.0).expect("unexpected error");
+        sm.handle_event(0.0).expect("unexpected error");
+        assert_eq!(vec!["42", "division by zero"], *sm.data.lock().unwrap());
+
+        // The base still divides by zero
+        let mut sm = base.lock().build(0, Mutex::default());
+        sm.handle_event(0.0).expect("unexpected error");
+        assert_eq!(vec!["inf"], *sm.data.lock().unwrap());
+    }
+}
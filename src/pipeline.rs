@@ -0,0 +1,150 @@
+//! Pipelines: a State Machine whose published domain events drive a second State Machine.
+
+use std::any::Any;
+use std::fmt::Debug;
+use thiserror::Error;
+use crate::{StateMachine, StateMachineError};
+
+/// Two State Machines chained together: the domain events the first one publishes (see
+/// [crate::StateTransitionEffectData::publish]) are the Events of the second. This composes small
+/// machines into a processing chain, such as a tokenizer feeding a parser.
+pub struct Pipeline<'a, TEvent, TState, TData, TOutput, TOutputState, TOutputData>
+where TState: PartialEq<TState> + Clone + Send + 'a, TOutputState: PartialEq<TOutputState> + Clone + Send + 'a
+{
+    first: StateMachine<'a, TEvent, TState, TData>,
+    second: StateMachine<'a, TOutput, TOutputState, TOutputData>,
+}
+
+/// The states of both State Machines of a [Pipeline] after it has handled an Event
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PipelineOutcome<TState, TOutputState> {
+    /// The state of the first State Machine
+    pub first: TState,
+    /// The state of the second State Machine
+    pub second: TOutputState,
+}
+
+/// Error handling an Event with [Pipeline::handle_event], from whichever State Machine failed
+#[derive(Error, Debug)]
+pub enum PipelineError<TState: Debug + Send + Clone + Eq + PartialEq, TOutputState: Debug + Send + Clone + Eq + PartialEq> {
+    /// The first State Machine failed to handle the Event; the second one received nothing
+    #[error("first state machine failed: {0}")]
+    First(StateMachineError<TState>),
+    /// The second State Machine failed to handle one of the first one's outputs; the outputs after
+    /// it were discarded
+    #[error("second state machine failed: {0}")]
+    Second(StateMachineError<TOutputState>),
+}
+
+impl <'a, TEvent, TState, TData, TOutput: Any, TOutputState, TOutputData> Pipeline<'a, TEvent, TState, TData, TOutput, TOutputState, TOutputData>
+where TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TOutputState: PartialEq<TOutputState> + Debug + Clone + Send + Eq + PartialEq + 'a
+{
+    /// Creates a `Pipeline` feeding the outputs of `first` into `second`.
+    pub fn new(first: StateMachine<'a, TEvent, TState, TData>, second: StateMachine<'a, TOutput, TOutputState, TOutputData>) -> Self {
+        Self {
+            first,
+            second,
+        }
+    }
+
+    /// The first State Machine, which handles the Events passed to [Pipeline::handle_event].
+    pub fn first(&self) -> &StateMachine<'a, TEvent, TState, TData> {
+        &self.first
+    }
+
+    /// The second State Machine, which handles the outputs of the first.
+    pub fn second(&self) -> &StateMachine<'a, TOutput, TOutputState, TOutputData> {
+        &self.second
+    }
+
+    /// Handles an Event with the first State Machine, as with
+    /// [StateMachine::handle_event_publishing], and then each domain event of type `TOutput` it
+    /// published with the second State Machine, in the order they were published.
+    pub fn handle_event(&mut self, event: TEvent) -> Result<PipelineOutcome<TState, TOutputState>, PipelineError<TState, TOutputState>> {
+        let outcome = self.first.handle_event_publishing::<TOutput>(event).map_err(PipelineError::First)?;
+        for output in outcome.domain_events {
+            self.second.handle_event(output).map_err(PipelineError::Second)?;
+        }
+        Ok(PipelineOutcome {
+            first: outcome.state,
+            second: self.second.state.clone(),
+        })
+    }
+
+    /// Splits the `Pipeline` back into its two State Machines.
+    pub fn into_inner(self) -> (StateMachine<'a, TEvent, TState, TData>, StateMachine<'a, TOutput, TOutputState, TOutputData>) {
+        (self.first, self.second)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Mutex;
+    use crate::{Pipeline, PipelineOutcome, StateMachineFactory};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Lexing {
+        Between,
+        InNumber
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Token {
+        Number(i64),
+        Plus,
+        End
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum Parsing {
+        ExpectingNumber,
+        ExpectingOperator,
+        Done
+    }
+
+    #[test]
+    fn test_pipeline() {
+        // Turns characters into Tokens, buffering the digits of each number
+        let tokenizer = StateMachineFactory::<char, Lexing, Mutex<String>>::new()
+            .with_predicated_transition_effect(Any, Lexing::InNumber, |d| d.event.is_ascii_digit(), |d| {
+                d.data.lock().unwrap().push(*d.event);
+                Ok(())
+            })
+            .with_predicated_transition_effect(Lexing::InNumber, Lexing::Between, |d| !d.event.is_ascii_digit(), |d| {
+                let digits = std::mem::take(&mut *d.data.lock().unwrap());
+                d.publish(Token::Number(digits.parse().expect("only digits are buffered")));
+                Ok(())
+            })
+            .with_publishing_transition_effect(Lexing::Between, Same, |d| Ok(match d.event {
+                '+' => vec![Token::Plus],
+                '=' => vec![Token::End],
+                _ => vec![]
+            }))
+            .lock();
+
+        // Sums the Numbers between Plus Tokens until the End
+        let parser = StateMachineFactory::<Token, Parsing, Mutex<i64>>::new()
+            .with_predicated_transition_effect(Parsing::ExpectingNumber, Parsing::ExpectingOperator, |d| matches!(d.event, Token::Number(_)), |d| {
+                if let Token::Number(number) = d.event {
+                    *d.data.lock().unwrap() += number;
+                }
+                Ok(())
+            })
+            .with_event_transition(&Token::Plus, Parsing::ExpectingOperator, Parsing::ExpectingNumber)
+            .with_event_transition(&Token::End, Parsing::ExpectingOperator, Parsing::Done)
+            .lock();
+
+        let mut pipeline = Pipeline::new(tokenizer.build(Lexing::Between, Mutex::default()), parser.build(Parsing::ExpectingNumber, Mutex::default()));
+        for character in "12+30".chars() {
+            pipeline.handle_event(character).expect("unexpected error");
+        }
+        assert_eq!(Parsing::ExpectingNumber, pipeline.second().state);
+        assert_eq!(PipelineOutcome {
+            first: Lexing::Between,
+            second: Parsing::Done,
+        }, pipeline.handle_event('=').expect("unexpected error"));
+        assert_eq!(42, *pipeline.second().data.lock().unwrap());
+    }
+}
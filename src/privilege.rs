@@ -0,0 +1,108 @@
+//! Transitions that can only be taken on presenting a token of the right kind.
+
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::{FromState, StateMachine, StateMachineError, StateMachineFactory, StateMachineTransition, StateTransitionEffectData, ToState};
+use crate::queue::DispatchContext;
+
+/// Boxed check of whether the token presented, if any, authorises a privileged Transition
+pub(crate) type PrivilegeCheck<'a> = Arc<dyn Fn(Option<&(dyn Any + Send)>) -> bool + Send + Sync + 'a>;
+
+/// A token authorising the privileged Transitions of its kind, obtained with
+/// [StateMachine::acquire_token] and presented with [StateMachine::handle_event_with_token]. The
+/// kind is any type the application chooses, such as an enum of operator roles, so that which
+/// Transitions need which authorisation is part of the API rather than hidden in guards on the
+/// data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransitionToken<TKind> {
+    kind: TKind,
+}
+
+impl <TKind> TransitionToken<TKind> {
+    /// The kind of privileged Transitions this token authorises.
+    pub fn kind(&self) -> &TKind {
+        &self.kind
+    }
+}
+
+impl <'a, TEvent: PartialEq<TEvent> + Sync, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Adds a Transition with a side effect for Events matching `event`, like
+    /// `.with_event_transition_effect(..)`, that may only be taken with a token of kind `kind`,
+    /// such as an emergency override. When the Transition applies but the Event wasn't handled
+    /// with [StateMachine::handle_event_with_token] and a token of that kind, handling fails with
+    /// [StateMachineError::Unauthorized] before the effect runs.
+    pub fn with_privileged_transition_effect<TKind: Any + PartialEq + Send + Sync + 'a>(mut self, kind: TKind, event: &'a TEvent, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self = self.with_event_transition_effect(event, from_state, get_to_state, effect);
+        self.transitions.last_mut().expect("a Transition was just added").privilege = Some(Arc::new(move |token| {
+            token.and_then(|token| token.downcast_ref::<TKind>()).is_some_and(|presented| *presented == kind)
+        }));
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Issues a token of kind `kind`, for authorising the privileged Transitions of that kind
+    /// (see [StateMachineFactory::with_privileged_transition_effect]). Hand out the State Machine,
+    /// or the tokens acquired from it, only to the parts of the application allowed to take them.
+    pub fn acquire_token<TKind>(&self, kind: TKind) -> TransitionToken<TKind> {
+        TransitionToken { kind }
+    }
+
+    /// Handles an Event like [StateMachine::handle_event], presenting `token` to authorise
+    /// privileged Transitions of its kind. The token applies to the Event and the Events it
+    /// enqueues, and is dropped once the Event has been handled.
+    pub fn handle_event_with_token<TKind: Any + Send>(&mut self, event: TEvent, token: TransitionToken<TKind>) -> Result<&TState, StateMachineError<TState>> {
+        let mut context = DispatchContext::new();
+        context.token = Some(Box::new(token.kind));
+        self.handle_external_event_in(&event, context)?;
+        Ok(&self.state)
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> StateMachineTransition<'a, TEvent, TState, TData> {
+    /// True unless the Transition is privileged and the token presented doesn't authorise it
+    pub(crate) fn authorised(&self, context: &DispatchContext<TEvent, TState>) -> bool {
+        self.privilege.as_ref().is_none_or(|check| check(context.token.as_deref()))
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{StateMachineError, StateMachineFactory};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Running,
+        Halted
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Override
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Roles {
+        Operator,
+        Supervisor
+    }
+
+    #[test]
+    fn test_privileged_transition() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_privileged_transition_effect(Roles::Supervisor, &Events::Override, States::Running, States::Halted, |_| Ok(()))
+            .lock();
+        let mut sm = factory.build(States::Running, ());
+
+        assert!(matches!(sm.handle_event(Events::Override), Err(StateMachineError::Unauthorized(States::Running, States::Halted))));
+        let operator = sm.acquire_token(Roles::Operator);
+        assert!(matches!(sm.handle_event_with_token(Events::Override, operator), Err(StateMachineError::Unauthorized(..))));
+        assert!(matches!(sm.handle_event_with_token(Events::Override, sm.acquire_token("supervisor")), Err(StateMachineError::Unauthorized(..))));
+        assert_eq!(States::Running, sm.state);
+
+        let supervisor = sm.acquire_token(Roles::Supervisor);
+        assert_eq!(&States::Halted, sm.handle_event_with_token(Events::Override, supervisor).expect("unexpected error"));
+    }
+}
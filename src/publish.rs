@@ -0,0 +1,99 @@
+//! Domain events published by effects, for event-sourcing integrations.
+
+use std::any::Any;
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::{FromState, StateMachine, StateMachineError, StateMachineFactory, StateMachineTransition, StateTransitionEffectData, ToState};
+
+/// The result of [StateMachine::handle_event_publishing]: the state the State Machine ended up in,
+/// and the domain events published while it got there.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TransitionOutcome<TState, TDomainEvent> {
+    /// The state after handling the Event and every Event it enqueued
+    pub state: TState,
+    /// The domain events published by effects, in the order they were published
+    pub domain_events: Vec<TDomainEvent>,
+}
+
+impl <TEvent, TState, TData> StateTransitionEffectData<'_, TEvent, TState, TData> {
+    /// Publishes a domain event: an outbound fact about what happened, such as `OrderShipped`, as
+    /// opposed to the Events that drive the State Machine. Domain events are collected for the
+    /// caller of [StateMachine::handle_event_publishing] to persist or forward, and discarded by
+    /// the other ways of handling an Event.
+    pub fn publish<TDomainEvent: Any + Send>(&self, domain_event: TDomainEvent) {
+        self.context.published.borrow_mut().push(Box::new(domain_event));
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Adds a Transition with no predicate, like `.with_transition_effect(..)`, whose effect returns
+    /// the domain events to publish. Each one is published with [StateTransitionEffectData::publish]
+    /// once the effect succeeds; if it fails, none are.
+    pub fn with_publishing_transition_effect<TDomainEvent: Any + Send>(mut self, from_state: impl Into<FromState<TState>>, get_to_state: impl Into<ToState<TEvent, TState, TData>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<Vec<TDomainEvent>, Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.transitions.push(StateMachineTransition::new(None, from_state.into(), get_to_state.into(), Some(Arc::new(move |d| {
+            let context = d.context;
+            for domain_event in effect(d)? {
+                context.published.borrow_mut().push(Box::new(domain_event));
+            }
+            Ok(())
+        }))));
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Handles an Event like [StateMachine::handle_event], and returns the domain events published
+    /// while handling it and every Event it enqueued, for the caller to persist to an event store.
+    /// Only domain events of type `TDomainEvent` are returned; a State Machine publishing several
+    /// types usually wraps them in one enum. If handling fails, the domain events are discarded
+    /// along with the error.
+    pub fn handle_event_publishing<TDomainEvent: Any>(&mut self, event: TEvent) -> Result<TransitionOutcome<TState, TDomainEvent>, StateMachineError<TState>> {
+        let context = self.handle_external_event(&event)?;
+        Ok(TransitionOutcome {
+            state: self.state.clone(),
+            domain_events: context.published.take().into_iter()
+                .filter_map(|domain_event| (domain_event as Box<dyn Any>).downcast().ok())
+                .map(|domain_event| *domain_event)
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{StateMachineFactory, TransitionOutcome};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Open,
+        Shipped
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Ship
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    enum OrderEvents {
+        Packed(u32),
+        Shipped(u32)
+    }
+
+    #[test]
+    fn test_publishing_transition_effect() {
+        let factory = StateMachineFactory::<Events, States, u32>::new()
+            .with_publishing_transition_effect(States::Open, States::Shipped, |d| {
+                Ok(vec![OrderEvents::Packed(*d.data), OrderEvents::Shipped(*d.data)])
+            })
+            .lock();
+
+        let mut sm = factory.build(States::Open, 7);
+        assert_eq!(TransitionOutcome {
+            state: States::Shipped,
+            domain_events: vec![OrderEvents::Packed(7), OrderEvents::Shipped(7)],
+        }, sm.handle_event_publishing(Events::Ship).expect("unexpected error"));
+        assert!(sm.handle_event_publishing::<OrderEvents>(Events::Ship).expect("unexpected error").domain_events.is_empty());
+    }
+}
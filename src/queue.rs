@@ -0,0 +1,178 @@
+//! Internal queue of events raised by effects while an event is being handled.
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::Duration;
+use crate::{CancellationToken, StateTransitionEffectData};
+
+/// Priority given to events enqueued with [StateTransitionEffectData::enqueue], and to listeners
+/// added with [crate::StateMachineFactory::with_listener]. This is the lowest priority, so these
+/// events are handled (and these listeners run) after any with a higher priority.
+pub const DEFAULT_PRIORITY: u32 = 0;
+
+/// Runtime context shared by every effect and predicate that runs while handling one external
+/// event, including the events raised by those effects.
+pub(crate) struct DispatchContext<TEvent, TState> {
+    queue: RefCell<EventQueue<TEvent>>,
+    /// The states of the regions of the enclosing [crate::CompositeMachine], if any
+    pub(crate) regions: Vec<TState>,
+    /// Events scheduled by effects, along with their delay, in the order they were scheduled
+    pub(crate) scheduled: RefCell<Vec<(Duration, TEvent)>>,
+    /// Domain events published by effects, in the order they were published
+    pub(crate) published: RefCell<Vec<Box<dyn Any + Send>>>,
+    /// How long the State Machine had been idle when the external event arrived
+    pub(crate) since_last_event: Duration,
+    /// The outcomes of the async guards awaited for the external event, by guard id, until that
+    /// event has been dispatched
+    pub(crate) async_guards: RefCell<Vec<bool>>,
+    /// The context passed to [crate::StateMachine::handle_event_with_guard_ctx], if any
+    pub(crate) guard_ctx: Option<Box<dyn Any + Send>>,
+    /// True while a predicate runs, the only time the guard context is visible
+    pub(crate) in_guard: Cell<bool>,
+    /// Tokens handed out with [crate::StateTransitionEffectData::exit_token] by the Transition
+    /// being taken, until it completes
+    pub(crate) exit_tokens: RefCell<Vec<CancellationToken>>,
+    /// How many enqueued Events led to the event being handled; see
+    /// [crate::StateTransitionEffectData::depth]
+    pub(crate) depth: Cell<usize>,
+    /// The kind of the token passed to [crate::StateMachine::handle_event_with_token], if any
+    pub(crate) token: Option<Box<dyn Any + Send>>,
+}
+
+impl <TEvent, TState> DispatchContext<TEvent, TState> {
+    pub(crate) fn new() -> Self {
+        Self::with_regions(Vec::new())
+    }
+
+    pub(crate) fn with_regions(regions: Vec<TState>) -> Self {
+        Self {
+            queue: RefCell::new(EventQueue::default()),
+            regions,
+            scheduled: RefCell::new(Vec::new()),
+            published: RefCell::new(Vec::new()),
+            since_last_event: Duration::ZERO,
+            async_guards: RefCell::new(Vec::new()),
+            guard_ctx: None,
+            in_guard: Cell::new(false),
+            exit_tokens: RefCell::new(Vec::new()),
+            depth: Cell::new(0),
+            token: None,
+        }
+    }
+
+    /// Removes the next event to be handled from the queue, if any, making its depth current
+    pub(crate) fn next_event(&self) -> Option<TEvent> {
+        let (event, depth) = self.queue.borrow_mut().pop()?;
+        self.depth.set(depth);
+        Some(event)
+    }
+}
+
+/// Priority queue of events, ordered by descending priority and then by insertion order
+struct EventQueue<TEvent> {
+    entries: BinaryHeap<QueuedEvent<TEvent>>,
+    next_sequence: u64,
+}
+
+impl <TEvent> Default for EventQueue<TEvent> {
+    fn default() -> Self {
+        Self {
+            entries: BinaryHeap::new(),
+            next_sequence: 0,
+        }
+    }
+}
+
+impl <TEvent> EventQueue<TEvent> {
+    fn push(&mut self, event: TEvent, priority: u32, depth: usize) {
+        self.entries.push(QueuedEvent {
+            priority,
+            sequence: Reverse(self.next_sequence),
+            event,
+            depth,
+        });
+        self.next_sequence += 1;
+    }
+
+    fn pop(&mut self) -> Option<(TEvent, usize)> {
+        self.entries.pop().map(|queued| (queued.event, queued.depth))
+    }
+
+    /// The queued events in the order they will be handled
+    fn pending(&self) -> Vec<&TEvent> {
+        let mut entries: Vec<&QueuedEvent<TEvent>> = self.entries.iter().collect();
+        entries.sort_by(|a, b| b.cmp(a));
+        entries.into_iter().map(|queued| &queued.event).collect()
+    }
+}
+
+struct QueuedEvent<TEvent> {
+    priority: u32,
+    sequence: Reverse<u64>,
+    event: TEvent,
+    /// The depth of the Event once it is handled
+    depth: usize,
+}
+
+impl <TEvent> QueuedEvent<TEvent> {
+    fn key(&self) -> (u32, Reverse<u64>) {
+        (self.priority, self.sequence)
+    }
+}
+
+impl <TEvent> PartialEq for QueuedEvent<TEvent> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl <TEvent> Eq for QueuedEvent<TEvent> {}
+
+impl <TEvent> PartialOrd for QueuedEvent<TEvent> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl <TEvent> Ord for QueuedEvent<TEvent> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl <TEvent, TState, TData> StateTransitionEffectData<'_, TEvent, TState, TData> {
+    /// Enqueues an Event to be handled by the State Machine once it has finished handling the
+    /// current one. Enqueued events are handled in the order they were enqueued, after any events
+    /// enqueued with a higher priority using [StateTransitionEffectData::enqueue_priority].
+    pub fn enqueue(&self, event: TEvent) {
+        self.enqueue_priority(event, DEFAULT_PRIORITY);
+    }
+
+    /// Enqueues an Event with a priority. Once the State Machine has finished handling the current
+    /// event, queued events are handled highest priority first, and in the order they were
+    /// enqueued within the same priority. Priorities only order this internal queue; they have no
+    /// bearing on events passed to [crate::StateMachine::handle_event] by the caller.
+    pub fn enqueue_priority(&self, event: TEvent, priority: u32) {
+        self.context.queue.borrow_mut().push(event, priority, self.context.depth.get() + 1);
+    }
+
+    /// How deep the Event being handled is in a chain of enqueued Events: 0 for an Event passed
+    /// to [crate::StateMachine::handle_event], 1 for an Event enqueued while handling it, and so
+    /// on. Effects that enqueue Events in response to Events can use this to stop a cascade at a
+    /// depth of their choosing, rather than running into
+    /// [crate::StateMachineFactory::with_queue_limit].
+    pub fn depth(&self) -> usize {
+        self.context.depth.get()
+    }
+
+    /// The Events enqueued so far that have yet to be handled, in the order they will be handled.
+    /// Guards can use this for flow control, such as skipping work that an Event already queued
+    /// for later would undo. Guards only decide whether their Transition applies, so they must
+    /// not enqueue Events themselves: leave that to effects, which run once the Transition is
+    /// taken.
+    pub fn pending_events(&self) -> Vec<TEvent> where TEvent: Clone {
+        self.context.queue.borrow().pending().into_iter().cloned().collect()
+    }
+}
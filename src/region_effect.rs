@@ -0,0 +1,137 @@
+//! Effects that run when the State Machine enters or leaves a set of States as a whole.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::{FromState, StateMachine, StateMachineError, StateMachineFactory, StateTransitionEffectData, TransitionEffect};
+use crate::queue::DispatchContext;
+
+/// An Effect registered with [StateMachineFactory::with_region_entry_effect] or
+/// [StateMachineFactory::with_region_exit_effect]
+pub(crate) struct RegionEffect<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    states: FromState<TState>,
+    /// True for an entry effect, false for an exit effect
+    entry: bool,
+    effect: TransitionEffect<'a, TEvent, TState, TData>,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Clone for RegionEffect<'a, TEvent, TState, TData> {
+    fn clone(&self) -> Self {
+        Self {
+            states: self.states.clone(),
+            entry: self.entry,
+            effect: self.effect.clone(),
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Adds an Effect that runs whenever the State Machine enters one of `states` from a state
+    /// outside them, such as entering "operator mode" in whichever of `Adding`, `Subtracting`,
+    /// `Multiplying` or `Dividing`. Transitions between the states of the set don't run it. Region
+    /// entry effects run after any transit effects (see [StateMachineFactory::with_transit_effect])
+    /// and before the entry effects of the state entered. Descending into initial substates doesn't
+    /// count as entering them.
+    pub fn with_region_entry_effect(mut self, states: impl Into<FromState<TState>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.definition.region_effects.push(RegionEffect {
+            states: states.into(),
+            entry: true,
+            effect: Arc::new(effect),
+        });
+        self
+    }
+
+    /// Adds an Effect that runs whenever the State Machine leaves `states` altogether, moving from
+    /// one of them to a state outside them, the counterpart of
+    /// [StateMachineFactory::with_region_entry_effect]. Transitions between the states of the set
+    /// don't run it. Region exit effects run once the effect of the Transition has run, before any
+    /// transit effects.
+    pub fn with_region_exit_effect(mut self, states: impl Into<FromState<TState>>, effect: impl Fn(StateTransitionEffectData<TEvent, TState, TData>) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self
+    {
+        self.definition.region_effects.push(RegionEffect {
+            states: states.into(),
+            entry: false,
+            effect: Arc::new(effect),
+        });
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Runs the region entry effects, or else the region exit effects, of the sets that a state
+    /// change from the current state into `to_state` enters or leaves
+    pub(crate) fn run_region_effects(&self, entry: bool, event: &TEvent, to_state: &TState, context: &DispatchContext<TEvent, TState>) -> Result<(), StateMachineError<TState>> {
+        for region_effect in self.definition.region_effects.iter().filter(|region_effect| region_effect.entry == entry) {
+            let was_inside = self.definition.state_matches(&region_effect.states, &self.state);
+            let is_inside = self.definition.state_matches(&region_effect.states, to_state);
+            if was_inside == is_inside || is_inside != entry {
+                continue;
+            }
+            let result = (region_effect.effect)(StateTransitionEffectData {
+                data: &self.data,
+                event,
+                from: &self.state,
+                to: to_state,
+                context
+            });
+            trace_decision!(effect = if entry { "region entry effect" } else { "region exit effect" }, ok = result.is_ok(), "effect run");
+            result.map_err(|e| StateMachineError::EffectError(self.state.clone(), to_state.clone(), e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Mutex;
+    use crate::StateMachineFactory;
+    use crate::FromState::AnyOf;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding,
+        Subtracting
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Add,
+        Subtract,
+        Equals
+    }
+
+    #[test]
+    fn test_region_effects() {
+        let operators = || AnyOf(vec![States::Adding, States::Subtracting]);
+        let order = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, AnyOf(vec![States::Idle, States::Subtracting]), States::Adding)
+            .with_event_transition(&Events::Subtract, AnyOf(vec![States::Idle, States::Adding]), States::Subtracting)
+            .with_event_transition(&Events::Equals, operators(), States::Idle)
+            .with_region_entry_effect(operators(), |d| {
+                order.lock().unwrap().push(format!("entered operators from {:?}", d.from));
+                Ok(())
+            })
+            .with_region_exit_effect(operators(), |d| {
+                order.lock().unwrap().push(format!("left operators for {:?}", d.to));
+                Ok(())
+            })
+            .with_entry_effect(States::Adding, |_| {
+                order.lock().unwrap().push("entered Adding".to_string());
+                Ok(())
+            })
+            .lock()
+            .build(States::Idle, ());
+
+        for event in [Events::Add, Events::Subtract, Events::Add, Events::Equals] {
+            sm.handle_event(event).expect("unexpected error");
+        }
+        assert_eq!(vec![
+            "entered operators from Idle",
+            "entered Adding",
+            "entered Adding",
+            "left operators for Idle"
+        ], *order.lock().unwrap());
+    }
+}
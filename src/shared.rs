@@ -0,0 +1,222 @@
+//! Thread-safe, shareable wrapper around a [StateMachine].
+
+use std::fmt::Debug;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::{StateMachine, StateMachineError};
+use crate::timer::Wakeup;
+
+/// A [StateMachine] behind an `Arc<RwLock<..>>`, for sharing one machine between threads (such as
+/// the handlers of a web server).
+///
+/// Reading the current state only takes a read lock, so any number of threads can do so
+/// concurrently, while [SharedStateMachine::handle_event] takes the write lock and so serializes
+/// transitions. Cloning a `SharedStateMachine` is cheap and yields another handle to the same
+/// machine. It is `Send` and `Sync` whenever the State and Data types are.
+///
+/// # Poisoning
+///
+/// If an effect or predicate panics while an event is being handled, the lock is poisoned.
+/// `SharedStateMachine` recovers from poisoning rather than propagating the panic to every other
+/// handle, and [SharedStateMachine::is_poisoned] reports whether this has happened. What the State
+/// Machine looks like afterwards depends on where the panic occurred:
+/// - A Transition only moves into its target after its effect, and any exit, transit and entry
+///   effects, have succeeded, so the state is whichever one had been reached before the panic.
+///   When the panic occurs while descending into initial substates, that is the parent, or one of
+///   the substates above the one being entered.
+/// - Sticky Events, replay mode and observers suppressed with
+///   [StateMachine::with_observers_suppressed] are restored as the panic unwinds.
+/// - Events enqueued or scheduled while handling the Event that panicked are discarded, and the
+///   cancellation tokens handed out for a state it didn't enter aren't cancelled.
+/// - Data mutated through interior mutability may have been partially updated by the effect that
+///   panicked.
+pub struct SharedStateMachine<'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    inner: Arc<RwLock<StateMachine<'a, TEvent, TState, TData>>>,
+    pub(crate) wakeup: Arc<Wakeup>,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Clone for SharedStateMachine<'a, TEvent, TState, TData> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            wakeup: self.wakeup.clone(),
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> SharedStateMachine<'a, TEvent, TState, TData> {
+    /// Wraps a `StateMachine` so that it can be shared between threads.
+    pub fn new(state_machine: StateMachine<'a, TEvent, TState, TData>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(state_machine)),
+            wakeup: Arc::new(Wakeup::default()),
+        }
+    }
+
+    /// Returns a copy of the current state, taking a read lock.
+    pub fn current_state(&self) -> TState {
+        self.read().state.clone()
+    }
+
+    /// Runs `read` with the data of the State Machine while holding a read lock, returning its
+    /// result, for taking a snapshot of data that isn't safe to read while effects are updating
+    /// it, as in `shared.read_data(|d| d.lock().unwrap().total)`. Any number of threads can read
+    /// the data concurrently, while [SharedStateMachine::handle_event] waits for them to finish,
+    /// so a Transition never runs in the middle of a read. A read waits for an Event that is
+    /// being handled, so data read from long-running effects is better kept in atomics, which
+    /// can be read without any lock.
+    ///
+    /// Handling an Event through any handle of the same machine from within `read` deadlocks, as
+    /// the write lock waits for the read lock to be released; return the data needed from `read`
+    /// and handle the Event afterwards.
+    pub fn read_data<R>(&self, read: impl FnOnce(&TData) -> R) -> R {
+        read(&self.read().data)
+    }
+
+    /// Handles an Event while holding the write lock, returning the resulting state.
+    pub fn handle_event(&self, event: TEvent) -> Result<TState, StateMachineError<TState>> {
+        let result = self.write().handle_event(event).cloned();
+        self.wakeup.notify();
+        result
+    }
+
+    /// True if an effect or predicate has panicked while this machine was handling an event.
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, StateMachine<'a, TEvent, TState, TData>> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, StateMachine<'a, TEvent, TState, TData>> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Wraps this `StateMachine` in a [SharedStateMachine], so that it can be shared between threads.
+    pub fn into_shared(self) -> SharedStateMachine<'a, TEvent, TState, TData> {
+        SharedStateMachine::new(self)
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+    use std::thread;
+    use crate::StateMachineFactory;
+    use crate::FromState::Any;
+    use crate::ToState::Calc;
+
+    #[test]
+    fn test_shared_state_machine() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Increment
+        }
+
+        let effects_run = AtomicUsize::new(0);
+        let factory = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition_effect(
+                &Events::Increment,
+                Any,
+                Calc(Box::new(|d| d.from + 1)),
+                |_| {
+                    effects_run.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+            .lock();
+        let shared = factory.build(0, ()).into_shared();
+
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                let reader = shared.clone();
+                scope.spawn(move || {
+                    let mut last_seen = 0;
+                    for _ in 0..1_000 {
+                        // States only ever increase, even while writers are transitioning
+                        let state = reader.current_state();
+                        assert!(state >= last_seen);
+                        last_seen = state;
+                    }
+                });
+            }
+            for _ in 0..2 {
+                let writer = shared.clone();
+                scope.spawn(move || {
+                    for _ in 0..10 {
+                        writer.handle_event(Events::Increment).expect("unexpected error");
+                    }
+                });
+            }
+        });
+
+        assert_eq!(20, shared.current_state());
+        assert_eq!(20, effects_run.load(Ordering::SeqCst));
+        assert!(!shared.is_poisoned());
+    }
+
+    #[test]
+    fn test_read_data() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Transfer
+        }
+
+        // Moves one unit from the first account to the second, which readers must never see half done
+        let shared = StateMachineFactory::<Events, u32, [AtomicU32; 2]>::new()
+            .with_event_transition_effect(&Events::Transfer, Any, Calc(Box::new(|d| d.from + 1)), |d| {
+                d.data[0].fetch_sub(1, Ordering::SeqCst);
+                thread::yield_now();
+                d.data[1].fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .lock()
+            .build(0, [AtomicU32::new(100), AtomicU32::new(0)])
+            .into_shared();
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let reader = shared.clone();
+                scope.spawn(move || {
+                    for _ in 0..1_000 {
+                        let total = reader.read_data(|accounts| accounts.iter().map(|account| account.load(Ordering::SeqCst)).sum::<u32>());
+                        assert_eq!(100, total);
+                    }
+                });
+            }
+            let writer = shared.clone();
+            scope.spawn(move || {
+                for _ in 0..50 {
+                    writer.handle_event(Events::Transfer).expect("unexpected error");
+                }
+            });
+        });
+
+        assert_eq!((50, 50), shared.read_data(|accounts| (accounts[0].load(Ordering::SeqCst), accounts[1].load(Ordering::SeqCst))));
+    }
+
+    #[test]
+    fn test_shared_state_machine_recovers_from_poisoning() {
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Panic,
+            Next
+        }
+
+        let shared = StateMachineFactory::<Events, u32, ()>::new()
+            .with_event_transition_effect(&Events::Panic, 0, 1, |_| panic!("effect panicked"))
+            .with_event_transition(&Events::Next, 0, 2)
+            .lock()
+            .build(0, ())
+            .into_shared();
+
+        let panicking = shared.clone();
+        assert!(thread::spawn(move || panicking.handle_event(Events::Panic)).join().is_err());
+
+        assert!(shared.is_poisoned());
+        // The panicking transition never completed, so the machine is still in its previous state
+        assert_eq!(0, shared.current_state());
+        assert_eq!(2, shared.handle_event(Events::Next).expect("unexpected error"));
+    }
+}
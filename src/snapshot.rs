@@ -0,0 +1,140 @@
+//! Snapshots of where a State Machine is, and the differences between them.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::{StateMachine, StateMachineFactory};
+
+/// Where a State Machine was when [StateMachine::snapshot] was called, for comparing machines
+/// across time or across replicas with [StateSnapshot::diff].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StateSnapshot<TEvent, TState> {
+    /// The current state
+    pub state: TState,
+    /// The state left by the most recent change of state, if the State Machine has changed state
+    pub previous_state: Option<TState>,
+    /// The most recent Event handled, if recorded; see [StateMachineFactory::record_last_event]
+    pub last_event: Option<TEvent>,
+}
+
+/// Which parts of two [StateSnapshot]s differ, returned by [StateSnapshot::diff]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SnapshotDiff {
+    /// True if the current states differ
+    pub state: bool,
+    /// True if the previous states differ
+    pub previous_state: bool,
+    /// True if the last Events differ
+    pub last_event: bool,
+}
+
+impl SnapshotDiff {
+    /// Determines whether the snapshots are the same in every respect.
+    pub fn is_empty(&self) -> bool {
+        !(self.state || self.previous_state || self.last_event)
+    }
+}
+
+impl <TEvent: PartialEq + Debug, TState: PartialEq + Debug> StateSnapshot<TEvent, TState> {
+    /// Compares this snapshot with `other`, such as the same State Machine at a later point, or a
+    /// replica that may have diverged from it.
+    pub fn diff(&self, other: &Self) -> SnapshotDiff {
+        SnapshotDiff {
+            state: self.state != other.state,
+            previous_state: self.previous_state != other.previous_state,
+            last_event: self.last_event != other.last_event,
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Makes State Machines remember the most recent Event they handled, including Events enqueued
+    /// by effects, for [StateMachine::snapshot]. This clones every Event, so it is off by default.
+    pub fn record_last_event(mut self) -> Self
+    where TEvent: Clone
+    {
+        if self.definition.event_clone.is_none() {
+            self.definition.event_clone = Some(Arc::new(TEvent::clone));
+        }
+        self.definition.record_last_event = true;
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// The state left by the most recent change of state, or `None` if the State Machine is still
+    /// in the state it was built in.
+    pub fn previous_state(&self) -> Option<&TState> {
+        self.previous_state.as_ref()
+    }
+
+    /// Takes a snapshot of the current state, the previous state and, with
+    /// [StateMachineFactory::record_last_event], the most recent Event handled.
+    pub fn snapshot(&self) -> StateSnapshot<TEvent, TState>
+    where TEvent: Clone
+    {
+        StateSnapshot {
+            state: self.state.clone(),
+            previous_state: self.previous_state.clone(),
+            last_event: self.last_event.clone(),
+        }
+    }
+
+    /// Remembers `event` as the most recent Event handled, if the definition records it
+    pub(crate) fn record_event(&mut self, event: &TEvent) {
+        if !self.definition.record_last_event {
+            return;
+        }
+        let clone = self.definition.event_clone.as_ref().expect("events are cloneable whenever the last one is recorded");
+        self.last_event = Some(clone(event));
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{SnapshotDiff, StateMachineFactory};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Running,
+        Done
+    }
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Start,
+        Finish,
+        Ping
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let factory = StateMachineFactory::new()
+            .with_event_transition(&Events::Start, States::Idle, States::Running)
+            .with_event_transition(&Events::Finish, States::Running, States::Done)
+            .record_last_event()
+            .lock();
+        let mut sm = factory.build(States::Idle, ());
+        let initial = sm.snapshot();
+        assert_eq!(None, initial.previous_state);
+        assert_eq!(None, initial.last_event);
+
+        sm.handle_event(Events::Start).expect("unexpected error");
+        let started = sm.snapshot();
+        assert_eq!(Some(&States::Idle), sm.previous_state());
+        assert_eq!(SnapshotDiff { state: true, previous_state: true, last_event: true }, initial.diff(&started));
+
+        // An Event that changes nothing only changes the last Event
+        sm.handle_event(Events::Ping).expect("unexpected error");
+        let pinged = sm.snapshot();
+        assert_eq!(SnapshotDiff { state: false, previous_state: false, last_event: true }, started.diff(&pinged));
+
+        let mut replica = factory.build(States::Idle, ());
+        replica.fire_all([Events::Start, Events::Ping]).expect("unexpected error");
+        assert!(replica.snapshot().diff(&pinged).is_empty());
+
+        sm.handle_event(Events::Finish).expect("unexpected error");
+        assert_eq!(Some(&States::Running), sm.previous_state());
+        assert!(!replica.snapshot().diff(&sm.snapshot()).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+//! Data for State Machines that keep read-only configuration apart from a mutable working set.
+
+use std::cell::{RefCell, RefMut};
+use std::fmt::Debug;
+use crate::{LockedStateMachineFactory, StateMachine, StateTransitionEffectData};
+
+/// State Machine data made up of two separate borrows: a shared, read-only configuration and an
+/// exclusive, mutable working set.
+///
+/// Effects only ever receive a shared reference to the State Machine's data, so the working set is
+/// handed out through a [RefCell]. Effects run one at a time, so the only way to trigger a borrow
+/// panic is to call [SplitData::working] again while a previous [RefMut] is still alive.
+pub struct SplitData<'c, TConfig, TWorking> {
+    config: &'c TConfig,
+    working: RefCell<&'c mut TWorking>,
+}
+
+impl <'c, TConfig, TWorking> SplitData<'c, TConfig, TWorking> {
+    /// Creates a `SplitData` from a read-only configuration and a mutable working set.
+    pub fn new(config: &'c TConfig, working: &'c mut TWorking) -> Self {
+        Self {
+            config,
+            working: RefCell::new(working),
+        }
+    }
+
+    /// The read-only configuration.
+    pub fn config(&self) -> &TConfig {
+        self.config
+    }
+
+    /// Mutable access to the working set.
+    pub fn working(&self) -> RefMut<'_, TWorking> {
+        RefMut::map(self.working.borrow_mut(), |working| &mut **working)
+    }
+}
+
+impl <'a, 'c, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TConfig, TWorking> LockedStateMachineFactory<'a, TEvent, TState, SplitData<'c, TConfig, TWorking>> {
+    /// Builds a StateMachine with a specified initial state, read-only configuration and mutable
+    /// working set.
+    pub fn build_split(&self, initial_state: TState, config: &'c TConfig, working: &'c mut TWorking) -> StateMachine<'a, TEvent, TState, SplitData<'c, TConfig, TWorking>> {
+        self.build(initial_state, SplitData::new(config, working))
+    }
+}
+
+impl <'c, TEvent, TState, TConfig, TWorking> StateTransitionEffectData<'_, TEvent, TState, SplitData<'c, TConfig, TWorking>> {
+    /// The read-only configuration of the State Machine.
+    pub fn config(&self) -> &TConfig {
+        self.data.config()
+    }
+
+    /// Mutable access to the working set of the State Machine.
+    pub fn working(&self) -> RefMut<'_, TWorking> {
+        self.data.working()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{SplitData, StateMachineFactory};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[test]
+    fn test_split_data() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Accumulating,
+            Full
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Add(u32)
+        }
+
+        struct Limits {
+            max_total: u32,
+        }
+
+        #[derive(Default)]
+        struct Accumulator {
+            total: u32,
+            rejected: u32,
+        }
+
+        let limits = Limits { max_total: 10 };
+        let mut accumulator = Accumulator::default();
+
+        {
+            let factory = StateMachineFactory::<Events, States, SplitData<Limits, Accumulator>>::new()
+                .with_transition_effect(
+                    Any,
+                    Same,
+                    |d| {
+                        let Events::Add(amount) = d.event;
+                        let mut working = d.working();
+                        if working.total + amount > d.config().max_total {
+                            working.rejected += 1;
+                        } else {
+                            working.total += amount;
+                        }
+                        Ok(())
+                    })
+                .with_predicated_transition(
+                    States::Accumulating,
+                    States::Full,
+                    |d| d.working().total == d.config().max_total)
+                .lock();
+
+            let mut sm = factory.build_split(States::Accumulating, &limits, &mut accumulator);
+            sm.handle_event(Events::Add(4)).expect("unexpected error");
+            sm.handle_event(Events::Add(7)).expect("unexpected error");
+            assert_eq!(States::Accumulating, sm.state);
+            sm.handle_event(Events::Add(6)).expect("unexpected error");
+            assert_eq!(States::Full, sm.state);
+        }
+
+        // The working set is handed back once the State Machine goes out of scope
+        assert_eq!(10, accumulator.total);
+        assert_eq!(1, accumulator.rejected);
+    }
+}
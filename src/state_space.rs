@@ -0,0 +1,126 @@
+//! Lazy exploration of the States reachable from an initial State.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use crate::{StateMachineFactory, Target};
+
+/// Iterator over the States reachable from an initial State, in breadth-first order, returned by
+/// [StateMachineFactory::state_space_iter]. The definition is only explored as far as the States
+/// taken from the iterator require.
+pub struct StateSpaceIter<'f, 'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> {
+    factory: &'f StateMachineFactory<'a, TEvent, TState, TData>,
+    events: &'f [TEvent],
+    /// Every State discovered so far
+    seen: Vec<TState>,
+    /// The States discovered but not returned yet
+    discovered: VecDeque<TState>,
+    /// The States returned whose Transitions haven't been followed yet
+    unexplored: VecDeque<TState>,
+    incomplete: bool,
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Explores the States reachable from `initial` through the Events in `events`, returning
+    /// `initial` followed by each newly discovered State in breadth-first order. Unlike the other
+    /// analyses, which build the whole graph up front, States are only discovered as they are
+    /// taken from the iterator, so exploring a huge definition can stop after a number of States
+    /// with [Iterator::take], or once a time budget runs out. As with
+    /// [StateMachineFactory::events_accepted_in], guards can't be evaluated statically, so guarded
+    /// Transitions count as taken, and Transitions without an Event apply to every Event. The
+    /// targets of [Calc](crate::ToState::Calc) Transitions aren't known until they are taken, so
+    /// they are skipped, and [StateSpaceIter::is_incomplete] then reports that States may have
+    /// been missed.
+    pub fn state_space_iter<'f>(&'f self, initial: TState, events: &'f [TEvent]) -> StateSpaceIter<'f, 'a, TEvent, TState, TData> {
+        StateSpaceIter {
+            factory: self,
+            events,
+            seen: vec![initial.clone()],
+            discovered: VecDeque::from([initial]),
+            unexplored: VecDeque::new(),
+            incomplete: false,
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> StateSpaceIter<'_, 'a, TEvent, TState, TData> {
+    /// True if a [Calc](crate::ToState::Calc) Transition was skipped while following the
+    /// Transitions of the States returned so far, so that some reachable States may be missing.
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+
+    /// Follows the Transitions out of `state`, recording the States it leads to that weren't
+    /// discovered before
+    fn explore(&mut self, state: &TState) {
+        let definition = &self.factory.definition;
+        for transition in &self.factory.transitions {
+            if !definition.state_matches(&transition.from_state, state) {
+                continue;
+            }
+            if let Some(expected) = transition.event {
+                if !self.events.iter().any(|event| definition.event_matches(expected, event)) {
+                    continue;
+                }
+            }
+            match &transition.get_to_state {
+                Target::To(to_state) => {
+                    if !self.seen.iter().any(|seen| definition.same_state(seen, to_state)) {
+                        self.seen.push(to_state.clone());
+                        self.discovered.push_back(to_state.clone());
+                    }
+                }
+                Target::Same => {}
+                Target::Calc(_) => self.incomplete = true
+            }
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Iterator for StateSpaceIter<'_, 'a, TEvent, TState, TData> {
+    type Item = TState;
+
+    fn next(&mut self) -> Option<TState> {
+        loop {
+            if let Some(state) = self.discovered.pop_front() {
+                self.unexplored.push_back(state.clone());
+                return Some(state);
+            }
+            let state = self.unexplored.pop_front()?;
+            self.explore(&state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::StateMachineFactory;
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Increment,
+        Double
+    }
+
+    #[test]
+    fn test_state_space_iter() {
+        let mut factory = StateMachineFactory::<Events, u32, ()>::new();
+        for state in 0..1000 {
+            factory = factory
+                .with_event_transition(&Events::Increment, state, state + 1)
+                .with_event_transition(&Events::Double, state, state * 2);
+        }
+
+        let events = [Events::Increment, Events::Double];
+        let explored: Vec<u32> = factory.state_space_iter(1, &events).take(6).collect();
+        assert_eq!(vec![1, 2, 3, 4, 6, 5], explored);
+        let events = [Events::Increment];
+        let mut states = factory.state_space_iter(998, &events);
+        assert_eq!(vec![998, 999, 1000], states.by_ref().collect::<Vec<_>>());
+        assert!(!states.is_incomplete());
+
+        let factory = factory.with_event_transition(&Events::Increment, 1000, crate::ToState::Calc(Box::new(|d: crate::StateTransitionToStateData<_, u32, _>| d.from + 1)));
+        let mut states = factory.state_space_iter(999, &events);
+        assert_eq!(vec![999, 1000], states.by_ref().collect::<Vec<_>>());
+        assert!(states.is_incomplete());
+    }
+}
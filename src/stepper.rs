@@ -0,0 +1,138 @@
+//! Ready-made State Machine for numbered steps, such as the pages of a wizard or a carousel.
+
+use crate::{LockedStateMachineFactory, StateMachine, StateMachineError, StateMachineFactory, StateTransitionToStateData};
+use crate::FromState::Any;
+use crate::ToState::Calc;
+
+/// Events handled by a [StepperMachine]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum StepperEvent {
+    /// Moves to the next step
+    Next,
+    /// Moves to the previous step
+    Prev,
+    /// Moves to the step with the given number, counting from 0
+    GoTo(usize),
+}
+
+/// A State Machine whose states are the steps `0..total_steps`, moved through with
+/// [StepperEvent]s. The Transitions between steps are wired up by the constructor, so a wizard
+/// doesn't have to register one per step.
+///
+/// Events that would leave the range of steps are rejected: the step doesn't change, and
+/// [StepperMachine::handle_event] still succeeds. A machine created with [StepperMachine::wrapping]
+/// instead moves from the last step to the first on [StepperEvent::Next], and from the first to
+/// the last on [StepperEvent::Prev].
+pub struct StepperMachine {
+    machine: StateMachine<'static, StepperEvent, usize>,
+    total_steps: usize,
+    wrap: bool,
+}
+
+/// The step that `event` leads to from `step`, or `None` if the event is rejected there
+fn target(event: &StepperEvent, step: usize, total_steps: usize, wrap: bool) -> Option<usize> {
+    match *event {
+        StepperEvent::Next if step + 1 < total_steps => Some(step + 1),
+        StepperEvent::Next => wrap.then_some(0),
+        StepperEvent::Prev if step > 0 => Some(step - 1),
+        StepperEvent::Prev => wrap.then_some(total_steps - 1),
+        StepperEvent::GoTo(to) => (to < total_steps).then_some(to)
+    }
+}
+
+impl StepperMachine {
+    /// Creates a `StepperMachine` with `total_steps` steps, starting at step 0, that rejects moving
+    /// past either end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_steps` is 0.
+    pub fn new(total_steps: usize) -> Self {
+        Self::with_wrap(total_steps, false)
+    }
+
+    /// Creates a `StepperMachine` with `total_steps` steps, starting at step 0, that wraps around
+    /// at either end.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_steps` is 0.
+    pub fn wrapping(total_steps: usize) -> Self {
+        Self::with_wrap(total_steps, true)
+    }
+
+    fn with_wrap(total_steps: usize, wrap: bool) -> Self {
+        assert!(total_steps > 0, "a StepperMachine needs at least one step");
+        let factory: LockedStateMachineFactory<'static, StepperEvent, usize> = StateMachineFactory::new()
+            .with_states(0..total_steps)
+            .with_predicated_transition(
+                Any,
+                Calc(Box::new(move |d: StateTransitionToStateData<StepperEvent, usize, ()>| {
+                    target(d.event, *d.from, total_steps, wrap).unwrap_or(*d.from)
+                })),
+                move |d| target(d.event, *d.from, total_steps, wrap).is_some())
+            .lock();
+        Self {
+            machine: factory.build(0, ()),
+            total_steps,
+            wrap,
+        }
+    }
+
+    /// Handles a [StepperEvent], returning the step the machine is on afterwards.
+    pub fn handle_event(&mut self, event: StepperEvent) -> Result<usize, StateMachineError<usize>> {
+        self.machine.handle_event(event).copied()
+    }
+
+    /// The step the machine is on, counting from 0.
+    pub fn current_step(&self) -> usize {
+        self.machine.state
+    }
+
+    /// The number of steps.
+    pub fn total_steps(&self) -> usize {
+        self.total_steps
+    }
+
+    /// Determines whether [StepperEvent::Next] would move to another step.
+    pub fn can_advance(&self) -> bool {
+        target(&StepperEvent::Next, self.current_step(), self.total_steps, self.wrap)
+            .is_some_and(|step| step != self.current_step())
+    }
+
+    /// Determines whether [StepperEvent::Prev] would move to another step.
+    pub fn can_go_back(&self) -> bool {
+        target(&StepperEvent::Prev, self.current_step(), self.total_steps, self.wrap)
+            .is_some_and(|step| step != self.current_step())
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{StepperEvent, StepperMachine};
+
+    #[test]
+    fn test_stepper() {
+        let mut wizard = StepperMachine::new(5);
+        assert_eq!(5, wizard.total_steps());
+        assert!(!wizard.can_go_back());
+        assert_eq!(0, wizard.handle_event(StepperEvent::Prev).expect("unexpected error"));
+
+        for step in 1..5 {
+            assert!(wizard.can_advance());
+            assert_eq!(step, wizard.handle_event(StepperEvent::Next).expect("unexpected error"));
+        }
+        assert!(!wizard.can_advance());
+        assert_eq!(4, wizard.handle_event(StepperEvent::Next).expect("unexpected error"));
+
+        assert_eq!(3, wizard.handle_event(StepperEvent::Prev).expect("unexpected error"));
+        assert_eq!(1, wizard.handle_event(StepperEvent::GoTo(1)).expect("unexpected error"));
+        assert_eq!(1, wizard.handle_event(StepperEvent::GoTo(5)).expect("unexpected error"));
+        assert_eq!(1, wizard.current_step());
+
+        let mut carousel = StepperMachine::wrapping(5);
+        assert_eq!(4, carousel.handle_event(StepperEvent::Prev).expect("unexpected error"));
+        assert!(carousel.can_advance());
+        assert_eq!(0, carousel.handle_event(StepperEvent::Next).expect("unexpected error"));
+    }
+}
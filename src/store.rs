@@ -0,0 +1,118 @@
+//! Data for State Machines whose effects each keep their own typed data.
+
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use crate::{LockedStateMachineFactory, StateMachine, StateTransitionEffectData};
+
+/// State Machine data made up of independent slots, one for each type of value stored. This lets
+/// independently-developed effects each keep their own data, instead of sharing one monolithic
+/// data type that has to know about all of them.
+///
+/// Slots are looked up by type at runtime, so a mistake that the compiler would otherwise catch
+/// only shows up when an effect runs: [DataStore::get] and [DataStore::get_mut] return `None` for a
+/// type that was never stored. Like [crate::SplitData], slots are handed out through a [RefCell],
+/// so borrowing the same slot mutably twice at once panics.
+#[derive(Default)]
+pub struct DataStore {
+    slots: HashMap<TypeId, RefCell<Box<dyn Any + Send>>>,
+}
+
+impl DataStore {
+    /// Creates an empty `DataStore`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores a value in the slot for its type, replacing any value of the same type.
+    pub fn with<T: Any + Send>(mut self, value: T) -> Self {
+        self.slots.insert(TypeId::of::<T>(), RefCell::new(Box::new(value)));
+        self
+    }
+
+    /// The value stored for type `T`, or `None` if there is none.
+    pub fn get<T: Any>(&self) -> Option<Ref<'_, T>> {
+        let slot = self.slots.get(&TypeId::of::<T>())?;
+        Some(Ref::map(slot.borrow(), |value| value.downcast_ref().expect("slots are keyed by type")))
+    }
+
+    /// Mutable access to the value stored for type `T`, or `None` if there is none.
+    pub fn get_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        let slot = self.slots.get(&TypeId::of::<T>())?;
+        Some(RefMut::map(slot.borrow_mut(), |value| value.downcast_mut().expect("slots are keyed by type")))
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a> LockedStateMachineFactory<'a, TEvent, TState, DataStore> {
+    /// Builds a StateMachine with a specified initial state, whose effects reach their data through
+    /// the slots of `store`.
+    pub fn build_with_store(&self, initial_state: TState, store: DataStore) -> StateMachine<'a, TEvent, TState, DataStore> {
+        self.build(initial_state, store)
+    }
+}
+
+impl <TEvent, TState> StateTransitionEffectData<'_, TEvent, TState, DataStore> {
+    /// The value of type `T` in the State Machine's [DataStore], or `None` if there is none.
+    pub fn get<T: Any>(&self) -> Option<Ref<'_, T>> {
+        self.data.get()
+    }
+
+    /// Mutable access to the value of type `T` in the State Machine's [DataStore], or `None` if
+    /// there is none.
+    pub fn get_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        self.data.get_mut()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{DataStore, StateMachineFactory};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[test]
+    fn test_data_store() {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        enum States {
+            Running
+        }
+
+        #[derive(Eq, PartialEq)]
+        enum Events {
+            Request(u32)
+        }
+
+        // Each effect keeps its own data, without knowing about the other's
+        #[derive(Default)]
+        struct RequestCounter(usize);
+
+        #[derive(Default)]
+        struct LargestRequest(u32);
+
+        let factory = StateMachineFactory::<Events, States, DataStore>::new()
+            .with_transition_effect(Any, Same, |d| {
+                d.get_mut::<RequestCounter>().expect("registered").0 += 1;
+                Ok(())
+            })
+            .with_transition_effect(Any, Same, |d| {
+                let Events::Request(size) = d.event;
+                let mut largest = d.get_mut::<LargestRequest>().expect("registered");
+                largest.0 = largest.0.max(*size);
+                Ok(())
+            })
+            .lock();
+
+        let store = DataStore::new()
+            .with(RequestCounter::default())
+            .with(LargestRequest::default());
+        let mut sm = factory.build_with_store(States::Running, store);
+        sm.handle_event(Events::Request(3)).expect("unexpected error");
+        sm.handle_event(Events::Request(8)).expect("unexpected error");
+        sm.handle_event(Events::Request(5)).expect("unexpected error");
+
+        assert_eq!(3, sm.data.get::<RequestCounter>().expect("registered").0);
+        assert_eq!(8, sm.data.get::<LargestRequest>().expect("registered").0);
+        assert!(sm.data.get::<String>().is_none());
+    }
+}
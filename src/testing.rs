@@ -0,0 +1,78 @@
+//! Assertions for testing State Machine definitions, as in
+//! `assert_transition(&mut sm, Events::Add, States::Adding)`.
+//!
+//! Each assertion handles an Event and panics with the Event's outcome if it isn't the expected
+//! one, which reads better in a test than mapping every [StateMachineError] by hand.
+
+use std::fmt::Debug;
+use crate::{StateMachine, StateMachineError};
+
+/// Handles `event` and asserts that the State Machine ends up in `expected_to`.
+///
+/// # Panics
+///
+/// Panics if handling the Event fails, or if the State Machine ends up in any other state.
+#[track_caller]
+pub fn assert_transition<'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData>(sm: &mut StateMachine<'a, TEvent, TState, TData>, event: TEvent, expected_to: TState) {
+    let from = sm.state.clone();
+    match sm.handle_event(event) {
+        Ok(state) if *state == expected_to => {}
+        Ok(state) => panic!("expected a transition from {:?} to {:?}, but the state machine is in {:?}", from, expected_to, state),
+        Err(error) => panic!("expected a transition from {:?} to {:?}, but handling the event failed: {}", from, expected_to, error)
+    }
+}
+
+/// Handles `event` and asserts that it is rejected: that the State Machine stays in its current
+/// state, either because no Transition changes the state or because handling the Event fails, as
+/// when a pre-transition hook vetoes it. Returns the error, if handling failed, for further
+/// assertions.
+///
+/// # Panics
+///
+/// Panics if the state changed at any point while handling the Event (see
+/// [StateMachine::state_changed]), even if it ended up back where it started.
+#[track_caller]
+pub fn assert_rejected<'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData>(sm: &mut StateMachine<'a, TEvent, TState, TData>, event: TEvent) -> Option<StateMachineError<TState>> {
+    let from = sm.state.clone();
+    let error = sm.handle_event(event).err();
+    if sm.state_changed() {
+        panic!("expected the event to be rejected in {:?}, but the state machine moved to {:?}", from, sm.state);
+    }
+    error
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use crate::StateMachineFactory;
+    use crate::testing::{assert_rejected, assert_transition};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Adding
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Add,
+        Equals
+    }
+
+    #[test]
+    fn test_assertions() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Add, States::Idle, States::Adding)
+            .with_event_transition(&Events::Equals, States::Adding, States::Idle)
+            .lock();
+
+        let mut sm = factory.build(States::Idle, ());
+        assert!(assert_rejected(&mut sm, Events::Equals).is_none());
+        assert_transition(&mut sm, Events::Add, States::Adding);
+
+        let panic = catch_unwind(AssertUnwindSafe(|| assert_transition(&mut sm, Events::Add, States::Idle))).expect_err("the state didn't change");
+        assert_eq!(Some(&"expected a transition from Adding to Idle, but the state machine is in Adding".to_string()), panic.downcast_ref::<String>());
+        let panic = catch_unwind(AssertUnwindSafe(|| assert_rejected(&mut sm, Events::Equals))).expect_err("the state changed");
+        assert_eq!(Some(&"expected the event to be rejected in Adding, but the state machine moved to Idle".to_string()), panic.downcast_ref::<String>());
+    }
+}
@@ -0,0 +1,832 @@
+//! Timed behaviour: events raised after a State Machine has spent a while in a state.
+
+use std::fmt::Debug;
+#[cfg(feature = "async")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::task::{Poll, Waker};
+#[cfg(feature = "async")]
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::queue::DispatchContext;
+use crate::{Clock, SharedStateMachine, StateMachine, StateMachineError, StateMachineFactory, StateTransitionEffectData};
+
+/// An Event raised once a State Machine has been in `state` for `after`
+#[derive(Clone)]
+pub(crate) struct Timeout<TEvent, TState> {
+    pub(crate) state: TState,
+    pub(crate) after: Duration,
+    pub(crate) event: TEvent,
+}
+
+/// A move into `to_state` once a State Machine has handled no Event for `after`
+pub(crate) struct InactivityTimeout<'a, TState, TData> {
+    pub(crate) after: Duration,
+    pub(crate) to_state: TState,
+    pub(crate) effect: InactivityEffect<'a, TState, TData>,
+}
+
+impl <TState: Clone, TData> Clone for InactivityTimeout<'_, TState, TData> {
+    fn clone(&self) -> Self {
+        Self {
+            after: self.after,
+            to_state: self.to_state.clone(),
+            effect: self.effect.clone(),
+        }
+    }
+}
+
+/// Boxed effect of an [InactivityTimeout], receiving the State expired and the data
+type InactivityEffect<'a, TState, TData> = Arc<dyn Fn(&TState, &TData) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a>;
+
+/// The Events scheduled by effects, each to be handled once its due time has passed, kept in the
+/// order they come due so that they can be inspected as a slice
+#[derive(Default, Clone)]
+pub(crate) struct ScheduledEvents<TEvent> {
+    due: Vec<Instant>,
+    events: Vec<TEvent>,
+}
+
+impl <TEvent> ScheduledEvents<TEvent> {
+    pub(crate) fn new() -> Self {
+        Self {
+            due: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Adds an Event after every Event due at or before `due`, so that Events due at the same time
+    /// stay in the order they were scheduled
+    fn insert(&mut self, due: Instant, event: TEvent) {
+        let position = self.due.partition_point(|scheduled| *scheduled <= due);
+        self.due.insert(position, due);
+        self.events.insert(position, event);
+    }
+
+    /// Removes the Event that comes due first
+    fn pop_first(&mut self) -> Option<TEvent> {
+        if self.events.is_empty() {
+            return None;
+        }
+        self.due.remove(0);
+        Some(self.events.remove(0))
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Sets the [Clock] that State Machines built from this factory use for timed behaviour. This
+    /// is the [crate::SystemClock] by default; tests can use a [crate::MockClock] instead.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.definition.clock = Arc::new(clock);
+        self
+    }
+
+    /// Controls whether timeouts are also checked at the start of [StateMachine::handle_event]. If
+    /// the timeout of the current state has elapsed by then, its Event is handled first, and the
+    /// incoming Event is then handled from the resulting state. This suits State Machines that only
+    /// move on when Events arrive anyway, as it needs neither a background thread nor calls to
+    /// [StateMachine::tick]; the flip side is that a timeout only fires once an Event arrives (or
+    /// `tick` is called).
+    pub fn lazy_timeouts(mut self, lazy: bool) -> Self {
+        self.definition.lazy_timeouts = lazy;
+        self
+    }
+
+    /// Raises `event` once the State Machine has been in `state` for the duration `after`. Timeouts
+    /// are checked by [StateMachine::tick] (and, with [StateMachineFactory::lazy_timeouts], when an
+    /// Event is handled), so the Event is handled by the next check after the timeout has elapsed,
+    /// through the same Transitions as any other Event. If handling the
+    /// Event leaves the machine in `state`, the timeout starts over. Each state has at most one
+    /// timeout; registering another one for the same state replaces it.
+    pub fn with_timeout(mut self, state: TState, after: Duration, event: TEvent) -> Self {
+        self.definition.timeouts.retain(|timeout| timeout.state != state);
+        self.definition.timeouts.push(Timeout { state, after, event });
+        self
+    }
+
+    /// Moves the State Machine into `to_state`, from whatever state it is in, once it has handled
+    /// no Event for the duration `after`, as measured by [StateMachine::time_since_last_event]; for
+    /// example to expire a session. Unlike [StateMachineFactory::with_timeout], this doesn't depend
+    /// on the current state, and doesn't go through a Transition: `effect` runs with the state
+    /// being left and the data, and the state then changes without entry effects, listeners or
+    /// transition loggers, as there is no Event to pass them. If `effect` fails, the state doesn't
+    /// change, the error is returned and the inactivity period starts over.
+    ///
+    /// The timeout is checked by [StateMachine::tick], and when an Event arrives, before anything
+    /// else, so that the Event is handled from `to_state`. Its deadline is part of
+    /// [StateMachine::next_deadline], so background timers expire idle machines too. It doesn't
+    /// fire while the State Machine is in `to_state` already. Setting another inactivity timeout replaces this one.
+    pub fn with_global_inactivity_timeout(mut self, after: Duration, to_state: TState, effect: impl Fn(&TState, &TData) -> Result<(), Box<dyn std::error::Error + Send>> + Send + Sync + 'a) -> Self {
+        self.definition.inactivity_timeout = Some(InactivityTimeout {
+            after,
+            to_state,
+            effect: Arc::new(effect),
+        });
+        self
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    fn current_timeout(&self) -> Option<&Timeout<TEvent, TState>> {
+        self.definition.timeouts.iter().find(|timeout| self.definition.same_state(&timeout.state, &self.state))
+    }
+
+    fn timeout_deadline(&self) -> Option<Instant> {
+        let timeout = self.current_timeout()?;
+        Some(self.entered_at.unwrap_or_else(|| self.definition.clock.now()) + timeout.after)
+    }
+
+    /// When the inactivity timeout expires, unless the State Machine is already in its target
+    fn inactivity_deadline(&self) -> Option<Instant> {
+        let timeout = self.definition.inactivity_timeout.as_ref()
+            .filter(|timeout| !self.definition.same_state(&self.state, &timeout.to_state))?;
+        Some(self.last_event_at.unwrap_or_else(|| self.definition.clock.now()) + timeout.after)
+    }
+
+    /// The earliest time at which [StateMachine::tick] has something to do: either the timeout of
+    /// the current state elapses, the inactivity timeout set with
+    /// [StateMachineFactory::with_global_inactivity_timeout] expires, or an Event scheduled with
+    /// [StateTransitionEffectData::schedule] comes due. Returns `None` if none of them is pending.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let scheduled = self.scheduled.due.first().copied();
+        [self.timeout_deadline(), self.inactivity_deadline(), scheduled].into_iter().flatten().min()
+    }
+
+    /// The time since the State Machine last handled an Event passed to
+    /// [StateMachine::handle_event] or its variants, or since it was built if it hasn't handled
+    /// any, according to the definition's [Clock]. Unlike the time in the current state, this is
+    /// reset by every Event, whether or not a Transition was taken, which supports idle timeouts
+    /// such as the expiry of a session. Neither timeouts nor Events scheduled with
+    /// [StateTransitionEffectData::schedule] reset it, as they come from the State Machine itself.
+    pub fn time_since_last_event(&self) -> Duration {
+        let now = self.definition.clock.now();
+        now.saturating_duration_since(self.last_event_at.unwrap_or(now))
+    }
+
+    /// Moves into the target of the inactivity timeout if the State Machine has been inactive for
+    /// longer than it allows
+    pub(crate) fn expire_if_inactive(&mut self, inactive_for: Duration) -> Result<(), StateMachineError<TState>> {
+        let Some(timeout) = &self.definition.inactivity_timeout else {
+            return Ok(());
+        };
+        if inactive_for < timeout.after || self.definition.same_state(&self.state, &timeout.to_state) {
+            return Ok(());
+        }
+        if let Err(e) = (timeout.effect)(&self.state, &self.data) {
+            // The inactivity period starts over, so that a failing effect doesn't run again
+            // straight away
+            self.last_event_at = Some(self.definition.clock.now());
+            return Err(StateMachineError::EffectError(self.state.clone(), timeout.to_state.clone(), e));
+        }
+        self.previous_state = Some(std::mem::replace(&mut self.state, timeout.to_state.clone()));
+        self.entered_at = Some(self.definition.clock.now());
+        self.state_changed = true;
+        self.consecutive_same = 0;
+        self.event_counts.clear();
+        self.cancel_exit_tokens();
+        Ok(())
+    }
+
+    /// Handles the Event of the current state's timeout if it has elapsed by `now`
+    pub(crate) fn fire_elapsed_timeout(&mut self, now: Instant) -> Result<(), StateMachineError<TState>> {
+        if self.timeout_deadline().is_none_or(|deadline| deadline > now) {
+            return Ok(());
+        }
+        // The definition is shared, so holding on to it lets the Event be handled by reference
+        let definition = self.definition.clone();
+        let timeout = definition.timeouts.iter()
+            .find(|timeout| definition.same_state(&timeout.state, &self.state))
+            .expect("a deadline implies a timeout");
+        // The timeout starts over unless its Event leads elsewhere, including when handling it
+        // fails, so that a failing timeout doesn't fire again straight away
+        self.entered_at = Some(now);
+        self.handle_event_in(&timeout.event, &DispatchContext::new())
+    }
+
+    /// The Events yet to be handled, in the order [StateMachine::tick] would handle them. These are
+    /// the Events scheduled with [StateTransitionEffectData::schedule], whether or not they have
+    /// come due. Events enqueued with [StateTransitionEffectData::enqueue] are handled before
+    /// [StateMachine::handle_event] returns, so they are never pending by the time this can be
+    /// called; an effect that wants a follow-up to stay pending schedules it instead, with a delay
+    /// of zero if it is due straight away.
+    pub fn pending_events(&self) -> &[TEvent] {
+        &self.scheduled.events
+    }
+
+    /// The number of [StateMachine::pending_events].
+    pub fn pending_len(&self) -> usize {
+        self.scheduled.events.len()
+    }
+
+    /// Handles every pending Event now, whether or not it has come due, in the order of
+    /// [StateMachine::pending_events], for example to flush pending work before shutting down.
+    /// Returns the outcome of each Event; unlike [StateMachine::tick], a failing Event doesn't stop
+    /// the ones after it from being handled. Events scheduled while draining stay pending.
+    pub fn drain_pending(&mut self) -> Vec<Result<TState, StateMachineError<TState>>> {
+        let pending = std::mem::replace(&mut self.scheduled, ScheduledEvents::new());
+        pending.events.into_iter()
+            .map(|event| self.handle_event_in(&event, &DispatchContext::new()).map(|()| self.state.clone()))
+            .collect()
+    }
+
+    /// Adds the Events scheduled while handling an Event to the timer queue
+    pub(crate) fn schedule_events(&mut self, scheduled: Vec<(Duration, TEvent)>) {
+        if scheduled.is_empty() {
+            return;
+        }
+        let now = self.definition.clock.now();
+        for (delay, event) in scheduled {
+            self.scheduled.insert(now + delay, event);
+        }
+    }
+}
+
+impl <'a, TEvent: Clone, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Checks the timeout of the current state against the State Machine's [Clock], and handles
+    /// its Event if it has elapsed. At most one timeout fires per call, so a State Machine whose
+    /// Event leads into a state with an elapsed timeout of its own only moves on at the next call.
+    ///
+    /// Then handles every Event scheduled with [StateTransitionEffectData::schedule] that has come
+    /// due, in the order they came due, and in the order they were scheduled when several came due
+    /// at the same time. Events scheduled while doing so are left for a later call, even if they
+    /// are already due. If an Event fails, the error is returned: the failing Event is dropped and
+    /// the Events after it stay scheduled. Likewise, a timeout whose Event fails starts over. As
+    /// with Events enqueued by effects, timeouts and scheduled Events aren't preceded by sticky
+    /// Events (see [StateMachine::set_sticky]).
+    pub fn tick(&mut self) -> Result<&TState, StateMachineError<TState>> {
+        let now = self.definition.clock.now();
+        self.expire_if_inactive(self.time_since_last_event())?;
+        self.fire_elapsed_timeout(now)?;
+
+        // Events scheduled meanwhile are due no earlier than now, so they go after those due already
+        let due_count = self.scheduled.due.partition_point(|due| *due <= now);
+        for _ in 0..due_count {
+            let event = self.scheduled.pop_first().expect("due Events are still scheduled");
+            self.handle_event_in(&event, &DispatchContext::new())?;
+        }
+        Ok(&self.state)
+    }
+}
+
+impl <TEvent, TState, TData> StateTransitionEffectData<'_, TEvent, TState, TData> {
+    /// The time the State Machine had been idle for when the Event being handled was passed to it,
+    /// as measured by [StateMachine::time_since_last_event]. Events enqueued while handling it
+    /// report the same time.
+    pub fn time_since_last_event(&self) -> Duration {
+        self.context.since_last_event
+    }
+
+    /// Schedules an Event to be handled by the State Machine once `delay` has passed, such as a
+    /// retry after a backoff. Unlike [StateTransitionEffectData::enqueue], the Event isn't handled
+    /// by the current call to [StateMachine::handle_event]: it is handled by the first call to
+    /// [StateMachine::tick] (or the background timer) after it has come due, according to the
+    /// State Machine's [Clock].
+    pub fn schedule(&self, event: TEvent, delay: Duration) {
+        self.context.scheduled.borrow_mut().push((delay, event));
+    }
+}
+
+/// Wakes timer threads when a [SharedStateMachine] changes or a timer is cancelled
+#[derive(Default)]
+pub(crate) struct Wakeup {
+    generation: Mutex<u64>,
+    condvar: Condvar,
+    /// The tasks awaiting [Wakeup::changed]
+    #[cfg(feature = "async")]
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Wakeup {
+    pub(crate) fn notify(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.condvar.notify_all();
+        #[cfg(feature = "async")]
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn generation(&self) -> u64 {
+        *self.generation.lock().unwrap()
+    }
+
+    /// Resolves once [Wakeup::notify] is called after `generation` was read, the counterpart of
+    /// [Wakeup::wait] for timers running on an [crate::Executor]
+    #[cfg(feature = "async")]
+    pub(crate) fn changed(&self, generation: u64) -> impl Future<Output = ()> + '_ {
+        std::future::poll_fn(move |cx| {
+            let mut wakers = self.wakers.lock().unwrap();
+            if self.generation() != generation {
+                return Poll::Ready(());
+            }
+            // Polling again before the next notification registers the task only once
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Waits until [Wakeup::notify] is called after `generation` was read, or until `timeout`
+    /// (if any) has passed
+    #[cfg(feature = "async")]
+    fn wait(&self, generation: u64, timeout: Option<Duration>) {
+        let guard = self.generation.lock().unwrap();
+        match timeout {
+            Some(timeout) => drop(self.condvar.wait_timeout_while(guard, timeout, |g| *g == generation).unwrap()),
+            None => drop(self.condvar.wait_while(guard, |g| *g == generation).unwrap()),
+        }
+    }
+}
+
+impl <TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Sync + Eq + PartialEq + 'static, TData> SharedStateMachine<'static, TEvent, TState, TData>
+where TEvent: Clone + Send + Sync + 'static, TData: Send + Sync + 'static
+{
+    /// Checks the timeout of the current state while holding the write lock; see
+    /// [StateMachine::tick].
+    pub fn tick(&self) -> Result<TState, StateMachineError<TState>> {
+        let result = self.write().tick().cloned();
+        self.wakeup.notify();
+        result
+    }
+
+    /// Spawns a background thread that calls [SharedStateMachine::tick] whenever the timeout of the
+    /// current state elapses or a scheduled Event comes due, so that they fire without the caller
+    /// driving a tick loop. The thread sleeps until the next deadline and is woken early whenever
+    /// an Event is handled, as that may change the deadline. Errors returned by these Events are
+    /// passed to `on_error`, after which the thread waits for at least [TIMER_ERROR_BACKOFF]
+    /// before ticking again.
+    ///
+    /// The thread stops when the returned [TimerHandle] is dropped. Deadlines are computed from the
+    /// State Machine's [Clock] but waited for in real time, so use [StateMachine::tick] directly
+    /// when testing with a [crate::MockClock].
+    ///
+    /// Available with the `async` feature. The thread needs no async runtime, but it lives behind
+    /// the same feature as `SharedStateMachine::spawn_timer_on`, which drives the same loop from a
+    /// task on one, so that background timers are opted into in one place.
+    #[cfg(feature = "async")]
+    pub fn spawn_timer(&self, on_error: impl FnMut(StateMachineError<TState>) + Send + 'static) -> TimerHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut timer = TimerLoop::new(self.clone(), cancelled.clone(), on_error);
+        let thread = std::thread::spawn(move || {
+            loop {
+                match timer.step() {
+                    TimerStep::Continue => {}
+                    TimerStep::Wait(generation, timeout) => timer.shared.wakeup.wait(generation, timeout),
+                    TimerStep::Backoff => std::thread::sleep(TIMER_ERROR_BACKOFF),
+                    TimerStep::Stop => break
+                }
+            }
+        });
+        TimerHandle {
+            cancelled,
+            wakeup: self.wakeup.clone(),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// How long a background timer waits before ticking again after [SharedStateMachine::tick]
+/// failed, so that an Event that keeps failing doesn't keep it busy.
+#[cfg(feature = "async")]
+pub const TIMER_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// What a background timer does next, as determined by [TimerLoop::step]
+#[cfg(feature = "async")]
+pub(crate) enum TimerStep {
+    /// Checks the deadline again straight away
+    Continue,
+    /// Waits until the State Machine changes after the generation was read, or until the timeout
+    /// (if any) has passed
+    Wait(u64, Option<Duration>),
+    /// Waits for [TIMER_ERROR_BACKOFF], whatever happens meanwhile, after ticking failed
+    Backoff,
+    /// Stops, as the timer was cancelled
+    Stop,
+}
+
+/// The loop of a background timer, shared by the thread of [SharedStateMachine::spawn_timer] and
+/// the task of `SharedStateMachine::spawn_timer_on`, which only differ in how they wait
+#[cfg(feature = "async")]
+pub(crate) struct TimerLoop<TEvent: 'static, TState: PartialEq<TState> + Debug + Clone + Send + Eq + 'static, TData: 'static> {
+    pub(crate) shared: SharedStateMachine<'static, TEvent, TState, TData>,
+    cancelled: Arc<AtomicBool>,
+    on_error: Box<dyn FnMut(StateMachineError<TState>) + Send>,
+}
+
+#[cfg(feature = "async")]
+impl <TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Sync + Eq + PartialEq + 'static, TData> TimerLoop<TEvent, TState, TData>
+where TEvent: Clone + Send + Sync + 'static, TData: Send + Sync + 'static
+{
+    pub(crate) fn new(shared: SharedStateMachine<'static, TEvent, TState, TData>, cancelled: Arc<AtomicBool>, on_error: impl FnMut(StateMachineError<TState>) + Send + 'static) -> Self {
+        Self {
+            shared,
+            cancelled,
+            on_error: Box::new(on_error),
+        }
+    }
+
+    /// Ticks if the next deadline has passed, and determines what to do next
+    pub(crate) fn step(&mut self) -> TimerStep {
+        let generation = self.shared.wakeup.generation();
+        if self.cancelled.load(Ordering::SeqCst) {
+            return TimerStep::Stop;
+        }
+        let (deadline, now) = {
+            let sm = self.shared.read();
+            (sm.next_deadline(), sm.definition.clock.now())
+        };
+        match deadline {
+            Some(deadline) if deadline <= now => match self.shared.tick() {
+                Ok(_) => TimerStep::Continue,
+                Err(e) => {
+                    (self.on_error)(e);
+                    TimerStep::Backoff
+                }
+            },
+            Some(deadline) => TimerStep::Wait(generation, Some(deadline - now)),
+            None => TimerStep::Wait(generation, None)
+        }
+    }
+}
+
+/// Handle to a timer thread started with [SharedStateMachine::spawn_timer]. Dropping it stops the
+/// thread, waiting for any timeout it is currently handling, or any backoff after an error, to
+/// finish.
+#[cfg(feature = "async")]
+pub struct TimerHandle {
+    cancelled: Arc<AtomicBool>,
+    wakeup: Arc<Wakeup>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "async")]
+impl Drop for TimerHandle {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.wakeup.notify();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::{Clock, MockClock, StateMachineFactory};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Connecting,
+        Connected,
+        Failed
+    }
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    enum Events {
+        Connected,
+        Retry,
+        TimedOut
+    }
+
+    #[test]
+    fn test_tick() {
+        let clock = MockClock::new();
+        let mut sm = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            .with_timeout(States::Connecting, Duration::from_secs(5), Events::TimedOut)
+            .with_event_transition(&Events::TimedOut, States::Connecting, States::Failed)
+            .with_event_transition(&Events::Connected, States::Connecting, States::Connected)
+            .lock().build(States::Connecting, ());
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(&States::Connecting, sm.tick().expect("unexpected error"));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(&States::Failed, sm.tick().expect("unexpected error"));
+        // Failed has no timeout
+        assert_eq!(None, sm.next_deadline());
+
+        // Timeouts only apply while the machine stays in the state
+        let mut sm = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            .with_timeout(States::Connecting, Duration::from_secs(5), Events::TimedOut)
+            .with_event_transition(&Events::TimedOut, States::Connecting, States::Failed)
+            .with_event_transition(&Events::Connected, States::Connecting, States::Connected)
+            .lock().build(States::Connecting, ());
+        sm.handle_event(Events::Connected).expect("unexpected error");
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(&States::Connected, sm.tick().expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_lazy_timeouts() {
+        let clock = MockClock::new();
+        let factory = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            .lazy_timeouts(true)
+            .with_timeout(States::Connecting, Duration::from_secs(5), Events::TimedOut)
+            .with_event_transition(&Events::TimedOut, States::Connecting, States::Failed)
+            .with_event_transition(&Events::Connected, States::Connecting, States::Connected)
+            .lock();
+
+        let mut sm = factory.build(States::Connecting, ());
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(&States::Connected, sm.handle_event(Events::Connected).expect("unexpected error"));
+
+        // The timeout has elapsed by the time Connected arrives, so the machine has already failed
+        let mut sm = factory.build(States::Connecting, ());
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(&States::Failed, sm.handle_event(Events::Connected).expect("unexpected error"));
+    }
+
+    #[test]
+    fn test_schedule() {
+        let clock = MockClock::new();
+        let attempts = AtomicUsize::new(0);
+        let mut sm = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            // Each attempt fails until the third, and schedules a retry after a growing backoff
+            .with_event_transition_effect(&Events::Retry, States::Connecting, Same, |data| {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                if attempt < 3 {
+                    data.schedule(Events::Retry, Duration::from_secs(attempt as u64));
+                } else {
+                    data.enqueue(Events::Connected);
+                }
+                Ok(())
+            })
+            .with_event_transition(&Events::Connected, States::Connecting, States::Connected)
+            .lock().build(States::Connecting, ());
+
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+        assert_eq!(Some(clock.now() + Duration::from_secs(1)), sm.next_deadline());
+
+        // The retry isn't handled before it comes due
+        sm.tick().expect("unexpected error");
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+
+        clock.advance(Duration::from_secs(1));
+        sm.tick().expect("unexpected error");
+        assert_eq!(2, attempts.load(Ordering::SeqCst));
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(&States::Connected, sm.tick().expect("unexpected error"));
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+        assert_eq!(None, sm.next_deadline());
+    }
+
+    #[test]
+    fn test_schedule_without_sticky() {
+        let clock = MockClock::new();
+        let handled = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::<Events, States, ()>::new()
+            .with_clock(clock.clone())
+            .with_transition_effect(Any, Same, |d| {
+                handled.lock().unwrap().push(d.event.clone());
+                if *d.event == Events::Retry {
+                    d.schedule(Events::TimedOut, Duration::from_secs(1));
+                }
+                Ok(())
+            })
+            .lock().build(States::Connecting, ());
+
+        sm.set_sticky(Events::Connected);
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        assert_eq!(vec![Events::Connected, Events::Retry], *handled.lock().unwrap());
+
+        // Neither ticking nor draining precedes the scheduled Event with the sticky one
+        clock.advance(Duration::from_secs(1));
+        sm.tick().expect("unexpected error");
+        assert_eq!(vec![Events::Connected, Events::Retry, Events::TimedOut], *handled.lock().unwrap());
+        assert_eq!(Duration::from_secs(1), sm.time_since_last_event());
+
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        handled.lock().unwrap().clear();
+        sm.drain_pending().into_iter().for_each(|outcome| { outcome.expect("unexpected error"); });
+        assert_eq!(vec![Events::TimedOut], *handled.lock().unwrap());
+    }
+
+    #[test]
+    fn test_failing_timeout_starts_over() {
+        let clock = MockClock::new();
+        let mut sm = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            .with_timeout(States::Connecting, Duration::from_secs(5), Events::TimedOut)
+            .with_event_transition_effect(&Events::TimedOut, States::Connecting, States::Failed, |_| Err(Box::new(std::fmt::Error)))
+            .lock().build(States::Connecting, ());
+
+        clock.advance(Duration::from_secs(5));
+        assert!(sm.tick().is_err());
+        // The timeout doesn't fire again until it elapses once more
+        assert_eq!(Some(clock.now() + Duration::from_secs(5)), sm.next_deadline());
+        assert_eq!(&States::Connecting, sm.tick().expect("unexpected error"));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_changed_registers_once() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::sync::Arc;
+        use std::task::{Context, Wake, Waker};
+        use super::Wakeup;
+
+        struct Ignored;
+
+        impl Wake for Ignored {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let wakeup = Wakeup::default();
+        let mut changed = pin!(wakeup.changed(wakeup.generation()));
+        let waker = Waker::from(Arc::new(Ignored));
+        let mut cx = Context::from_waker(&waker);
+        for _ in 0..3 {
+            assert!(changed.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(1, wakeup.wakers.lock().unwrap().len());
+
+        wakeup.notify();
+        assert!(changed.as_mut().poll(&mut cx).is_ready());
+        assert!(wakeup.wakers.lock().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_spawn_timer() {
+        use std::time::Instant;
+
+        let shared = StateMachineFactory::new()
+            .with_timeout(States::Connecting, Duration::from_millis(20), Events::TimedOut)
+            .with_event_transition(&Events::TimedOut, States::Connecting, States::Failed)
+            .lock().build(States::Connecting, ())
+            .into_shared();
+
+        let timer = shared.spawn_timer(|e| panic!("unexpected error {e:?}"));
+        let started = Instant::now();
+        while shared.current_state() != States::Failed {
+            assert!(started.elapsed() < Duration::from_secs(5), "timeout never fired");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        // Dropping the handle stops the thread
+        drop(timer);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_spawn_timer_errors() {
+        use std::sync::Arc;
+        use std::time::Instant;
+
+        let shared = StateMachineFactory::new()
+            .with_timeout(States::Connecting, Duration::from_millis(5), Events::TimedOut)
+            .with_event_transition_effect(&Events::TimedOut, States::Connecting, States::Failed, |_| Err(Box::new(std::fmt::Error)))
+            .lock().build(States::Connecting, ())
+            .into_shared();
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let timer_errors = errors.clone();
+        let timer = shared.spawn_timer(move |_| {
+            timer_errors.fetch_add(1, Ordering::SeqCst);
+        });
+        let started = Instant::now();
+        while errors.load(Ordering::SeqCst) == 0 {
+            assert!(started.elapsed() < Duration::from_secs(5), "timeout never fired");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // The timer backs off rather than firing again as soon as the timeout elapses
+        std::thread::sleep(crate::TIMER_ERROR_BACKOFF / 2);
+        assert_eq!(1, errors.load(Ordering::SeqCst));
+        drop(timer);
+    }
+
+    #[test]
+    fn test_time_since_last_event() {
+        let clock = MockClock::new();
+        let idle = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            .with_predicated_transition_effect(Any, Same, |d| matches!(d.event, Events::Retry), |d| {
+                idle.lock().unwrap().push(d.time_since_last_event());
+                Ok(())
+            })
+            .lock().build(States::Connecting, ());
+
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(Duration::from_secs(3), sm.time_since_last_event());
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        assert_eq!(Duration::ZERO, sm.time_since_last_event());
+
+        // Events that take no Transition reset it too
+        clock.advance(Duration::from_secs(5));
+        sm.handle_event(Events::TimedOut).expect("unexpected error");
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(Duration::from_secs(2), sm.time_since_last_event());
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        assert_eq!(vec![Duration::from_secs(3), Duration::from_secs(2)], *idle.lock().unwrap());
+    }
+
+    #[test]
+    fn test_drain_pending() {
+        let mut sm = StateMachineFactory::new()
+            .with_clock(MockClock::new())
+            .with_event_transition_effect(&Events::Retry, States::Connecting, Same, |d| {
+                d.schedule(Events::TimedOut, Duration::from_secs(30));
+                d.schedule(Events::Connected, Duration::from_secs(10));
+                d.schedule(Events::Retry, Duration::from_secs(20));
+                Ok(())
+            })
+            .with_event_transition(&Events::Connected, States::Connecting, States::Connected)
+            .lock().build(States::Connecting, ());
+
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        assert_eq!(3, sm.pending_len());
+        assert_eq!([Events::Connected, Events::Retry, Events::TimedOut], sm.pending_events());
+
+        // Nothing has come due, but draining handles everything
+        let outcomes: Vec<_> = sm.drain_pending().into_iter().map(|outcome| outcome.expect("unexpected error")).collect();
+        assert_eq!(vec![States::Connected; 3], outcomes);
+        assert_eq!(0, sm.pending_len());
+    }
+
+    #[test]
+    fn test_global_inactivity_timeout() {
+        let clock = MockClock::new();
+        let expired = Mutex::new(Vec::new());
+        let mut sm = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            .with_global_inactivity_timeout(Duration::from_secs(60), States::Failed, |from, _| {
+                expired.lock().unwrap().push(*from);
+                Ok(())
+            })
+            .with_event_transition(&Events::Retry, States::Connecting, States::Connected)
+            .lock().build(States::Connecting, ());
+
+        clock.advance(Duration::from_secs(59));
+        sm.tick().expect("unexpected error");
+        assert_eq!(States::Connecting, sm.state);
+
+        // The machine expires before the Event is handled, so Retry finds no Transition
+        clock.advance(Duration::from_secs(2));
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        assert_eq!(States::Failed, sm.state);
+        assert!(sm.state_changed());
+        assert_eq!(vec![States::Connecting], *expired.lock().unwrap());
+
+        // Doesn't expire again from the target
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(&States::Failed, sm.tick().expect("unexpected error"));
+        assert_eq!(1, expired.lock().unwrap().len());
+        assert_eq!(None, sm.next_deadline());
+    }
+
+    #[test]
+    fn test_inactivity_deadline() {
+        let clock = MockClock::new();
+        let mut sm = StateMachineFactory::new()
+            .with_clock(clock.clone())
+            .with_global_inactivity_timeout(Duration::from_secs(60), States::Failed, |_, _| Err(Box::new(std::fmt::Error)))
+            .with_event_transition(&Events::Retry, States::Connecting, Same)
+            .lock().build(States::Connecting, ());
+
+        assert_eq!(Some(clock.now() + Duration::from_secs(60)), sm.next_deadline());
+        clock.advance(Duration::from_secs(30));
+        sm.handle_event(Events::Retry).expect("unexpected error");
+        assert_eq!(Some(clock.now() + Duration::from_secs(60)), sm.next_deadline());
+
+        // A failing effect starts the period over rather than leaving the deadline elapsed
+        clock.advance(Duration::from_secs(60));
+        assert!(sm.tick().is_err());
+        assert_eq!(Some(clock.now() + Duration::from_secs(60)), sm.next_deadline());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_spawn_timer_inactivity() {
+        use std::time::Instant;
+
+        let shared = StateMachineFactory::new()
+            .with_global_inactivity_timeout(Duration::from_millis(20), States::Failed, |_, _| Ok(()))
+            .with_event_transition(&Events::Retry, States::Connecting, Same)
+            .lock().build(States::Connecting, ())
+            .into_shared();
+
+        let timer = shared.spawn_timer(|e| panic!("unexpected error {e:?}"));
+        let started = Instant::now();
+        while shared.current_state() != States::Failed {
+            assert!(started.elapsed() < Duration::from_secs(5), "session never expired");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        drop(timer);
+    }
+}
@@ -0,0 +1,132 @@
+//! Logging the decisions taken while handling an Event, enabled by the `tracing` feature.
+//!
+//! Each Event dispatched opens a `dispatch` span at trace level, with target `statement`, recording
+//! the Event and the current State. Within it, a `candidate transition` event is emitted for every
+//! Transition considered, by its position in the definition, with an `outcome` of
+//! `"after exclusive"`, `"shadowed"`, `"event mismatch"`, `"state mismatch"`, `"guard failed"`,
+//! `"counted"` (with the `count` so far) or `"matched"`, along with the target State when matched; an `effect run` event for every effect,
+//! else effect and entry effect run, with whether it succeeded; and a final `event handled` event
+//! with the resulting State. Without the feature none of this is compiled in.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use crate::{Definition, StateMachineFactory};
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> Definition<'a, TEvent, TState, TData> {
+    /// Describes `event` for the decision log, if Events are traced
+    pub(crate) fn describe_event(&self, event: &TEvent) -> Option<String> {
+        self.event_format.as_ref().map(|format| format(event))
+    }
+}
+
+impl <'a, TEvent: Debug, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Records the Event in the `dispatch` span of the decision log, by its [Debug] representation.
+    /// Events needn't implement [Debug], so they are left out of the decision log unless this is
+    /// set.
+    pub fn with_traced_events(mut self) -> Self {
+        self.definition.event_format = Some(Arc::new(|event: &TEvent| format!("{event:?}")));
+        self
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use crate::StateMachineFactory;
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Running
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    enum Events {
+        Start,
+        Stop
+    }
+
+    /// Collects the fields of every span and event as a line of text
+    struct Collector {
+        lines: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Line(String);
+
+    impl Visit for Line {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            if field.name() == "message" {
+                self.0.insert_str(0, &format!("{value:?}:"));
+            } else {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push_str(&format!(" {}={}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut line = Line(format!("{}:", span.metadata().name()));
+            span.record(&mut line);
+            self.lines.lock().unwrap().push(line.0);
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut line = Line(String::new());
+            event.record(&mut line);
+            self.lines.lock().unwrap().push(line.0);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_decision_log() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition_effect(&Events::Stop, States::Running, States::Idle, |_| Ok(()))
+            .with_predicated_transition(Any, Same, |_| false)
+            .with_event_transition_effect(&Events::Start, States::Idle, States::Running, |_| Ok(()))
+            .with_traced_events()
+            .lock();
+        let mut sm = factory.build(States::Idle, ());
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        tracing::subscriber::with_default(Collector { lines: lines.clone() }, || {
+            sm.handle_event(Events::Start).expect("unexpected error");
+            sm.handle_event(Events::Start).expect("unexpected error");
+        });
+        assert_eq!(vec![
+            "dispatch: event=Start state=Idle",
+            "candidate transition: position=0 outcome=event mismatch",
+            "candidate transition: position=1 outcome=guard failed",
+            "candidate transition: position=2 outcome=matched to=Running",
+            "effect run: position=2 effect=effect ok=true",
+            "event handled: state=Running transitions_taken=1 ok=true",
+            "dispatch: event=Start state=Running",
+            "candidate transition: position=0 outcome=event mismatch",
+            "candidate transition: position=1 outcome=guard failed",
+            "candidate transition: position=2 outcome=state mismatch",
+            "event handled: state=Running transitions_taken=0 ok=true",
+        ], *lines.lock().unwrap());
+    }
+}
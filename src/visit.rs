@@ -0,0 +1,138 @@
+//! Visitor API over the Transitions registered with a [StateMachineFactory].
+
+use std::fmt::Debug;
+use crate::{FromState, StateMachineFactory, StateMachineTransition};
+use crate::Target::{Calc, Same, To};
+
+/// What causes a Transition to be considered.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Trigger<'t, TEvent> {
+    /// The Transition applies to a specific Event, as registered with
+    /// [StateMachineFactory::with_event_transition] and similar.
+    Event(&'t TEvent),
+    /// The Transition applies whenever its predicate returns true.
+    Guard,
+    /// The Transition applies to every Event.
+    Auto,
+}
+
+impl <TEvent> Clone for Trigger<'_, TEvent> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl <TEvent> Copy for Trigger<'_, TEvent> {}
+
+/// Receives the structure of a definition from [StateMachineFactory::visit]. Both methods do
+/// nothing by default, so visitors only need to implement the ones they are interested in.
+pub trait TransitionVisitor<TEvent, TState> {
+    /// Called once for every State the definition knows about: the registered States, followed by
+    /// any other State named by a Transition.
+    fn visit_state(&mut self, _state: &TState) {}
+
+    /// Called for every Transition, once for each State it can be taken from. `to` is `None` when
+    /// the target is calculated when the Transition is taken; `label` is the name of the
+    /// Transition's predicate, if it was given one with
+    /// [StateMachineFactory::with_named_predicated_transition_effect].
+    fn visit_transition(&mut self, _from: &TState, _trigger: Trigger<'_, TEvent>, _to: Option<&TState>, _label: Option<&str>) {}
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Clone + Send + 'a, TData> StateMachineTransition<'a, TEvent, TState, TData> {
+    /// What causes this Transition to be considered
+    pub(crate) fn trigger(&self) -> Trigger<'a, TEvent> {
+        match (self.event, &self.event_predicate) {
+            (Some(event), _) => Trigger::Event(event),
+            (None, Some(_)) => Trigger::Guard,
+            (None, None) => Trigger::Auto
+        }
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachineFactory<'a, TEvent, TState, TData> {
+    /// Walks the definition, calling `visitor` for each State and then for each Transition in
+    /// registration order. Transitions from [FromState::Any], [FromState::AnyOf] or
+    /// [FromState::Not] are visited once for each State they apply to, and [Same](crate::ToState::Same) Transitions lead back into their source State.
+    pub fn visit(&self, visitor: &mut impl TransitionVisitor<TEvent, TState>) {
+        let states = self.known_states();
+        for state in &states {
+            visitor.visit_state(state);
+        }
+        self.visit_edges(&states, |from, transition, to| visitor.visit_transition(from, transition.trigger(), to, transition.name.as_deref()));
+    }
+
+    /// Calls `edge` for every Transition, once for each of `states` it can be taken from, with
+    /// the State it leads to, as [StateMachineFactory::visit] visits them
+    pub(crate) fn visit_edges(&self, states: &[TState], mut edge: impl FnMut(&TState, &StateMachineTransition<'a, TEvent, TState, TData>, Option<&TState>)) {
+        for transition in &self.transitions {
+            let from_states = match &transition.from_state {
+                FromState::Any => states.iter().collect(),
+                FromState::AnyOf(from_states) => from_states.iter().collect(),
+                FromState::Not(_) => states.iter().filter(|state| transition.from_state.matches(state)).collect(),
+                FromState::From(from_state) => vec![from_state]
+            };
+            for from in from_states {
+                let to = match &transition.get_to_state {
+                    To(to_state) => Some(to_state),
+                    Same => Some(from),
+                    Calc(_) => None
+                };
+                edge(from, transition, to);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use crate::{StateMachineFactory, Trigger, TransitionVisitor};
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Idle,
+        Running
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    enum Events {
+        Start,
+        Log
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        states: Vec<States>,
+        transitions: Vec<String>,
+    }
+
+    impl TransitionVisitor<Events, States> for Recorder {
+        fn visit_state(&mut self, state: &States) {
+            self.states.push(*state);
+        }
+
+        fn visit_transition(&mut self, from: &States, trigger: Trigger<'_, Events>, to: Option<&States>, _label: Option<&str>) {
+            self.transitions.push(format!("{:?} -{:?}-> {:?}", from, trigger, to));
+        }
+    }
+
+    #[test]
+    fn test_visit() {
+        let factory = StateMachineFactory::<Events, States, ()>::new()
+            .with_event_transition(&Events::Start, States::Idle, States::Running)
+            .with_event_transition(&Events::Log, Any, Same)
+            .with_predicated_transition(States::Running, States::Idle, |_| true);
+
+        let mut recorder = Recorder::default();
+        factory.visit(&mut recorder);
+
+        assert_eq!(vec![States::Idle, States::Running], recorder.states);
+        assert_eq!(vec![
+            "Idle -Event(Start)-> Some(Running)",
+            "Idle -Event(Log)-> Some(Idle)",
+            "Running -Event(Log)-> Some(Running)",
+            "Running -Guard-> Some(Idle)",
+        ], recorder.transitions);
+    }
+}
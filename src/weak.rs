@@ -0,0 +1,105 @@
+//! Listeners that don't keep their observer alive, for observers that come and go.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::{StateMachine, StateTransitionEffectData};
+
+/// Boxed listener added with [StateMachine::add_weak_listener], returning whether its observer is
+/// still alive
+type WeakListener<'a, TEvent, TState, TData> = Arc<dyn Fn(&StateTransitionEffectData<TEvent, TState, TData>) -> bool + Send + Sync + 'a>;
+
+/// The weak listeners of one State Machine. Listeners are pruned while Transitions run them,
+/// which only have shared access to the State Machine, hence the lock. Cloning a State Machine
+/// gives the clone the same listeners.
+pub(crate) struct WeakListeners<'a, TEvent, TState, TData> {
+    listeners: Mutex<Vec<WeakListener<'a, TEvent, TState, TData>>>,
+}
+
+impl <TEvent, TState, TData> Default for WeakListeners<'_, TEvent, TState, TData> {
+    fn default() -> Self {
+        Self {
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl <'a, TEvent, TState, TData> Clone for WeakListeners<'a, TEvent, TState, TData> {
+    fn clone(&self) -> Self {
+        Self {
+            listeners: Mutex::new(self.lock().clone()),
+        }
+    }
+}
+
+impl <'a, TEvent, TState, TData> WeakListeners<'a, TEvent, TState, TData> {
+    fn lock(&self) -> MutexGuard<'_, Vec<WeakListener<'a, TEvent, TState, TData>>> {
+        self.listeners.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs every listener whose observer is alive, and removes the others
+    pub(crate) fn run(&self, transition_effect_data: &StateTransitionEffectData<TEvent, TState, TData>) {
+        self.lock().retain(|listener| listener(transition_effect_data));
+    }
+}
+
+impl <'a, TEvent, TState: PartialEq<TState> + Debug + Clone + Send + Eq + PartialEq + 'a, TData> StateMachine<'a, TEvent, TState, TData> {
+    /// Adds a listener to this State Machine that observes every Transition taken, like those
+    /// added with [crate::StateMachineFactory::with_listener], for as long as `observer` is alive.
+    /// Only a weak reference to the observer is kept, and `listener` is passed the observer along
+    /// with the effect data, so a short-lived observer such as a UI widget can watch a long-lived
+    /// State Machine without being kept alive by it. Once the observer has been dropped, the
+    /// listener is removed the next time a Transition is taken. Weak listeners run after the
+    /// listeners of the definition, in the order they were added.
+    pub fn add_weak_listener<TObserver: Send + Sync + 'a>(&mut self, observer: &Arc<TObserver>, listener: impl Fn(&TObserver, &StateTransitionEffectData<TEvent, TState, TData>) + Send + Sync + 'a) {
+        let observer = Arc::downgrade(observer);
+        self.weak_listeners.listeners.get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(Arc::new(move |d| match observer.upgrade() {
+                Some(observer) => {
+                    listener(&observer, d);
+                    true
+                }
+                None => false
+            }));
+    }
+
+    /// The number of listeners added with [StateMachine::add_weak_listener] that haven't been
+    /// removed yet, including any whose observer has been dropped since the last Transition.
+    pub fn weak_listener_count(&self) -> usize {
+        self.weak_listeners.lock().len()
+    }
+}
+
+#[cfg(test)]
+mod unit_tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::StateMachineFactory;
+    use crate::FromState::Any;
+    use crate::ToState::Same;
+
+    struct Widget {
+        transitions: AtomicUsize,
+    }
+
+    #[test]
+    fn test_weak_listener() {
+        let mut sm = StateMachineFactory::<(), u32, ()>::new()
+            .with_transition_effect(Any, Same, |_| Ok(()))
+            .lock()
+            .build(0, ());
+
+        let widget = Arc::new(Widget { transitions: AtomicUsize::new(0) });
+        sm.add_weak_listener(&widget, |widget, _| {
+            widget.transitions.fetch_add(1, Ordering::SeqCst);
+        });
+        sm.handle_event(()).expect("unexpected error");
+        assert_eq!(1, widget.transitions.load(Ordering::SeqCst));
+        assert_eq!(1, Arc::strong_count(&widget));
+
+        drop(widget);
+        assert_eq!(1, sm.weak_listener_count());
+        sm.handle_event(()).expect("unexpected error");
+        assert_eq!(0, sm.weak_listener_count());
+    }
+}
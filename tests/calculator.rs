@@ -1,11 +1,12 @@
 #[cfg(test)]
+#[allow(dead_code, clippy::match_like_matches_macro, clippy::unnecessary_mut_passed, clippy::clone_on_copy)]
 mod calculator_tests {
     use std::sync::atomic::Ordering::SeqCst;
-    use anyhow::anyhow;
     use atomic_float::AtomicF64;
     use statement::FromState::{Any, AnyOf};
     use statement::{StateMachineFactory, StateTransitionEffectData};
     use statement::ToState::Same;
+    use statement::testing::{assert_rejected, assert_transition};
 
     struct CalcData {
         pub input_value: AtomicF64,
@@ -106,22 +107,45 @@ mod calculator_tests {
                 })
             .lock().build(States::Idle, &mut init_data);
 
-        let error_mapper = |_| { anyhow!("error transitioning") };
-        sm.handle_event(Events::Digit {digit: 2}).map_err(error_mapper)?;
-        sm.handle_event(Events::Add).map_err(error_mapper)?;
-        sm.handle_event(Events::Digit {digit: 0}).map_err(error_mapper)?;
-        sm.handle_event(Events::Subtract).map_err(error_mapper)?;
-        sm.handle_event(Events::Digit {digit: 1}).map_err(error_mapper)?;
-        sm.handle_event(Events::Multiply).map_err(error_mapper)?;
-        sm.handle_event(Events::Digit {digit: 1}).map_err(error_mapper)?;
-        sm.handle_event(Events::Digit {digit: 2}).map_err(error_mapper)?;
-        sm.handle_event(Events::Digit {digit: 6}).map_err(error_mapper)?;
-        sm.handle_event(Events::Divide).map_err(error_mapper)?;
-        sm.handle_event(Events::Digit {digit: 3}).map_err(error_mapper)?;
-        sm.handle_event(Events::Equals).map_err(error_mapper)?;
+        assert_transition(&mut sm, Events::Digit {digit: 2}, States::Idle);
+        assert_transition(&mut sm, Events::Add, States::Adding);
+        assert_transition(&mut sm, Events::Digit {digit: 0}, States::Adding);
+        assert_transition(&mut sm, Events::Subtract, States::Subtracting);
+        assert_transition(&mut sm, Events::Digit {digit: 1}, States::Subtracting);
+        assert_transition(&mut sm, Events::Multiply, States::Multiplying);
+        assert_transition(&mut sm, Events::Digit {digit: 1}, States::Multiplying);
+        assert_transition(&mut sm, Events::Digit {digit: 2}, States::Multiplying);
+        assert_transition(&mut sm, Events::Digit {digit: 6}, States::Multiplying);
+        assert_transition(&mut sm, Events::Divide, States::Dividing);
+        assert_transition(&mut sm, Events::Digit {digit: 3}, States::Dividing);
+        assert_transition(&mut sm, Events::Equals, States::Idle);
 
         assert_eq!(42f64, sm.data.input_value.load(SeqCst));
 
+        #[cfg(feature = "metrics")]
+        {
+            use std::mem::discriminant;
+
+            let count = |state: States, event: Events| {
+                sm.metrics().events_in(&state)
+                    .and_then(|counts| counts.get(&discriminant(&event)))
+                    .copied()
+                    .unwrap_or(0)
+            };
+            let digit = Events::Digit { digit: 0 };
+            assert_eq!(5, sm.metrics().events_by_state().len());
+            assert_eq!((1, 1), (count(States::Idle, digit), count(States::Idle, Events::Add)));
+            assert_eq!((1, 1), (count(States::Adding, digit), count(States::Adding, Events::Subtract)));
+            assert_eq!((1, 1), (count(States::Subtracting, digit), count(States::Subtracting, Events::Multiply)));
+            assert_eq!((3, 1), (count(States::Multiplying, digit), count(States::Multiplying, Events::Divide)));
+            assert_eq!((1, 1), (count(States::Dividing, digit), count(States::Dividing, Events::Equals)));
+            assert_eq!(0, count(States::Idle, Events::Equals));
+        }
+
+        // There is nothing to calculate once the result is shown
+        assert!(assert_rejected(&mut sm, Events::Equals).is_none());
+        assert_eq!(42f64, sm.data.input_value.load(SeqCst));
+
         return Ok(());
 
         fn append_digit(d: &CalcData, b: u8) {
@@ -0,0 +1,84 @@
+#[cfg(test)]
+mod services_tests {
+    use std::sync::{Arc, Mutex};
+    use statement::StateMachineFactory;
+
+    /// A service that effects call out to, such as a database
+    trait OrderStore: Send + Sync {
+        fn save_status(&self, order_id: u32, status: &str);
+    }
+
+    #[derive(Default)]
+    struct MockOrderStore {
+        saved: Mutex<Vec<(u32, String)>>,
+    }
+
+    impl OrderStore for MockOrderStore {
+        fn save_status(&self, order_id: u32, status: &str) {
+            self.saved.lock().unwrap().push((order_id, status.to_string()));
+        }
+    }
+
+    struct AppContext {
+        order_id: u32,
+        store: Arc<dyn OrderStore>,
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    enum States {
+        Placed,
+        Paid,
+        Shipped
+    }
+
+    #[derive(Eq, PartialEq)]
+    enum Events {
+        Pay,
+        Ship
+    }
+
+    fn order_factory<TContext: std::ops::Deref<Target = AppContext>>() -> StateMachineFactory<'static, Events, States, TContext> {
+        StateMachineFactory::<Events, States, TContext>::new()
+            .with_event_transition_effect(&Events::Pay, States::Placed, States::Paid, |d| {
+                d.data.store.save_status(d.data.order_id, "paid");
+                Ok(())
+            })
+            .with_event_transition_effect(&Events::Ship, States::Paid, States::Shipped, |d| {
+                d.data.store.save_status(d.data.order_id, "shipped");
+                Ok(())
+            })
+    }
+
+    #[test]
+    fn borrowed_context_test() {
+        let store = Arc::new(MockOrderStore::default());
+        let context = AppContext { order_id: 7, store: store.clone() };
+
+        // The machine borrows the context, so it can't outlive this function
+        let mut sm = order_factory::<&AppContext>().lock().build(States::Placed, &context);
+        sm.handle_event(Events::Pay).expect("unexpected error");
+        sm.handle_event(Events::Ship).expect("unexpected error");
+
+        assert_eq!(States::Shipped, sm.state);
+        assert_eq!(vec![(7, "paid".to_string()), (7, "shipped".to_string())], *store.saved.lock().unwrap());
+    }
+
+    #[test]
+    fn shared_context_test() {
+        let store = Arc::new(MockOrderStore::default());
+
+        // The machine shares ownership of the context, so it can be built in one place and
+        // driven from another, such as a different thread
+        let build_machine = || {
+            let context = Arc::new(AppContext { order_id: 8, store: store.clone() });
+            order_factory::<Arc<AppContext>>().lock().build(States::Placed, context)
+        };
+        let mut sm = build_machine();
+        std::thread::spawn(move || {
+            sm.handle_event(Events::Pay).expect("unexpected error");
+            assert_eq!(States::Paid, sm.state);
+        }).join().expect("thread panicked");
+
+        assert_eq!(vec![(8, "paid".to_string())], *store.saved.lock().unwrap());
+    }
+}